@@ -0,0 +1,413 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Auth
+//!
+//! Who is allowed to do what. Everyone has a role, from `Everyone` up to
+//! `Owner`, and every command can require a role with `Plugin::required_role`.
+//! The dispatcher checks it before the plugin sees the command.
+//!
+//! Roles are granted to an identity, which is either a NickServ account name
+//! or a hostmask pattern like `*!*@club.example.org` (`*` and `?` work as
+//! usual). Nicks alone are never trusted, as anyone can take one. A grant can
+//! apply everywhere or only in one channel, and someone's role is the highest
+//! one granted to them.
+//!
+//! Accounts and hostmasks belong to one network, so every grant does too. The
+//! same account name on another network can be someone else entirely.
+//!
+//! Owners and admins from the config are granted on the network they're
+//! listed under, and can't be changed at runtime. Everything else is granted
+//! with the `grant` and `revoke` commands, and kept in storage.
+//!
+//! Accounts come from IRCv3 account tags when the server supports them, in
+//! which case a message without one is from someone who isn't logged in.
+//! Otherwise they come from WHOIS. The first command from someone the bot
+//! hasn't looked up yet only gets the roles their hostmask earns them.
+//!
+//! A nick can change hands without the bot seeing it, unless they share a
+//! channel, so what WHOIS said about someone who doesn't is only trusted for a
+//! minute before they're looked up again.
+
+use std::collections::{HashMap, HashSet};
+
+use irc::{IrcMsg, IrcPrivMsg, IrcWriter, BotInfo, Storage};
+use irc::middleware::{Middleware, Flow};
+use irc::scheduler::now;
+
+
+// How long, in seconds, an account from WHOIS is trusted for someone who
+// isn't in a channel with the bot.
+static ACCOUNT_TTL: i64 = 60;
+
+
+/// How much someone is trusted, lowest first.
+#[deriving(Clone, PartialEq, Eq, PartialOrd, Ord, Show, Encodable, Decodable)]
+pub enum Role {
+    Everyone,
+    Trusted,
+    Officer,
+    Admin,
+    Owner,
+}
+
+impl Role {
+    /// Read a role from its name.
+    pub fn parse(s: &str) -> Option<Role> {
+        match s.to_lowercase().as_slice() {
+            "everyone" => Some(Role::Everyone),
+            "trusted" => Some(Role::Trusted),
+            "officer" => Some(Role::Officer),
+            "admin" => Some(Role::Admin),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+
+    /// The role's name, as `parse` reads it.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Role::Everyone => "everyone",
+            Role::Trusted => "trusted",
+            Role::Officer => "officer",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+
+/// A role given to an identity on a network, everywhere or in one channel.
+#[deriving(Clone, PartialEq, Show, Encodable, Decodable)]
+pub struct Grant {
+    pub who: String,
+    pub role: Role,
+    pub network: String,
+    pub channel: Option<String>,
+}
+
+impl Grant {
+    /// Construct a grant that applies in every channel on a network.
+    pub fn everywhere(who: &str, role: Role, network: &str) -> Grant {
+        Grant {
+            who: who.to_string(),
+            role: role,
+            network: network.to_string(),
+            channel: None,
+        }
+    }
+
+    /// Does this grant apply to someone, on the given network and channel?
+    pub fn applies(&self, network: &str, account: Option<&str>,
+                   hostmask: &str, channel: &str) -> bool {
+        let here = match self.channel {
+            Some(ref c) => {
+                c.as_slice().to_lowercase() == channel.to_lowercase()
+            },
+            None => true,
+        };
+        self.network.as_slice() == network && here
+            && identifies(self.who.as_slice(), account, hostmask)
+    }
+
+    /// The grants made at runtime on a network.
+    pub fn all(storage: &Storage, network: &str) -> Vec<Grant> {
+        let grants: Vec<Grant> = storage.namespace("roles")
+                                        .get("grants")
+                                        .unwrap_or(Vec::new());
+        grants.into_iter().filter(|g| g.network.as_slice() == network).collect()
+    }
+
+    /// Keep this grant, replacing any for the same identity, network and
+    /// channel.
+    pub fn save(&self, storage: &Storage) {
+        storage.namespace("roles").transaction(|tx| {
+            let mut grants: Vec<Grant> = tx.get("grants")
+                                           .unwrap_or(Vec::new());
+            grants.retain(|g| {
+                g.who != self.who || g.network != self.network
+                    || g.channel != self.channel
+            });
+            grants.push(self.clone());
+            tx.set("grants", &grants);
+            true
+        });
+    }
+
+    /// Take away what an identity was granted on a network in a channel, or
+    /// everywhere if `channel` is None. Returns whether there was anything to
+    /// take away.
+    pub fn revoke(storage: &Storage, network: &str, who: &str,
+                  channel: Option<&str>) -> bool {
+        storage.namespace("roles").transaction(|tx| {
+            let mut grants: Vec<Grant> = tx.get("grants")
+                                           .unwrap_or(Vec::new());
+            let before = grants.len();
+            grants.retain(|g| {
+                g.who.as_slice() != who || g.network.as_slice() != network
+                    || g.channel.as_ref().map(|c| c.as_slice()) != channel
+            });
+            if grants.len() == before {
+                return false;
+            }
+            tx.set("grants", &grants);
+            true
+        })
+    }
+}
+
+
+/// Does an identity describe someone? Identities with a `!` or `@` are
+/// hostmask patterns, and anything else is an account name.
+pub fn identifies(who: &str, account: Option<&str>, hostmask: &str) -> bool {
+    if who.contains_char('!') || who.contains_char('@') {
+        let who = who.to_lowercase();
+        glob(who.as_slice(), hostmask.to_lowercase().as_slice())
+    } else {
+        match account {
+            Some(a) => a.to_lowercase() == who.to_lowercase(),
+            None => false,
+        }
+    }
+}
+
+/// Match a pattern where `*` is any run of characters and `?` any one.
+pub fn glob(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0u, 0u);
+    let mut star: Option<(uint, uint)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else {
+            match star {
+                Some((sp, st)) => {
+                    pi = sp + 1;
+                    ti = st + 1;
+                    star = Some((sp, st + 1));
+                },
+                None => return false,
+            }
+        }
+    }
+    while pi < p.len() && p[pi] == '*' {
+        pi += 1;
+    }
+    pi == p.len()
+}
+
+
+/// Works out who people are, and notes it on their messages as `account`
+/// (when known) and `hostmask`.
+pub struct Identify {
+    // Accounts by lowercased nick, and when they were looked up. None means
+    // they aren't logged in.
+    accounts: HashMap<String, (Option<String>, i64)>,
+    // Nicks we've sent a WHOIS for and not heard back about.
+    pending: HashSet<String>,
+    // Whether we've finished asking for capabilities.
+    cap_done: bool,
+    // Whether the server agreed to tag messages with accounts.
+    tagged: bool,
+}
+
+impl Identify {
+    pub fn new() -> Identify {
+        Identify {
+            accounts: HashMap::new(),
+            pending: HashSet::new(),
+            cap_done: false,
+            tagged: false,
+        }
+    }
+
+    fn logged_in(&mut self, msg: &IrcMsg) {
+        // :server 330 me them account :is logged in as
+        let words: Vec<&str> = msg.param.as_slice().words().collect();
+        if words.len() >= 3 {
+            let nick = words[1].to_lowercase();
+            self.pending.remove(&nick);
+            self.accounts.insert(nick, (Some(words[2].to_string()), now()));
+        }
+    }
+
+    fn end_of_whois(&mut self, msg: &IrcMsg) {
+        let words: Vec<&str> = msg.param.as_slice().words().collect();
+        if words.len() >= 2 {
+            let nick = words[1].to_lowercase();
+            if self.pending.remove(&nick) {
+                self.accounts.insert(nick, (None, now()));
+            }
+        }
+    }
+
+    // :server CAP me ACK :account-tag
+    fn acknowledged(&mut self, msg: &IrcMsg) {
+        let words: Vec<&str> = msg.param.as_slice().words().collect();
+        if words.get(1) != Some(&"ACK") {
+            return;
+        }
+        for cap in words.iter().skip(2) {
+            match cap.trim_left_chars(':') {
+                "account-tag" => self.tagged = true,
+                "-account-tag" => self.tagged = false,
+                _ => (),
+            }
+        }
+    }
+
+    // What WHOIS said about someone, as long as it can still be trusted:
+    // either it's recent, or they share a channel with us, so we'd have seen
+    // them quit or change nick.
+    fn cached(&mut self, key: &String, nick: &str,
+              info: &BotInfo) -> Option<Option<String>> {
+        let stale = match self.accounts.get(key) {
+            Some(&(ref account, at)) => {
+                if now() - at < ACCOUNT_TTL || info.state.is_around(nick) {
+                    return Some(account.clone());
+                }
+                true
+            },
+            None => false,
+        };
+        if stale {
+            self.accounts.remove(key);
+        }
+        None
+    }
+}
+
+impl Middleware for Identify {
+    fn connected(&mut self) {
+        self.accounts.clear();
+        self.pending.clear();
+        self.cap_done = false;
+        self.tagged = false;
+    }
+
+    fn incoming(&mut self, msg: &mut IrcMsg,
+                writer: &IrcWriter, info: &BotInfo) -> Flow {
+        match msg.code.as_slice() {
+            "CAP" => {
+                // Whether or not we got what we asked for, we're done asking
+                // once the first answer comes back. Later answers arrive
+                // after registration, when it's fine to get them.
+                let answered = msg.param.as_slice().contains(" ACK ")
+                    || msg.param.as_slice().contains(" NAK ");
+                self.acknowledged(msg);
+                if answered && !self.cap_done {
+                    writer.output("CAP END".to_string());
+                    self.cap_done = true;
+                }
+                return Flow::Continue;
+            },
+            "330" => self.logged_in(msg),
+            "318" => self.end_of_whois(msg),
+            _ => (),
+        }
+
+        let (nick, user) = match msg.match_sender() {
+            Some(sender) => sender,
+            None => return Flow::Continue,
+        };
+        let key = nick.as_slice().to_lowercase();
+        match msg.code.as_slice() {
+            "NICK" => {
+                let new = msg.param.as_slice().trim_left_chars(':')
+                                              .to_lowercase();
+                match self.accounts.remove(&key) {
+                    Some(account) => { self.accounts.insert(new, account); },
+                    None => (),
+                }
+            },
+            "QUIT" => { self.accounts.remove(&key); },
+            // With account-notify, `*` means logged out.
+            "ACCOUNT" => {
+                let account = msg.param.as_slice().trim_left_chars(':');
+                let account = if account == "*" {
+                    None
+                } else {
+                    Some(account.to_string())
+                };
+                self.accounts.insert(key.clone(), (account, now()));
+            },
+            _ => (),
+        }
+
+        msg.notes.insert("hostmask".to_string(),
+                         format!("{}!{}", nick, user));
+        let tagged = msg.tags.get(&"account".to_string()).map(|a| a.clone());
+        let account = if self.tagged {
+            tagged
+        } else {
+            match self.cached(&key, nick.as_slice(), info) {
+                Some(account) => account,
+                None => {
+                    // Look up anyone trying a command, for next time.
+                    let is_cmd = match IrcPrivMsg::new(&*msg) {
+                        Some(pm) => pm.txt.as_slice()
+                                          .starts_with(info.cmd_prefix),
+                        None => false,
+                    };
+                    if is_cmd && !self.pending.contains(&key) {
+                        writer.output(format!("WHOIS {}", nick));
+                        self.pending.insert(key.clone());
+                    }
+                    None
+                },
+            }
+        };
+        match account {
+            Some(account) => {
+                msg.notes.insert("account".to_string(), account);
+            },
+            None => (),
+        }
+        Flow::Continue
+    }
+
+    /// Roles are worked out from what this finds, so don't let anything
+    /// through that it couldn't look at.
+    fn guards(&self) -> bool { true }
+
+    fn name(&self) -> &'static str { "identify" }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{glob, identifies};
+
+    #[test]
+    fn globs() {
+        assert!(glob("*", ""));
+        assert!(glob("*!*@example.com", "nick!user@example.com"));
+        assert!(glob("nick!?ser@*", "nick!user@host"));
+        assert!(glob("*a*b", "xxaxxab"));
+        assert!(!glob("*!*@example.com", "nick!user@example.org"));
+        assert!(!glob("nick", "nickname"));
+        assert!(!glob("?", ""));
+    }
+
+    #[test]
+    fn identifies_accounts() {
+        let mask = "Nick!user@host";
+        assert!(identifies("alice", Some("Alice"), mask));
+        assert!(!identifies("alice", Some("bob"), mask));
+        // Without an account, a bare name identifies no one, even someone
+        // using it as their nick.
+        assert!(!identifies("nick", None, mask));
+    }
+
+    #[test]
+    fn identifies_hostmasks() {
+        assert!(identifies("*!*@HOST", None, "Nick!user@host"));
+        assert!(identifies("nick!*@*", Some("alice"), "Nick!user@host"));
+        assert!(!identifies("*!*@other", Some("alice"), "Nick!user@host"));
+    }
+}
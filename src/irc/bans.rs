@@ -0,0 +1,291 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Bans
+//!
+//! The bans and quiets the bot has set, kept in storage so they can be lifted
+//! when they run out even if the bot restarted in the meantime. Each network
+//! has bans of its own. Anything that bans someone, like the bans and
+//! moderation plugins, records it here:
+//!
+//! ```
+//! let ban = Ban::new("#cse-club", mask, BAN, nick, "flooding",
+//!                    Some(2 * 60 * 60));
+//! ban.apply(writer, &info.state);
+//! ban.save(&info.storage, info.network);
+//! ```
+//!
+//! Quiets are set however the network allows, which is `+q` on some and an
+//! extban like `+b ~q:mask` on others. Some have no way at all, and then
+//! `ChannelState::quiet` says so and `apply` does nothing.
+//!
+//! When the bot isn't opped, it asks ChanServ for ops and saves the ban as
+//! pending instead:
+//!
+//! ```
+//! ban.pending = true;
+//! ban.kick = Some(nick.to_string());
+//! ban.save(&info.storage, info.network);
+//! writer.chanserv(format!("OP {}", chan).as_slice());
+//! ```
+//!
+//! The bans plugin sets pending bans once the bot is opped, kicking anyone
+//! they were meant to kick who's still there. It lifts bans once they expire,
+//! and checks them against the channel's ban list whenever the bot joins.
+
+use std::fmt;
+
+use irc::{IrcWriter, Storage, ChannelState};
+use irc::scheduler::now;
+
+
+/// The channel mode for a ban.
+pub static BAN: &'static str = "b";
+
+/// Marks a quiet. The mode it's set with depends on the network.
+pub static QUIET: &'static str = "q";
+
+
+/// A ban or quiet set by the bot.
+#[deriving(Clone, PartialEq, Encodable, Decodable)]
+pub struct Ban {
+    pub channel: String,
+    pub mask: String,
+    // `BAN` or `QUIET`.
+    pub mode: String,
+    pub by: String,
+    pub reason: String,
+    // When it runs out, or None if it doesn't.
+    pub expires: Option<i64>,
+    // Waiting for the bot to be opped before it's set.
+    pub pending: bool,
+    // Who to kick once a pending ban is set.
+    pub kick: Option<String>,
+}
+
+impl Ban {
+    /// Construct a ban lasting `seconds` from now, or forever.
+    pub fn new(channel: &str, mask: &str, mode: &str, by: &str, reason: &str,
+               seconds: Option<i64>) -> Ban {
+        Ban {
+            channel: channel.to_string(),
+            mask: mask.to_string(),
+            mode: mode.to_string(),
+            by: by.to_string(),
+            reason: reason.to_string(),
+            expires: seconds.map(|s| now() + s),
+            pending: false,
+            kick: None,
+        }
+    }
+
+    /// Is this the same ban as another, whatever its details?
+    pub fn same(&self, channel: &str, mask: &str, mode: &str) -> bool {
+        self.channel.as_slice().to_lowercase() == channel.to_lowercase()
+            && self.mask.as_slice().to_lowercase() == mask.to_lowercase()
+            && self.mode.as_slice() == mode
+    }
+
+    /// Has it run out?
+    pub fn expired(&self) -> bool {
+        match self.expires {
+            Some(t) => t <= now(),
+            None => false,
+        }
+    }
+
+    /// The mode and argument to set or unset on this network, like
+    /// `b *!*@host`, or None for a quiet where there's no way to quiet.
+    fn modes(&self, state: &ChannelState) -> Option<String> {
+        if self.mode.as_slice() == QUIET {
+            state.quiet(self.mask.as_slice()).map(|(mode, mask)| {
+                format!("{} {}", mode, mask)
+            })
+        } else {
+            Some(format!("{} {}", self.mode, self.mask))
+        }
+    }
+
+    /// Set the mode on the channel. Returns whether it could be.
+    pub fn apply(&self, writer: &IrcWriter, state: &ChannelState) -> bool {
+        match self.modes(state) {
+            Some(modes) => {
+                let modes = format!("+{}", modes);
+                writer.mode(self.channel.as_slice(), modes.as_slice());
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Take the mode off the channel.
+    pub fn lift(&self, writer: &IrcWriter, state: &ChannelState) {
+        match self.modes(state) {
+            Some(modes) => {
+                let modes = format!("-{}", modes);
+                writer.mode(self.channel.as_slice(), modes.as_slice());
+            },
+            None => (),
+        }
+    }
+
+    /// Every ban recorded on a network.
+    pub fn all(storage: &Storage, network: &str) -> Vec<Ban> {
+        let key = format!("bans:{}", network);
+        storage.namespace("bans").get(key.as_slice()).unwrap_or(Vec::new())
+    }
+
+    /// Keep this ban, replacing any record of the same one on the network.
+    pub fn save(&self, storage: &Storage, network: &str) {
+        let key = format!("bans:{}", network);
+        let key = key.as_slice();
+        storage.namespace("bans").transaction(|tx| {
+            let mut bans: Vec<Ban> = tx.get(key).unwrap_or(Vec::new());
+            bans.retain(|b| {
+                !b.same(self.channel.as_slice(), self.mask.as_slice(),
+                        self.mode.as_slice())
+            });
+            bans.push(self.clone());
+            tx.set(key, &bans);
+            true
+        });
+    }
+
+    /// Forget a ban on a network. Returns whether it was recorded.
+    pub fn remove(storage: &Storage, network: &str, channel: &str, mask: &str,
+                  mode: &str) -> bool {
+        let key = format!("bans:{}", network);
+        let key = key.as_slice();
+        storage.namespace("bans").transaction(|tx| {
+            let mut bans: Vec<Ban> = tx.get(key).unwrap_or(Vec::new());
+            let before = bans.len();
+            bans.retain(|b| !b.same(channel, mask, mode));
+            if bans.len() == before {
+                return false;
+            }
+            tx.set(key, &bans);
+            true
+        })
+    }
+}
+
+impl fmt::Show for Ban {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.mode.as_slice() == QUIET { "quiet" } else { "ban" };
+        try!(write!(f, "{} {} by {}", kind, self.mask, self.by));
+        if self.pending {
+            try!(write!(f, " (not set yet)"));
+        }
+        if !self.reason.is_empty() {
+            try!(write!(f, " ({})", self.reason));
+        }
+        match self.expires {
+            Some(t) => {
+                let left = t - now();
+                write!(f, ", {} left", format_seconds(if left > 0 {
+                    left
+                } else {
+                    0
+                }))
+            },
+            None => write!(f, ", permanent"),
+        }
+    }
+}
+
+
+/// A mask covering everyone from the same host as a hostmask, like
+/// `*!*@club.example.org`. Takes `nick!user@host` or `user@host`.
+pub fn ban_mask(hostmask: &str) -> String {
+    match hostmask.rfind('@') {
+        Some(i) => format!("*!*{}", hostmask.slice_from(i)),
+        None => format!("{}!*@*", hostmask),
+    }
+}
+
+
+/// Read a duration like `30m`, `2h`, `1d12h` or `1w`, in seconds.
+pub fn parse_duration(s: &str) -> Option<i64> {
+    if s.is_empty() {
+        return None;
+    }
+    let mut total = 0i64;
+    let mut n = String::new();
+    for c in s.chars() {
+        if c.is_digit() {
+            n.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        let count: i64 = match from_str(n.as_slice()) {
+            Some(count) => count,
+            None => return None,
+        };
+        total += count * unit;
+        n.clear();
+    }
+    if !n.is_empty() {
+        return None;
+    }
+    Some(total)
+}
+
+/// Write a number of seconds like `parse_duration` reads them.
+pub fn format_seconds(secs: i64) -> String {
+    let units = [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60), ("s", 1)];
+    let mut left = secs;
+    let mut out = String::new();
+    for &(name, size) in units.iter() {
+        if left >= size {
+            out.push_str(format!("{}{}", left / size, name).as_slice());
+            left %= size;
+        }
+    }
+    if out.is_empty() { "0s".to_string() } else { out }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{ban_mask, parse_duration, format_seconds};
+
+    #[test]
+    fn masks_hosts() {
+        assert_eq!(ban_mask("bob!~bob@club.example.org"),
+                   "*!*@club.example.org".to_string());
+        assert_eq!(ban_mask("bob@club.example.org"),
+                   "*!*@club.example.org".to_string());
+        assert_eq!(ban_mask("bob"), "bob!*@*".to_string());
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30m"), Some(30 * 60));
+        assert_eq!(parse_duration("1d12h"), Some(36 * 60 * 60));
+        assert_eq!(parse_duration("1w"), Some(7 * 24 * 60 * 60));
+        assert_eq!(parse_duration("90s"), Some(90));
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("30"), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("2y"), None);
+        assert_eq!(parse_duration("1h 30m"), None);
+    }
+
+    #[test]
+    fn formats_what_it_parses() {
+        assert_eq!(format_seconds(0), "0s".to_string());
+        assert_eq!(format_seconds(parse_duration("1d12h30s").unwrap()),
+                   "1d12h30s".to_string());
+    }
+}
@@ -0,0 +1,202 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Bus
+//!
+//! Lets plugins talk to each other without knowing about each other. A plugin
+//! publishes an event on a topic, like `calendar.meeting`, and every plugin
+//! subscribed to that topic gets it.
+//!
+//! Events are published through `IrcWriter::publish` and delivered on the event
+//! loop, one at a time in the order they were published. Each event goes to
+//! its subscribers in the order the plugins were registered, which a reloaded
+//! plugin keeps. A subscription ending in `*` matches every topic starting
+//! with what comes before it.
+//!
+//! The payload is any json-encodable value, and subscribers decode it into
+//! whatever type they expect:
+//!
+//! ```
+//! writer.publish(Event::new(self.name, "calendar.meeting", &meeting));
+//!
+//! // In the subscriber's `event` method:
+//! let meeting: Option<Meeting> = event.payload();
+//! ```
+
+use std::collections::RingBuf;
+use std::io::IoError;
+use serialize::{json, Encodable, Decodable};
+
+
+/// How many deliveries the bus remembers.
+static HISTORY: uint = 50;
+
+
+/// Something that happened, published by a plugin.
+#[deriving(Clone, Show)]
+pub struct Event {
+    pub source: String,
+    pub topic: String,
+    payload: String,
+}
+
+impl Event {
+    /// Construct an event. `source` is the publishing plugin's name.
+    pub fn new<'b, T>(source: &str, topic: &str, payload: &T) -> Event
+        where T: Encodable<json::Encoder<'b>, IoError> {
+        Event {
+            source: source.to_string(),
+            topic: topic.to_string(),
+            payload: json::encode(payload),
+        }
+    }
+
+    /// Decode the payload, or None if it isn't the expected type.
+    pub fn payload<T>(&self) -> Option<T>
+        where T: Decodable<json::Decoder, json::DecoderError> {
+        json::decode(self.payload.as_slice()).ok()
+    }
+}
+
+
+/// A record of one event reaching one plugin.
+#[deriving(Clone, Show)]
+pub struct Delivery {
+    pub topic: String,
+    pub plugin: String,
+}
+
+
+/// Keeps track of who is subscribed to what.
+pub struct EventBus {
+    subscriptions: Vec<(String, Vec<String>)>,
+    history: RingBuf<Delivery>,
+}
+
+impl EventBus {
+    /// Construct a bus with no subscribers.
+    pub fn new() -> EventBus {
+        EventBus {
+            subscriptions: Vec::new(),
+            history: RingBuf::new(),
+        }
+    }
+
+    /// Subscribe a plugin to the given topics, in place of any it had
+    /// before. A plugin that was already subscribed keeps its place in the
+    /// delivery order, and one subscribed to nothing is dropped.
+    pub fn subscribe(&mut self, plugin: &str, topics: Vec<&str>) {
+        let topics: Vec<String> = topics.iter().map(|t| t.to_string())
+                                        .collect();
+        let at = self.subscriptions.iter().position(|&(ref p, _)| {
+            p.as_slice() == plugin
+        });
+        let at = match at {
+            Some(i) => {
+                self.subscriptions.remove(i);
+                i
+            },
+            None => self.subscriptions.len(),
+        };
+        if !topics.is_empty() {
+            self.subscriptions.insert(at, (plugin.to_string(), topics));
+        }
+    }
+
+    /// The plugins that should get an event, in delivery order.
+    pub fn subscribers(&self, event: &Event) -> Vec<String> {
+        let topic = event.topic.as_slice();
+        self.subscriptions.iter().filter(|&&(_, ref topics)| {
+            topics.iter().any(|t| matches(t.as_slice(), topic))
+        }).map(|&(ref plugin, _)| plugin.clone()).collect()
+    }
+
+    /// Note that an event reached a plugin.
+    pub fn delivered(&mut self, event: &Event, plugin: &str) {
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(Delivery {
+            topic: event.topic.clone(),
+            plugin: plugin.to_string(),
+        });
+    }
+
+    /// The most recent deliveries, oldest first.
+    #[cfg(test)]
+    pub fn history(&self) -> Vec<Delivery> {
+        self.history.iter().map(|d| d.clone()).collect()
+    }
+}
+
+
+/// Does a subscription match a topic?
+fn matches(subscription: &str, topic: &str) -> bool {
+    if subscription.ends_with("*") {
+        topic.starts_with(subscription.slice_to(subscription.len() - 1))
+    } else {
+        subscription == topic
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Event, EventBus, HISTORY};
+
+    fn event(topic: &str) -> Event {
+        Event::new("calendar", topic, &"standup".to_string())
+    }
+
+    #[test]
+    fn delivers_in_registration_order() {
+        let mut bus = EventBus::new();
+        bus.subscribe("log", vec!["calendar.*"]);
+        bus.subscribe("announce", vec!["calendar.meeting"]);
+        bus.subscribe("other", vec!["weather.rain"]);
+        let to = bus.subscribers(&event("calendar.meeting"));
+        assert_eq!(to, vec!["log".to_string(), "announce".to_string()]);
+        let to = bus.subscribers(&event("calendar.holiday"));
+        assert_eq!(to, vec!["log".to_string()]);
+    }
+
+    #[test]
+    fn resubscribing_keeps_the_order() {
+        let mut bus = EventBus::new();
+        bus.subscribe("log", vec!["calendar.meeting"]);
+        bus.subscribe("announce", vec!["calendar.meeting"]);
+        bus.subscribe("log", vec!["calendar.*"]);
+        let to = bus.subscribers(&event("calendar.meeting"));
+        assert_eq!(to, vec!["log".to_string(), "announce".to_string()]);
+        assert_eq!(bus.subscribers(&event("calendar.holiday")),
+                   vec!["log".to_string()]);
+    }
+
+    #[test]
+    fn unsubscribed_plugins_get_nothing() {
+        let mut bus = EventBus::new();
+        bus.subscribe("log", vec!["calendar.meeting"]);
+        bus.subscribe("log", vec![]);
+        assert!(bus.subscribers(&event("calendar.meeting")).is_empty());
+    }
+
+    #[test]
+    fn payload_round_trips() {
+        let e = event("calendar.meeting");
+        assert_eq!(e.payload::<String>(), Some("standup".to_string()));
+        assert_eq!(e.payload::<uint>(), None);
+    }
+
+    #[test]
+    fn history_is_bounded_and_ordered() {
+        let mut bus = EventBus::new();
+        for i in range(0, HISTORY + 5) {
+            bus.delivered(&event(format!("t{}", i).as_slice()), "log");
+        }
+        let history = bus.history();
+        assert_eq!(history.len(), HISTORY);
+        assert_eq!(history[0].topic, "t5".to_string());
+        assert_eq!(history[HISTORY - 1].topic,
+                   format!("t{}", HISTORY + 4));
+    }
+}
@@ -0,0 +1,546 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Channels
+//!
+//! Keeps track of the channels the bot is in: who's in each one, what modes
+//! they have there (op, voice and so on), the ban list once it's been asked
+//! for, and everyone's hostmask and account as far as they've been seen. The
+//! core updates it from every message before the middleware runs, and plugins
+//! read it through `BotInfo::state`:
+//!
+//! ```
+//! if info.state.am_op("#cse-club") {
+//!     writer.output(format!("KICK #cse-club {} :Bye", nick));
+//! }
+//! ```
+//!
+//! Once a ban list has been read, the core publishes a `channel.bans` event
+//! with the channel's name, for plugins that want to check it. When the bot is
+//! opped or deopped it publishes `channel.opped` or `channel.deopped`, so
+//! plugins can catch up on anything they were waiting to do. Whenever someone
+//! parts, is kicked or quits, it publishes a `channel.left` event with a
+//! `Departure` for each channel they were in.
+//!
+//! The server's `PREFIX` and `CHANMODES` from the 005 reply are used to read
+//! mode changes, with the usual defaults until they arrive. Along with
+//! `EXTBAN`, they also say how to quiet someone on this network, as `+q` is a
+//! list of quiets on some servers and the channel owners on others. Nicks and
+//! channel names are compared without case.
+
+use std::collections::HashMap;
+use std::collections::hash_map::{Occupied, Vacant};
+
+use irc::msg::IrcMsg;
+
+
+/// Someone leaving a channel, the payload of a `channel.left` event.
+#[deriving(Clone, Show, Encodable, Decodable)]
+pub struct Departure {
+    pub channel: String,
+    pub nick: String,
+}
+
+impl Departure {
+    /// Construct a departure of someone from a channel.
+    pub fn new(channel: &str, nick: &str) -> Departure {
+        Departure {
+            channel: channel.to_string(),
+            nick: nick.to_string(),
+        }
+    }
+}
+
+
+/// Someone in a channel.
+#[deriving(Clone, Show)]
+pub struct Member {
+    pub nick: String,
+    // Prefix modes, like `o` and `v`.
+    pub modes: String,
+}
+
+
+/// A channel the bot is in.
+#[deriving(Clone, Show)]
+pub struct Channel {
+    pub name: String,
+    // Keyed by lowercased nick.
+    pub members: HashMap<String, Member>,
+    // Ban masks, once the list has been asked for with `MODE #chan +b`.
+    pub bans: Vec<String>,
+}
+
+
+/// What the bot knows about the channels it's in.
+pub struct ChannelState {
+    nick: String,
+    // Prefix modes and their symbols, highest first, like `(o, @)`.
+    prefixes: Vec<(char, char)>,
+    // Modes that always take an argument, and ones that do only when set.
+    always_arg: String,
+    set_arg: String,
+    // List modes, like `b` for bans.
+    list_modes: String,
+    // The extban prefix and the kinds of extban, from `EXTBAN=~,qjncr`.
+    extbans: Option<(String, String)>,
+    // Keyed by lowercased name.
+    channels: HashMap<String, Channel>,
+    // `user@host` by lowercased nick.
+    hosts: HashMap<String, String>,
+    // Accounts by lowercased nick, from account tags, extended joins and
+    // account notifications.
+    accounts: HashMap<String, String>,
+    // Ban lists still coming in, by lowercased channel.
+    listing: HashMap<String, Vec<String>>,
+}
+
+impl ChannelState {
+    /// Construct the state for a bot with the given nick, in no channels.
+    pub fn new(nick: &str) -> ChannelState {
+        ChannelState {
+            nick: nick.to_string(),
+            prefixes: vec![('o', '@'), ('v', '+')],
+            always_arg: "beIk".to_string(),
+            set_arg: "l".to_string(),
+            list_modes: "beI".to_string(),
+            extbans: None,
+            channels: HashMap::new(),
+            hosts: HashMap::new(),
+            accounts: HashMap::new(),
+            listing: HashMap::new(),
+        }
+    }
+
+    /// The bot's current nick.
+    pub fn nick(&self) -> &str {
+        self.nick.as_slice()
+    }
+
+    /// Is a nick the bot's own?
+    pub fn is_me(&self, nick: &str) -> bool {
+        nick.to_lowercase() == self.nick.as_slice().to_lowercase()
+    }
+
+    /// The names of the channels the bot is in.
+    pub fn channels(&self) -> Vec<&str> {
+        self.channels.values().map(|c| c.name.as_slice()).collect()
+    }
+
+    /// A channel the bot is in, if it is.
+    pub fn channel(&self, chan: &str) -> Option<&Channel> {
+        self.channels.get(&chan.to_lowercase())
+    }
+
+    /// The nicks of everyone in a channel.
+    pub fn members(&self, chan: &str) -> Vec<&str> {
+        match self.channel(chan) {
+            Some(c) => c.members.values().map(|m| m.nick.as_slice()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The prefix modes someone has in a channel, or None if they aren't in
+    /// it.
+    pub fn modes(&self, chan: &str, nick: &str) -> Option<&str> {
+        self.channel(chan).and_then(|c| {
+            c.members.get(&nick.to_lowercase())
+        }).map(|m| m.modes.as_slice())
+    }
+
+    /// Is someone a channel operator (or higher), or a half-op? Prefix modes
+    /// are listed highest first, so that's anything up to `h`, or `o` where
+    /// there are no half-ops.
+    pub fn is_op(&self, chan: &str, nick: &str) -> bool {
+        let lowest = match self.rank('h').or(self.rank('o')) {
+            Some(i) => i,
+            None => return false,
+        };
+        match self.modes(chan, nick) {
+            Some(modes) => modes.chars().any(|m| {
+                self.rank(m).map_or(false, |i| i <= lowest)
+            }),
+            None => false,
+        }
+    }
+
+    /// The mode and mask to quiet everyone matching a mask with, like
+    /// `(q, *!*@host)`, or None if the network has no way to. That's `+q`
+    /// where it's a list of quiets, as on Charybdis, or otherwise a `~q:` or
+    /// `m:` extban on the ban list, as on Unreal and InspIRCd.
+    pub fn quiet(&self, mask: &str) -> Option<(char, String)> {
+        let owner = self.prefixes.iter().any(|&(m, _)| m == 'q');
+        if !owner && self.list_modes.as_slice().contains_char('q') {
+            return Some(('q', mask.to_string()));
+        }
+        let (prefix, kinds) = match self.extbans {
+            Some((ref prefix, ref kinds)) => (prefix, kinds),
+            None => return None,
+        };
+        ['q', 'm'].iter().find(|k| {
+            kinds.as_slice().contains_char(**k)
+        }).map(|k| ('b', format!("{}{}:{}", prefix, k, mask)))
+    }
+
+    /// Does someone have any prefix mode at all, like voice?
+    pub fn is_privileged(&self, chan: &str, nick: &str) -> bool {
+        match self.modes(chan, nick) {
+            Some(modes) => !modes.is_empty(),
+            None => false,
+        }
+    }
+
+    /// Can the bot set modes and kick people in a channel?
+    pub fn am_op(&self, chan: &str) -> bool {
+        self.is_op(chan, self.nick.as_slice())
+    }
+
+    /// Is someone in any channel with the bot? Only then does the bot see
+    /// them change nick or quit.
+    pub fn is_around(&self, nick: &str) -> bool {
+        let key = nick.to_lowercase();
+        self.channels.values().any(|c| c.members.contains_key(&key))
+    }
+
+    /// Someone's full hostmask, if they've been seen.
+    pub fn hostmask(&self, nick: &str) -> Option<String> {
+        self.hosts.get(&nick.to_lowercase()).map(|h| {
+            format!("{}!{}", nick, h)
+        })
+    }
+
+    /// Someone's account, if they're known to be logged in.
+    pub fn account(&self, nick: &str) -> Option<&str> {
+        self.accounts.get(&nick.to_lowercase()).map(|a| a.as_slice())
+    }
+
+    /// Update the state from a message from the server.
+    pub fn update(&mut self, msg: &IrcMsg) {
+        let sender = msg.match_sender();
+        match sender {
+            Some((ref nick, ref host)) if !host.is_empty() => {
+                self.hosts.insert(nick.as_slice().to_lowercase(),
+                                  host.clone());
+            },
+            _ => (),
+        }
+        let nick = sender.map(|(nick, _)| nick).unwrap_or(String::new());
+        let nick = nick.as_slice();
+        let params = msg.params();
+        let param = |i: uint| params.get(i).map(|p| *p).unwrap_or("");
+        match msg.tags.get(&"account".to_string()) {
+            Some(account) if !nick.is_empty() => {
+                self.accounts.insert(nick.to_lowercase(), account.clone());
+            },
+            _ => (),
+        }
+
+        match msg.code.as_slice() {
+            // Whatever we asked for, this is the nick we got.
+            "001" => self.nick = param(0).to_string(),
+            "005" => {
+                for token in params.iter() {
+                    self.isupport(*token);
+                }
+            },
+            "353" => self.names(param(2), param(3)),
+            // :server 367 me #chan mask setter time
+            "367" => {
+                let chan = param(1).to_lowercase();
+                match self.listing.entry(chan) {
+                    Vacant(entry) => { entry.set(vec![param(2).to_string()]); },
+                    Occupied(entry) => {
+                        entry.into_mut().push(param(2).to_string());
+                    },
+                }
+            },
+            "368" => {
+                let chan = param(1).to_lowercase();
+                let bans = self.listing.remove(&chan).unwrap_or(Vec::new());
+                match self.channels.get_mut(&chan) {
+                    Some(c) => c.bans = bans,
+                    None => (),
+                }
+            },
+            "JOIN" => {
+                let chan = param(0);
+                // With extended-join: JOIN #chan account :realname. Without
+                // it or an account tag, whatever we knew is from before they
+                // were around, and can't be trusted.
+                if params.len() >= 3 {
+                    self.logged_in(nick, param(1));
+                } else if !msg.tags.contains_key(&"account".to_string()) {
+                    self.accounts.remove(&nick.to_lowercase());
+                }
+                if self.is_me(nick) {
+                    self.channels.insert(chan.to_lowercase(), Channel {
+                        name: chan.to_string(),
+                        members: HashMap::new(),
+                        bans: Vec::new(),
+                    });
+                }
+                self.add(chan, nick, String::new());
+            },
+            "PART" => self.remove(param(0), nick),
+            "KICK" => self.remove(param(0), param(1)),
+            "QUIT" => {
+                for c in self.channels.values_mut() {
+                    c.members.remove(&nick.to_lowercase());
+                }
+                self.hosts.remove(&nick.to_lowercase());
+                self.accounts.remove(&nick.to_lowercase());
+            },
+            "NICK" => self.rename(nick, param(0)),
+            "ACCOUNT" => self.logged_in(nick, param(0)),
+            "MODE" => {
+                let args: Vec<&str> = params.iter().skip(2).map(|p| *p)
+                                                           .collect();
+                self.mode(param(0), param(1), args);
+            },
+            _ => (),
+        }
+
+        // Whoever isn't in a channel with us could change hands unseen, so
+        // their hostmask and account are only good for this message.
+        self.forget_if_gone(nick);
+        if msg.code.as_slice() == "KICK" {
+            self.forget_if_gone(param(1));
+        }
+    }
+
+    fn forget_if_gone(&mut self, nick: &str) {
+        if nick.is_empty() || self.is_me(nick) || self.is_around(nick) {
+            return;
+        }
+        self.hosts.remove(&nick.to_lowercase());
+        self.accounts.remove(&nick.to_lowercase());
+    }
+
+    // `*` means logged out.
+    fn logged_in(&mut self, nick: &str, account: &str) {
+        if account == "*" {
+            self.accounts.remove(&nick.to_lowercase());
+        } else {
+            self.accounts.insert(nick.to_lowercase(), account.to_string());
+        }
+    }
+
+    /// Does a channel mode take an argument when it's set, or unset?
+    pub fn takes_arg(&self, mode: char, adding: bool) -> bool {
+        self.rank(mode).is_some()
+            || self.always_arg.as_slice().contains_char(mode)
+            || (adding && self.set_arg.as_slice().contains_char(mode))
+    }
+
+    // Where a prefix mode comes in `PREFIX`, highest first.
+    fn rank(&self, mode: char) -> Option<uint> {
+        self.prefixes.iter().position(|&(m, _)| m == mode)
+    }
+
+    // Read the parts of the 005 reply we care about.
+    fn isupport(&mut self, token: &str) {
+        if token.starts_with("PREFIX=(") {
+            // PREFIX=(ov)@+
+            let spec = token.slice_from(8);
+            match spec.find(')') {
+                Some(i) => {
+                    let modes = spec.slice_to(i).chars();
+                    let symbols = spec.slice_from(i + 1).chars();
+                    self.prefixes = modes.zip(symbols).collect();
+                },
+                None => (),
+            }
+        } else if token.starts_with("CHANMODES=") {
+            // CHANMODES=list,always,when-set,never
+            let kinds: Vec<&str> = token.slice_from(10).split(',').collect();
+            if kinds.len() >= 3 {
+                self.always_arg = format!("{}{}", kinds[0], kinds[1]);
+                self.set_arg = kinds[2].to_string();
+                self.list_modes = kinds[0].to_string();
+            }
+        } else if token.starts_with("EXTBAN=") {
+            // EXTBAN=~,qjncr, or EXTBAN=,Rcjm without a prefix.
+            let spec = token.slice_from(7);
+            match spec.find(',') {
+                Some(i) => {
+                    self.extbans = Some((spec.slice_to(i).to_string(),
+                                         spec.slice_from(i + 1).to_string()));
+                },
+                None => (),
+            }
+        }
+    }
+
+    // :server 353 me = #chan :@alice +bob carol
+    fn names(&mut self, chan: &str, names: &str) {
+        for name in names.words() {
+            let mut modes = String::new();
+            let mut nick = name;
+            loop {
+                let first = match nick.chars().next() {
+                    Some(c) => c,
+                    None => break,
+                };
+                match self.prefixes.iter().find(|&&(_, s)| s == first) {
+                    Some(&(m, _)) => modes.push(m),
+                    None => break,
+                }
+                nick = nick.slice_from(first.len_utf8());
+            }
+            // With userhost-in-names, names are full hostmasks.
+            let nick = match nick.find('!') {
+                Some(i) => {
+                    self.hosts.insert(nick.slice_to(i).to_lowercase(),
+                                      nick.slice_from(i + 1).to_string());
+                    nick.slice_to(i)
+                },
+                None => nick,
+            };
+            self.add(chan, nick, modes);
+        }
+    }
+
+    fn add(&mut self, chan: &str, nick: &str, modes: String) {
+        match self.channels.get_mut(&chan.to_lowercase()) {
+            Some(c) => {
+                c.members.insert(nick.to_lowercase(), Member {
+                    nick: nick.to_string(),
+                    modes: modes,
+                });
+            },
+            None => (),
+        }
+    }
+
+    fn remove(&mut self, chan: &str, nick: &str) {
+        if self.is_me(nick) {
+            self.channels.remove(&chan.to_lowercase());
+            return;
+        }
+        match self.channels.get_mut(&chan.to_lowercase()) {
+            Some(c) => { c.members.remove(&nick.to_lowercase()); },
+            None => (),
+        }
+    }
+
+    fn rename(&mut self, old: &str, new: &str) {
+        if self.is_me(old) {
+            self.nick = new.to_string();
+        }
+        let (old, key) = (old.to_lowercase(), new.to_lowercase());
+        for c in self.channels.values_mut() {
+            match c.members.remove(&old) {
+                Some(mut m) => {
+                    m.nick = new.to_string();
+                    c.members.insert(key.clone(), m);
+                },
+                None => (),
+            }
+        }
+        match self.hosts.remove(&old) {
+            Some(host) => { self.hosts.insert(key.clone(), host); },
+            None => (),
+        }
+        match self.accounts.remove(&old) {
+            Some(account) => { self.accounts.insert(key, account); },
+            None => (),
+        }
+    }
+
+    // MODE #chan +ov-b alice bob *!*@spam.example
+    fn mode(&mut self, chan: &str, changes: &str, args: Vec<&str>) {
+        let c = match self.channels.get_mut(&chan.to_lowercase()) {
+            Some(c) => c,
+            None => return,
+        };
+        let mut args = args.into_iter();
+        let mut adding = true;
+        for m in changes.chars() {
+            match m {
+                '+' => adding = true,
+                '-' => adding = false,
+                m if self.prefixes.iter().any(|&(p, _)| p == m) => {
+                    let nick = match args.next() {
+                        Some(nick) => nick.to_lowercase(),
+                        None => return,
+                    };
+                    let member = match c.members.get_mut(&nick) {
+                        Some(member) => member,
+                        None => continue,
+                    };
+                    let had = member.modes.as_slice().contains_char(m);
+                    if adding && !had {
+                        member.modes.push(m);
+                    } else if !adding && had {
+                        member.modes = member.modes.as_slice().chars()
+                                             .filter(|&x| x != m).collect();
+                    }
+                },
+                'b' => {
+                    let mask = match args.next() {
+                        Some(mask) => mask.to_string(),
+                        None => return,
+                    };
+                    c.bans.retain(|b| *b != mask);
+                    if adding {
+                        c.bans.push(mask);
+                    }
+                },
+                m if self.always_arg.as_slice().contains_char(m) => {
+                    args.next();
+                },
+                m if adding && self.set_arg.as_slice().contains_char(m) => {
+                    args.next();
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use irc::msg::IrcMsg;
+    use super::ChannelState;
+
+    fn state(isupport: &str) -> ChannelState {
+        let mut state = ChannelState::new("cleese");
+        let lines = [
+            format!(":server 005 cleese {} :are supported", isupport),
+            ":cleese!c@host JOIN #chan".to_string(),
+            ":server 353 cleese = #chan :~owner @op %half +voice".to_string(),
+        ];
+        for line in lines.iter() {
+            state.update(&IrcMsg::new(line.as_slice()).unwrap());
+        }
+        state
+    }
+
+    #[test]
+    fn quiets_with_a_list_mode() {
+        let s = state("PREFIX=(ov)@+ CHANMODES=bqeI,k,l,imnpst");
+        assert_eq!(s.quiet("*!*@h"), Some(('q', "*!*@h".to_string())));
+    }
+
+    #[test]
+    fn quiets_with_an_extban() {
+        let s = state("PREFIX=(qaohv)~&@%+ CHANMODES=beI,k,l,imnpst \
+                       EXTBAN=~,qjncr");
+        assert_eq!(s.quiet("*!*@h"), Some(('b', "~q:*!*@h".to_string())));
+        let s = state("PREFIX=(qaohv)~&@%+ CHANMODES=beI,k,l,imnpst \
+                       EXTBAN=,Rcjm");
+        assert_eq!(s.quiet("*!*@h"), Some(('b', "m:*!*@h".to_string())));
+        let s = state("PREFIX=(qaohv)~&@%+ CHANMODES=beI,k,l,imnpst");
+        assert_eq!(s.quiet("*!*@h"), None);
+    }
+
+    #[test]
+    fn owners_are_ops() {
+        let s = state("PREFIX=(qohv)~@%+ CHANMODES=beI,k,l,imnpst");
+        assert!(s.is_op("#chan", "owner"));
+        assert!(s.is_op("#chan", "op"));
+        assert!(s.is_op("#chan", "half"));
+        assert!(!s.is_op("#chan", "voice"));
+    }
+}
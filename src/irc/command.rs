@@ -12,6 +12,7 @@
 //! is what identifies the text as a command. The rest (command and args) are
 //! passed through to the plugin subsystem and processed there.
 
+use std::collections::HashMap;
 use irc::privmsg::*;
 use util::*;
 
@@ -22,6 +23,8 @@ pub struct IrcCommand<'a> {
     pub name: &'a str,
     pub args: Vec<&'a str>,
     pub channel: &'a str,
+    pub sender: &'a str,
+    pub notes: &'a HashMap<String, String>,
 }
 
 impl<'a> IrcCommand<'a> {
@@ -33,6 +36,8 @@ impl<'a> IrcCommand<'a> {
                     name: cmd.name,
                     args: cmd.args,
                     channel: msg.channel.as_slice(),
+                    sender: msg.sender_nick.as_slice(),
+                    notes: &msg.notes,
                 })
             },
             None => None,
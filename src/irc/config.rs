@@ -5,23 +5,111 @@
 //!
 //! Configuration is loaded from a json file. The only difference between
 //! JsonConfig and IrcConfig is that the IrcConfig doesn't own its strings.
+//!
+//! Secrets, like the server password, channel keys and oper password, don't
+//! have to be kept in the file. Any of them can be written `env:NAME` to read
+//! it from an environment variable, or `file:path` to read it from a file of
+//! its own:
+//!
+//! ```
+//! "password": "env:CLEESE_PASS",
+//! "keys": { "#officers": "file:secrets/officers.key" },
+//! "oper": { "name": "cleese", "password": "env:CLEESE_OPER" },
+//! "umodes": "+B"
+//! ```
+//!
+//! The server, nick, channels and command prefix at the top level are the
+//! first network the bot connects to, named by `name` (or else its host). The
+//! bot can be on more networks at once, listed under `networks`, each with
+//! its own settings. The nick and command prefix default to the top level's:
+//!
+//! ```
+//! "networks": [
+//!     {
+//!         "name": "oftc",
+//!         "host": "irc.oftc.net",
+//!         "port": 6667,
+//!         "channels": ["#cse-club"],
+//!         "cmd_prefix": "!"
+//!     }
+//! ]
+//! ```
+//!
+//! Instead of a `host` and `port`, a network can list several `servers`. The
+//! bot connects to the first one that answers, and moves on to the next
+//! whenever it loses the connection or the server closes the link:
+//!
+//! ```
+//! "servers": [
+//!     { "host": "chat.freenode.net", "port": 6667 },
+//!     { "host": "irc.freenode.net", "port": 8000 },
+//!     { "host": "chat.freenode.net", "port": 6697, "tls": true }
+//! ]
+//! ```
+//!
+//! There's no TLS support yet, so a config with a server marked `tls` is
+//! refused rather than connecting to it in the clear.
+//!
+//! Owners and admins, by NickServ account or hostmask pattern, are only
+//! trusted on the network they're listed under, as an account on one network
+//! says nothing about who has the same name on another. The ones at the top
+//! level are for the first network. Someone can also be written
+//! `network/account` to be trusted on the named network instead:
+//!
+//! ```
+//! "owners": ["alice", "oftc/alice"],
+//! "admins": ["*!*@club.example.org"]
+//! ```
 
 
 use regex::Regex;
+use std::collections::TreeMap;
 use serialize::{json, Decodable};
 use std::io::{File, Open, Read};
+use std::os;
+
+use irc::connection::Server;
+use irc::outbound::{Filter, OutboundConfig};
+use irc::policy::{Invites, InviteConfig, Rejoin, RejoinConfig};
 
 
 /// This configuration matches up exactly with what you see in the config file.
 pub struct IrcConfig<'a> {
-    pub host: &'a str,
-    pub port: u16,
-    pub nick: &'a str,
+    pub config_file: &'a str,
+    pub networks: Vec<NetworkConf<'a>>,
     pub descr: &'a str,
-    pub channels: Vec<&'a str>,
     pub in_blacklist: Vec<&'a str>,
     pub out_blacklist: Vec<Regex>,
+    pub data_dir: &'a str,
+    pub plugin_dir: Option<&'a str>,
+    pub watch_plugins: bool,
+    pub scripts_dir: Option<&'a str>,
+    pub wasm_dir: Option<&'a str>,
+    pub processes: Vec<(&'a str, Vec<&'a str>)>,
+    pub priorities: Vec<(&'a str, int)>,
+    pub filters: Vec<Box<Filter>>,
+    pub invites: Invites,
+    pub rejoin: Rejoin,
+}
+
+
+// The port to use when a host is given without one.
+static DEFAULT_PORT: u16 = 6667;
+
+
+/// One network to connect to, borrowed from the JsonConfig like IrcConfig.
+pub struct NetworkConf<'a> {
+    pub name: &'a str,
+    pub servers: Vec<Server>,
+    pub password: Option<&'a str>,
+    pub nick: &'a str,
+    pub channels: Vec<&'a str>,
+    pub keys: Vec<(&'a str, &'a str)>,
+    pub oper: Option<(&'a str, &'a str)>,
+    pub umodes: Option<&'a str>,
     pub cmd_prefix: &'a str,
+    pub owners: Vec<&'a str>,
+    pub admins: Vec<&'a str>,
 }
 
 
@@ -29,40 +117,330 @@ pub struct IrcConfig<'a> {
 /// as an IrcConfig.
 #[deriving(Decodable)]
 pub struct JsonConfig {
-    pub host: String,
-    pub port: u16,
+    pub name: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub servers: Option<Vec<ServerConfig>>,
+    pub password: Option<String>,
     pub nick: String,
     pub descr: String,
     pub channels: Vec<String>,
+    pub keys: Option<TreeMap<String, String>>,
+    pub oper: Option<OperConfig>,
+    pub umodes: Option<String>,
     pub in_blacklist: Vec<String>,
     pub out_blacklist: Vec<String>,
-    pub cmd_prefix: String
+    pub cmd_prefix: String,
+    pub data_dir: Option<String>,
+    pub owners: Option<Vec<String>>,
+    pub admins: Option<Vec<String>>,
+    pub plugin_dir: Option<String>,
+    pub watch_plugins: Option<bool>,
+    pub scripts_dir: Option<String>,
+    pub wasm_dir: Option<String>,
+    pub processes: Option<TreeMap<String, Vec<String>>>,
+    pub priorities: Option<TreeMap<String, int>>,
+    pub outbound: Option<OutboundConfig>,
+    pub invites: Option<InviteConfig>,
+    pub rejoin: Option<RejoinConfig>,
+    pub networks: Option<Vec<NetworkConfig>>,
 }
 
 impl JsonConfig {
     /// Attempt to open and decode the given configuration file.
     pub fn new(location: String) -> JsonConfig {
-        let p = Path::new(location.as_slice());
+        match JsonConfig::load(location.as_slice()) {
+            Ok(v) => v,
+            Err(e) => panic!("{}", e)
+        }
+    }
+
+    /// Open and decode the given configuration file, without giving up if it
+    /// can't be. Used when reloading the config while the bot is running.
+    pub fn load(location: &str) -> Result<JsonConfig, String> {
+        let p = Path::new(location);
         let mut file = match File::open_mode(&p, Open, Read) {
             Ok(f) => f,
-            Err(e) => panic!("file error: {}", e)
+            Err(e) => return Err(format!("file error: {}", e))
         };
 
         let decoded: String = match file.read_to_string() {
             Ok(f) => f,
-            Err(e) => panic!("file error: {}", e)
+            Err(e) => return Err(format!("file error: {}", e))
         };
 
         let json_object = match json::from_str(decoded.as_slice()) {
             Ok(x) => x,
-            Err(e) => panic!("json error: {}", e)
+            Err(e) => return Err(format!("json error: {}", e))
         };
         let mut decoder = json::Decoder::new(json_object);
 
-        return match Decodable::decode(&mut decoder) {
+        let mut conf: JsonConfig = match Decodable::decode(&mut decoder) {
             Ok(v) => v,
-            Err(e) => panic!("Decoding error: {}", e)
+            Err(e) => return Err(format!("Decoding error: {}", e))
+        };
+        try!(conf.read_secrets());
+        try!(conf.check_servers());
+        Ok(conf)
+    }
+
+    /// Make sure every network has a server to connect to.
+    fn check_servers(&self) -> Result<(), String> {
+        if servers(&self.host, self.port, &self.servers).is_empty() {
+            return Err("no host or servers given".to_string());
+        }
+        try!(check_tls(&self.servers));
+        for n in self.networks.iter().flat_map(|n| n.iter()) {
+            if servers(&n.host, n.port, &n.servers).is_empty() {
+                return Err(format!("no host or servers given for {}",
+                                   n.name));
+            }
+            try!(check_tls(&n.servers));
+        }
+        Ok(())
+    }
+
+    /// Swap every secret written `env:` or `file:` for what it points to.
+    fn read_secrets(&mut self) -> Result<(), String> {
+        try!(read_secrets(&mut self.password, &mut self.keys, &mut self.oper));
+        match self.networks {
+            Some(ref mut networks) => {
+                for n in networks.iter_mut() {
+                    try!(read_secrets(&mut n.password, &mut n.keys,
+                                      &mut n.oper));
+                }
+            },
+            None => (),
+        }
+        Ok(())
+    }
+
+    /// Every network to connect to, the top level's first.
+    pub fn networks<'a>(&'a self) -> Vec<NetworkConf<'a>> {
+        let servers = servers(&self.host, self.port, &self.servers);
+        let name = match self.name {
+            Some(ref name) => name.as_slice(),
+            None => match self.host {
+                Some(ref host) => host.as_slice(),
+                None => self.servers.as_ref().unwrap()[0].host.as_slice(),
+            },
         };
+        let mut networks = vec![NetworkConf {
+            name: name,
+            servers: servers,
+            password: self.password.as_ref().map(|x| x.as_slice()),
+            nick: self.nick.as_slice(),
+            channels: self.channels.iter().map(|x| x.as_slice()).collect(),
+            keys: borrow_keys(&self.keys),
+            oper: self.oper.as_ref().map(|o| {
+                (o.name.as_slice(), o.password.as_slice())
+            }),
+            umodes: self.umodes.as_ref().map(|x| x.as_slice()),
+            cmd_prefix: self.cmd_prefix.as_slice(),
+            owners: borrow_list(&self.owners),
+            admins: borrow_list(&self.admins),
+        }];
+        for n in self.networks.iter().flat_map(|n| n.iter()) {
+            networks.push(NetworkConf {
+                name: n.name.as_slice(),
+                servers: servers(&n.host, n.port, &n.servers),
+                password: n.password.as_ref().map(|x| x.as_slice()),
+                nick: n.nick.as_ref().unwrap_or(&self.nick).as_slice(),
+                channels: n.channels.iter().map(|x| x.as_slice()).collect(),
+                keys: borrow_keys(&n.keys),
+                oper: n.oper.as_ref().map(|o| {
+                    (o.name.as_slice(), o.password.as_slice())
+                }),
+                umodes: n.umodes.as_ref().map(|x| x.as_slice()),
+                cmd_prefix: n.cmd_prefix.as_ref().unwrap_or(&self.cmd_prefix)
+                                                 .as_slice(),
+                owners: borrow_list(&n.owners),
+                admins: borrow_list(&n.admins),
+            });
+        }
+        networks
     }
 }
 
+
+/// A network in the `networks` section of the config file.
+#[deriving(Decodable)]
+pub struct NetworkConfig {
+    pub name: String,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub servers: Option<Vec<ServerConfig>>,
+    pub password: Option<String>,
+    pub nick: Option<String>,
+    pub channels: Vec<String>,
+    pub keys: Option<TreeMap<String, String>>,
+    pub oper: Option<OperConfig>,
+    pub umodes: Option<String>,
+    pub cmd_prefix: Option<String>,
+    pub owners: Option<Vec<String>>,
+    pub admins: Option<Vec<String>>,
+}
+
+
+/// A server in a network's `servers`.
+#[deriving(Decodable)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub tls: Option<bool>,
+}
+
+
+/// The `oper` section of the config file.
+#[deriving(Decodable)]
+pub struct OperConfig {
+    pub name: String,
+    pub password: String,
+}
+
+
+/// Read a secret from the config. `env:NAME` is read from an environment
+/// variable and `file:path` from a file, without the trailing newline.
+/// Anything else is the secret itself.
+pub fn secret(value: &str) -> Result<String, String> {
+    if value.starts_with("env:") {
+        let name = value.slice_from(4);
+        match os::getenv(name) {
+            Some(v) => Ok(v),
+            None => Err(format!("{} isn't set", name)),
+        }
+    } else if value.starts_with("file:") {
+        let path = Path::new(value.slice_from(5));
+        match File::open(&path).read_to_string() {
+            Ok(v) => Ok(v.as_slice().trim_right_chars(['\r', '\n'].as_slice())
+                         .to_string()),
+            Err(e) => Err(format!("can't read {}: {}", path.display(), e)),
+        }
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+
+
+// Read the secrets for one network.
+fn read_secrets(password: &mut Option<String>,
+                keys: &mut Option<TreeMap<String, String>>,
+                oper: &mut Option<OperConfig>) -> Result<(), String> {
+    *password = match password.take() {
+        Some(p) => Some(try!(secret(p.as_slice()))),
+        None => None,
+    };
+    match *keys {
+        Some(ref mut keys) => {
+            for (_, key) in keys.iter_mut() {
+                *key = try!(secret(key.as_slice()));
+            }
+        },
+        None => (),
+    }
+    match *oper {
+        Some(ref mut oper) => {
+            oper.password = try!(secret(oper.password.as_slice()));
+        },
+        None => (),
+    }
+    Ok(())
+}
+
+// The servers for a network, from its `servers` if it has them, or else its
+// `host` and `port`.
+fn servers(host: &Option<String>, port: Option<u16>,
+           servers: &Option<Vec<ServerConfig>>) -> Vec<Server> {
+    match (host, servers) {
+        (_, &Some(ref servers)) => servers.iter().map(|s| {
+            Server {
+                host: s.host.clone(),
+                port: s.port.unwrap_or(DEFAULT_PORT),
+            }
+        }).collect(),
+        (&Some(ref host), &None) => vec![Server {
+            host: host.clone(),
+            port: port.unwrap_or(DEFAULT_PORT),
+        }],
+        (&None, &None) => Vec::new(),
+    }
+}
+
+// Refuse servers marked `tls`, as there's no TLS support to give them.
+fn check_tls(servers: &Option<Vec<ServerConfig>>) -> Result<(), String> {
+    for s in servers.iter().flat_map(|s| s.iter()) {
+        if s.tls == Some(true) {
+            return Err(format!("{}:{} is marked tls, but TLS isn't supported",
+                               s.host, s.port.unwrap_or(DEFAULT_PORT)));
+        }
+    }
+    Ok(())
+}
+
+// Borrow a list that may not be there.
+fn borrow_list<'a>(list: &'a Option<Vec<String>>) -> Vec<&'a str> {
+    match *list {
+        Some(ref l) => l.iter().map(|x| x.as_slice()).collect(),
+        None => Vec::new(),
+    }
+}
+
+// Borrow a network's channel keys.
+fn borrow_keys<'a>(keys: &'a Option<TreeMap<String, String>>)
+                   -> Vec<(&'a str, &'a str)> {
+    match *keys {
+        Some(ref k) => k.iter().map(|(chan, key)| {
+            (chan.as_slice(), key.as_slice())
+        }).collect(),
+        None => Vec::new(),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{File, fs};
+    use std::os;
+    use super::{secret, check_tls, ServerConfig};
+
+    #[test]
+    fn plain_secrets() {
+        assert_eq!(secret("hunter2"), Ok("hunter2".to_string()));
+    }
+
+    #[test]
+    fn secrets_from_the_environment() {
+        os::setenv("CLEESE_TEST_SECRET", "hunter2");
+        assert_eq!(secret("env:CLEESE_TEST_SECRET"),
+                   Ok("hunter2".to_string()));
+        os::unsetenv("CLEESE_TEST_SECRET");
+        assert!(secret("env:CLEESE_TEST_SECRET").is_err());
+    }
+
+    #[test]
+    fn secrets_from_files() {
+        let path = os::tmpdir().join(format!("cleese-secret-{}",
+                                             os::getpid()));
+        File::create(&path).write_str("hunter2\r\n").unwrap();
+        let read = secret(format!("file:{}", path.display()).as_slice());
+        fs::unlink(&path).unwrap();
+        assert_eq!(read, Ok("hunter2".to_string()));
+        assert!(secret(format!("file:{}", path.display()).as_slice())
+                .is_err());
+    }
+
+    #[test]
+    fn refuses_tls() {
+        let server = |tls: Option<bool>| ServerConfig {
+            host: "irc.example.org".to_string(),
+            port: None,
+            tls: tls,
+        };
+        assert!(check_tls(&None).is_ok());
+        assert!(check_tls(&Some(vec![server(None), server(Some(false))]))
+                .is_ok());
+        assert!(check_tls(&Some(vec![server(None), server(Some(true))]))
+                .is_err());
+    }
+}
@@ -4,65 +4,316 @@
 //! # Connection
 //!
 //! This is where the actual connecting to the server happens. The biggest issue
-//! is making sure the connection is safe. The connection establishes a
-//! sender and receiver of events.
+//! is making sure the connection is safe. There's a connection for each
+//! network, and they all feed the same event loop, so events say which
+//! network they're for.
+//!
+//! Each network has a list of servers. Connecting tries them in turn until one
+//! answers, and when the connection is lost, or the server closes the link,
+//! the next one is tried. Connecting and reading happen on a task of their
+//! own, so a network that's down doesn't hold up the others.
+//!
+//! A server that lets the bot in and then drops it straight away, because
+//! it's banned or connecting too often, would otherwise be hammered. Each
+//! connection that doesn't last makes the next attempt wait longer.
 
+use std::fmt;
 use std::io::*;
+use std::io::timer;
+use std::time::Duration;
+
+use irc::scheduler::{Timer, now};
+use irc::bus::Event;
+
 
+// How long to wait after trying every server, in seconds, before going round
+// again.
+static RETRY_DELAY: i64 = 30;
 
-// Events will be handled by main irc handler.
-// Quit is needed as a special case to close down the program.
+// How long a connection has to last, in seconds, for the next one not to wait.
+static STABLE: i64 = 60;
+
+// How long to wait after the first connection that doesn't last, in seconds.
+// It doubles with each one after that, up to MAX_BACKOFF.
+static BACKOFF: i64 = 5;
+static MAX_BACKOFF: i64 = 300;
+
+
+// Events will be handled by main irc handler. Everything but the timer and
+// job events starts with the name of the network it's for.
+// Quit is needed as a special case to close down the program, and leaves
+// every network with the given message.
 pub enum ConnectionEvent {
-    Output(String),
-    Received(String),
-    Quit,
+    Output(String, String),
+    // A line from a network's server, and the id of the connection it came in
+    // on.
+    Received(String, uint, String),
+    // A network's connection, by id, got through to the server at an index
+    // in its list, and a connection was lost.
+    Connected(String, uint, uint, TcpStream),
+    Disconnected(String, uint),
+    // Move a network on to the named server, or else the next one, and who
+    // to tell how it went.
+    Jump(String, Option<String>, String),
+    // Timers are added and cancelled through the event loop, and Tick is sent
+    // once a second to fire whatever is due. Cancel is for the network, plugin
+    // and id.
+    Schedule(Timer),
+    Cancel(String, String, String),
+    Tick,
+    // Output from a background job, and notice that the job is done.
+    JobOutput(uint, String),
+    JobDone(uint),
+    // An event published by a plugin.
+    Publish(String, Event),
+    // A request to change the running bot, and who to tell how it went.
+    Control(String, Control, String),
+    Quit(String),
 }
 
-// A connection to a server.
-pub struct ServerConnection {
-    pub tcp: TcpStream,
+// Things only the event loop can do to the bot, asked for by plugins.
+pub enum Control {
+    // List the plugins, and which are disabled.
+    Plugins,
+    Enable(String),
+    Disable(String),
+    // Reload a plugin loaded from a library.
+    Reload(String),
+    // Re-read the config file.
+    ReloadConfig,
+    Status,
+    // Show who handled the last few commands.
+    Handled,
+}
+
+// A server a network can be reached on.
+#[deriving(Clone)]
+pub struct Server {
     pub host: String,
     pub port: u16,
-    pub tx: Sender<ConnectionEvent>,
-    pub rx: Receiver<ConnectionEvent>,
+}
+
+impl Server {
+    // Is this the server someone asked for, by host or host:port?
+    pub fn is(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.host.as_slice().to_lowercase() == name
+            || format!("{}:{}", self.host, self.port).to_lowercase() == name
+    }
+}
+
+impl fmt::Show for Server {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+// A network's connection to one of its servers.
+pub struct ServerConnection {
+    pub network: String,
+    pub servers: Vec<Server>,
+    // The server we're on, or the first to try.
+    pub current: uint,
+    // Counts up with every connection, so events about an old one can be told
+    // apart.
+    pub id: uint,
+    pub tcp: Option<TcpStream>,
+    pub stream: Option<LineBufferedWriter<TcpStream>>,
+    // When the current connection got through, and how many in a row were
+    // lost before they'd lasted.
+    pub since: Option<i64>,
+    pub drops: uint,
 }
 
 impl ServerConnection {
-    // Will simply fail if we cannot connect.
-    pub fn new(host: &str, port: u16) -> ServerConnection {
-        let addr = format!("{}:{}", host, port);
-        let tcp = match TcpStream::connect(addr.as_slice()) {
-            Ok(x) => x,
-            Err(e) => { panic!("{}", e); },
+    // Nothing happens until `connect`.
+    pub fn new(network: &str, servers: Vec<Server>) -> ServerConnection {
+        ServerConnection {
+            network: network.to_string(),
+            servers: servers,
+            current: 0,
+            id: 0,
+            tcp: None,
+            stream: None,
+            since: None,
+            drops: 0,
+        }
+    }
+
+    // Drop the current connection, if there is one, and start trying the
+    // servers from the one at `start`. A `Connected` event comes once one
+    // answers, and a `Disconnected` once it's lost.
+    pub fn connect(&mut self, start: uint, tx: Sender<ConnectionEvent>) {
+        self.start(start, 0, tx);
+    }
+
+    // Move on to the server after the current one, once the connection has
+    // been lost. If it didn't last, wait a while first.
+    pub fn next(&mut self, tx: Sender<ConnectionEvent>) {
+        let lasted = match self.since {
+            Some(since) => now() - since >= STABLE,
+            None => false,
         };
-        println!("Connected to {}:{}", host, port);
+        self.drops = if lasted { 0 } else { self.drops + 1 };
+        let next = self.current + 1;
+        let wait = backoff(self.drops);
+        self.start(next, wait, tx);
+    }
 
-        let (tx, rx) = channel();
-        ServerConnection {
-            tcp: tcp,
-            host: host.to_string(),
-            port: port,
-            tx: tx,
-            rx: rx,
+    // Like `connect`, waiting a number of seconds before the first attempt.
+    fn start(&mut self, start: uint, wait: i64, tx: Sender<ConnectionEvent>) {
+        self.close();
+        self.id += 1;
+        self.current = start % self.servers.len();
+        let network = self.network.clone();
+        let servers = self.servers.clone();
+        let (start, id) = (self.current, self.id);
+        spawn(proc() {
+            if wait > 0 {
+                println!("Waiting {}s before reconnecting to {}",
+                         wait, network);
+                timer::sleep(Duration::seconds(wait));
+            }
+            run_connection(network, servers, start, id, tx);
+        });
+    }
+
+    // Take up a connection, unless it's from an attempt we've since given up
+    // on. Returns whether it was taken up.
+    pub fn connected(&mut self, id: uint, server: uint,
+                     tcp: &TcpStream) -> bool {
+        if !self.is_current(id) {
+            let mut tcp = tcp.clone();
+            let _ = tcp.close_read();
+            let _ = tcp.close_write();
+            return false;
         }
+        self.current = server;
+        self.stream = Some(LineBufferedWriter::new(tcp.clone()));
+        self.tcp = Some(tcp.clone());
+        self.since = Some(now());
+        true
+    }
+
+    // Is this the latest connection?
+    pub fn is_current(&self, id: uint) -> bool {
+        id == self.id
+    }
+
+    // The server we're on, or trying to get on.
+    pub fn server(&self) -> &Server {
+        &self.servers[self.current]
     }
 
     // Close tcp connection.
-    // Will cause all readers and writers to exit, possibly with safe errors.
-    pub fn close(mut self) {
-        match self.tcp.close_read() {
+    // Will cause its reader to exit, possibly with safe errors.
+    pub fn close(&mut self) {
+        self.stream = None;
+        self.since = None;
+        let mut tcp = match self.tcp.take() {
+            Some(tcp) => tcp,
+            None => return,
+        };
+        match tcp.close_read() {
             Err(e) => println!("Error closing read: {}", e),
             _ => (),
         };
-        match self.tcp.close_write() {
+        match tcp.close_write() {
             Err(e) => println!("Error closing write: {}", e),
             _ => (),
         };
-        drop(self.tcp.clone());
     }
 }
 
 
+// Try each server in turn from `start` until one answers, waiting a while
+// after every full round, then read from it until the connection is lost.
+// Gives up once the event loop has gone away.
+fn run_connection(network: String, servers: Vec<Server>, start: uint,
+                  id: uint, tx: Sender<ConnectionEvent>) {
+    let mut i = start;
+    let mut tcp = None;
+    while tcp.is_none() {
+        tcp = open(network.as_slice(), &servers[i % servers.len()]);
+        if tcp.is_some() {
+            break;
+        }
+        i += 1;
+        if i % servers.len() == start {
+            println!("Couldn't reach any server for {}, trying again in {}s",
+                     network, RETRY_DELAY);
+            timer::sleep(Duration::seconds(RETRY_DELAY));
+        }
+    }
+    let tcp = tcp.unwrap();
+    let server = i % servers.len();
+    let connected = ConnectionEvent::Connected(network.clone(), id, server,
+                                               tcp.clone());
+    if tx.send_opt(connected).is_err() {
+        return;
+    }
+
+    let mut reader = BufferedReader::new(tcp);
+    loop {
+        match reader.read_line() {
+            Ok(x) => {
+                let received = ConnectionEvent::Received(network.clone(), id,
+                                                         x);
+                if tx.send_opt(received).is_err() {
+                    return;
+                }
+            },
+            Err(e) => {
+                println!("Lost {} ({}): {}", network, servers[server], e);
+                break;
+            },
+        }
+    }
+    let _ = tx.send_opt(ConnectionEvent::Disconnected(network, id));
+}
+
+// How long to wait before connecting again, after a number of connections in a
+// row that didn't last.
+fn backoff(drops: uint) -> i64 {
+    if drops == 0 {
+        return 0;
+    }
+    let mut wait = BACKOFF;
+    for _ in range(1, drops) {
+        wait *= 2;
+        if wait >= MAX_BACKOFF {
+            return MAX_BACKOFF;
+        }
+    }
+    wait
+}
+
+// Connect to one server.
+fn open(network: &str, server: &Server) -> Option<TcpStream> {
+    let addr = format!("{}:{}", server.host, server.port);
+    match TcpStream::connect(addr.as_slice()) {
+        Ok(tcp) => {
+            println!("Connected to {} at {}", network, server);
+            Some(tcp)
+        },
+        Err(e) => {
+            println!("Couldn't connect to {} at {}: {}", network, server, e);
+            None
+        },
+    }
+}
+
+
+// Send a Tick every second until the event loop goes away.
+pub fn ticker(tx: Sender<ConnectionEvent>) {
+    loop {
+        timer::sleep(Duration::seconds(1));
+        if tx.send_opt(ConnectionEvent::Tick).is_err() {
+            break;
+        }
+    }
+}
+
 // Primitive write from tcp buffer.
 pub fn write_line(stream: &mut LineBufferedWriter<TcpStream>, s: &str) {
     match stream.write_line(s) {
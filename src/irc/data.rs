@@ -2,76 +2,196 @@
 // project's directory structure.
 
 use std::io::*;
+use std::mem;
 use regex::*;
-use std::collections::HashSet;
 use std::collections::HashMap;
+use std::collections::{RingBuf, TreeMap};
 
 use irc::config::*;
 use irc::connection::*;
 use irc::msg::IrcMsg;
 use irc::privmsg::IrcPrivMsg;
 use irc::writer::*;
-use irc::info::BotInfo;
 use irc::command::*;
+use irc::scheduler::{Scheduler, now};
+use irc::supervisor::Supervisor;
+use irc::worker::WorkerPool;
+use irc::bus::{Event, EventBus};
+use irc::loader::{DynamicPlugin, Reloader};
+use irc::middleware::{Middleware, Flow};
+use irc::outbound::Filter;
+use irc::limits::{Limiter, Limit, who};
+use irc::auth::Role;
+use irc::policy::{remember, forget};
+use irc::network::Network;
+use irc::channels::{ChannelState, Departure};
+use irc::storage::Storage;
 
 use irc::plugin::*;
 use util::join;
 
+// What a user is told when a plugin panics on their command.
+static PANIC_REPLY: &'static str = "Sorry, something went wrong there.";
+
+// How many background workers to run slow commands on.
+static WORKERS: uint = 4;
+
+// How many dispatched commands to remember for `handled`.
+static DISPATCH_HISTORY: uint = 20;
+
+// Who did what with a command.
+struct Dispatch {
+    cmd: String,
+    sender: String,
+    accepted: Option<String>,
+    observed: Vec<String>,
+    denied: Vec<String>,
+}
+
+impl Dispatch {
+    fn describe(&self) -> String {
+        let accepted = match self.accepted {
+            Some(ref name) => format!("accepted by {}", name),
+            None => "not accepted".to_string(),
+        };
+        let observed = if self.observed.is_empty() {
+            String::new()
+        } else {
+            let names: Vec<&str> = self.observed.iter().map(|x| {
+                x.as_slice()
+            }).collect();
+            format!(", observed by {}", join(&names, ", "))
+        };
+        let denied = if self.denied.is_empty() {
+            String::new()
+        } else {
+            let names: Vec<&str> = self.denied.iter().map(|x| {
+                x.as_slice()
+            }).collect();
+            format!(", denied by {}", join(&names, ", "))
+        };
+        format!("`{}` from {}: {}{}{}",
+                self.cmd, self.sender, accepted, observed, denied)
+    }
+}
+
 pub struct IrcData<'a> {
-    // General config.
-    pub info: BotInfo<'a>,
+    // Every network we're on, the first from the top of the config.
+    pub networks: Vec<Network<'a>>,
 
-    // String to avoid lifetime issues :)
-    pub in_blacklist: HashSet<String>,
-    pub out_blacklist: Vec<Regex>,
+    // Where the config came from, for reloading it.
+    pub config_file: String,
 
-    // Callbacks at received events.
-    pub raw_cb: Vec<|&str, &IrcWriter, &BotInfo|:'a>,
+    // When the bot started, in seconds.
+    pub started: i64,
 
-    // Callbacks for specific irc codes. Multimap.
-    pub code_cb: HashMap<String, Vec<|&IrcMsg, &IrcWriter, &BotInfo|:'a>>,
+    pub out_blacklist: Vec<Regex>,
 
-    // Callbacks for PRIVMSG.
-    pub privmsg_cb: Vec<|&IrcPrivMsg, &IrcWriter, &BotInfo|:'a>,
+    // Everything sent goes through these first. The filters from the config
+    // are kept apart from the ones plugins add, so they can be reloaded.
+    pub config_filters: Vec<Box<Filter>>,
+    pub filters: Vec<Box<Filter>>,
 
-    // Command callbacks. Multimap.
-    pub cmd_cb: HashMap<String, Vec<|&IrcCommand, &IrcWriter, &BotInfo|:'a>>,
+    // Everything received goes through here, after the network's own
+    // middleware, before the plugins see it.
+    pub middleware: Vec<Box<Middleware + 'a>>,
 
     // We can register external functions to be spawned during runtime.
     // Workaround as I couldn't get Irc to hold a valid tx we can return.
     // The problem is what to do with the rx.
     pub spawn_funcs: Vec<fn(Sender<ConnectionEvent>)>,
 
+    // Timers registered by plugins.
+    pub scheduler: Scheduler,
+
+    // Catches plugin panics and disables repeat offenders.
+    pub supervisor: Supervisor,
+
+    // Runs slow plugin commands off the event loop.
+    pub workers: WorkerPool,
+
+    // Who gets which events published by plugins.
+    pub bus: EventBus,
+
+    // Where plugins loaded from libraries came from, for reloading them.
+    pub reloader: Reloader,
+
+    // Rate limits and cooldowns for commands.
+    pub limiter: Limiter,
+
+    // Priorities from the config, overriding what plugins say.
+    pub priorities: HashMap<String, int>,
+
+    // The last few commands and who handled them.
+    dispatches: RingBuf<Dispatch>,
+
+    // Kept in dispatch order.
     pub plugins: Vec<Box<Plugin + 'a>>,
 }
 
 impl <'a> IrcData<'a> {
-    pub fn new<'b>(conf: IrcConfig<'b>) -> IrcData<'b> {
-        // Couldn't there be a nicer way to do this?
-        let mut in_blacklist = HashSet::new();
-        for x in conf.in_blacklist.iter() {
-            in_blacklist.insert(x.to_string());
+    pub fn new<'b>(conf: IrcConfig<'b>, storage: Storage,
+                   tx: Sender<ConnectionEvent>) -> IrcData<'b> {
+        let timers = Path::new(conf.data_dir).join("timers.json");
+        let watch = conf.watch_plugins;
+        let mut priorities = HashMap::new();
+        for &(name, priority) in conf.priorities.iter() {
+            priorities.insert(name.to_string(), priority);
         }
 
+        let networks: Vec<Network<'b>> = conf.networks.iter().map(|net| {
+            Network::new(&conf, net, storage.clone())
+        }).collect();
+        let mut scheduler = Scheduler::new(timers);
+        scheduler.adopt(networks[0].name());
+
         IrcData {
-            info: BotInfo::new(&conf),
-            in_blacklist: in_blacklist,
+            networks: networks,
+            config_file: conf.config_file.to_string(),
+            started: now(),
             out_blacklist: conf.out_blacklist,
+            config_filters: conf.filters,
+            filters: Vec::new(),
 
-            raw_cb: Vec::new(),
-            code_cb: HashMap::new(),
-            privmsg_cb: Vec::new(),
-            cmd_cb: HashMap::new(),
+            middleware: Vec::new(),
             spawn_funcs: Vec::new(),
+            scheduler: scheduler,
+            supervisor: Supervisor::new(),
+            workers: WorkerPool::new(WORKERS, tx),
+            bus: EventBus::new(),
+            reloader: Reloader::new(watch),
 
+            limiter: Limiter::new(),
+            priorities: priorities,
+            dispatches: RingBuf::new(),
             plugins: Vec::new(),
         }
     }
 
-    /// Actually write something to irc.
-    pub fn handle_write(&self, s: &String,
+    /// Actually write something to irc, once the outbound filters have had
+    /// their say.
+    pub fn handle_write(&mut self, s: &String,
                         stream: &mut LineBufferedWriter<TcpStream>) {
-        let s = s.as_slice();
+        let mut line = s.clone();
+        let filters = self.config_filters.iter_mut()
+                                         .chain(self.filters.iter_mut());
+        for filter in filters {
+            let key = format!("filter/{}", filter.name());
+            if self.supervisor.is_disabled(key.as_slice()) {
+                continue;
+            }
+            // A filter that panics might have been the one keeping a secret
+            // or a blocked target out, so the line is dropped.
+            let mut out = None;
+            let ok = self.supervisor.run(key.as_slice(), "outgoing line", || {
+                out = filter.outgoing(mem::replace(&mut line, String::new()));
+            });
+            line = match out {
+                Some(line) if ok => line,
+                _ => return,
+            };
+        }
+        let s = line.as_slice();
         let mut blacklisted = false;
         for re in self.out_blacklist.iter() {
             if re.is_match(s) {
@@ -79,24 +199,55 @@ impl <'a> IrcData<'a> {
             }
         }
         if !blacklisted {
-            println!("> {}", s);
+            println!("> {}", hide_secrets(s));
         }
         write_line(stream, s);
     }
 
-    /// Called when we receive a response from the server.
+    /// Which network a writer writes to. Anything unknown is the first.
+    fn net(&self, writer: &IrcWriter) -> uint {
+        self.find(writer.network())
+    }
+
+    /// Which network has a name. Anything unknown is the first.
+    pub fn find(&self, network: &str) -> uint {
+        self.networks.iter().position(|n| {
+            n.name() == network
+        }).unwrap_or(0)
+    }
+
+    /// Called when a network gets through to one of its servers. What we knew
+    /// about its channels, and what its middleware knew, is from the last
+    /// connection, so it's forgotten.
+    pub fn connected(&mut self, net: uint, server: &Server) {
+        let network = &mut self.networks[net];
+        network.info.server = Some(server.to_string());
+        network.info.state = ChannelState::new(network.info.nick);
+        for layer in network.middleware.iter_mut() {
+            layer.connected();
+        }
+    }
+
+    /// Called when a network loses its server.
+    pub fn disconnected(&mut self, net: uint) {
+        self.networks[net].info.server = None;
+    }
+
+    /// Called when we receive a response from a network's server. The writer
+    /// writes to the same network.
     pub fn handle_received(&mut self, line: &String, writer: &IrcWriter) {
         // Trim away newlines and unneeded spaces.
         let s = line.as_slice().trim();
-
-        for cb in self.raw_cb.iter_mut() {
-            (*cb)(s, writer, &self.info);
-        }
+        let net = self.net(writer);
 
         match IrcMsg::new(s) {
-            Some(msg) => {
-                // Print inside here so we can skip certain codes.
-                self.handle_msg(&msg, writer);
+            Some(mut msg) => {
+                self.update_state(net, &msg, writer);
+                self.update_channels(net, &msg);
+                if self.run_middleware(net, &mut msg, writer)
+                        && self.handle_msg(net, &msg, writer) {
+                    self.run_answered(net, &msg);
+                }
             },
             None => {
                 // Couldn't capture message, print it here.
@@ -105,87 +256,637 @@ impl <'a> IrcData<'a> {
         }
     }
 
-    /// Called when we see a PRIVMSG.
-    fn handle_priv_msg(&mut self, msg: &IrcPrivMsg, writer: &IrcWriter) {
-        for cb in self.privmsg_cb.iter_mut() {
-            (*cb)(msg, writer, &self.info);
+    /// Keep track of the channels we're in, and let plugins know about the
+    /// changes they might be waiting for.
+    fn update_state(&mut self, net: uint, msg: &IrcMsg, writer: &IrcWriter) {
+        let code = msg.code.as_slice();
+        let chan = msg.params().get(if code == "368" { 1 } else { 0 })
+                               .map(|c| c.to_string())
+                               .unwrap_or(String::new());
+        let state = &mut self.networks[net].info.state;
+        let was_op = state.am_op(chan.as_slice());
+        let left = departures(state, msg);
+        state.update(msg);
+        let is_op = state.am_op(chan.as_slice());
+
+        for departure in left.iter() {
+            writer.publish(Event::new("core", "channel.left", departure));
+        }
+        let topic = match code {
+            "368" => "channel.bans",
+            "MODE" if is_op && !was_op => "channel.opped",
+            "MODE" if was_op && !is_op => "channel.deopped",
+            _ => return,
+        };
+        writer.publish(Event::new("core", topic, &chan));
+    }
+
+    /// Remember the channels joined while running, and get back in to the
+    /// ones we're kicked from.
+    fn update_channels(&mut self, net: uint, msg: &IrcMsg) {
+        let nick = match msg.match_sender() {
+            Some((nick, _)) => nick,
+            None => String::new(),
+        };
+        let params = msg.params();
+        let param = |i: uint| params.get(i).map(|p| *p).unwrap_or("");
+        let network = &mut self.networks[net];
+        let name = network.name();
+        let me = network.info.state.is_me(nick.as_slice());
+        let storage = &network.info.storage;
+        let channels = &network.info.channels;
+        let configured = |chan: &str| channels.iter().any(|c| {
+            c.to_lowercase() == chan.to_lowercase()
+        });
+        let turned_away = match msg.code.as_slice() {
+            "KICK" if network.info.state.is_me(param(1)) => Some(param(0)),
+            // Full, invite only, banned or the wrong key.
+            "471" | "473" | "474" | "475"
+                if network.rejoin.is_rejoining(param(1)) => Some(param(1)),
+            "JOIN" if me => {
+                if !configured(param(0)) {
+                    remember(storage, name, param(0));
+                }
+                None
+            },
+            "PART" if me => {
+                network.rejoin.forget(param(0));
+                forget(storage, name, param(0));
+                None
+            },
+            _ => None,
+        };
+        match turned_away {
+            Some(chan) => match network.rejoin.kicked(chan, now()) {
+                Some(wait) => println!("Out of {} on {}, rejoining in {}s",
+                                       chan, name, wait),
+                None => {
+                    println!("Giving up on getting back in to {} on {}",
+                             chan, name);
+                    forget(storage, name, chan);
+                },
+            },
+            None => (),
+        }
+    }
+
+    /// Called once a second to fire any timers that are due.
+    pub fn handle_tick(&mut self, writer: &IrcWriter) {
+        self.workers.expire(now(), writer);
+        self.limiter.expire(now());
+        for network in self.networks.iter_mut() {
+            let writer = writer.on(network.name());
+            for chan in network.rejoin.due(now()).iter() {
+                let chan = chan.as_slice();
+                let key = network.info.key(chan);
+                writer.join_keyed(chan, key.as_ref().map(|k| k.as_slice()));
+            }
+        }
+
+        if self.reloader.watch {
+            for name in self.reloader.changed().iter() {
+                match self.reload_plugin(name.as_slice(), writer) {
+                    Ok(()) => println!("Reloaded plugin {}", name),
+                    Err(e) => println!("Couldn't reload {}: {}", name, e),
+                }
+                self.reloader.seen(name.as_slice());
+            }
+        }
+
+        for timer in self.scheduler.due(now()).iter() {
+            let net = match timer.network {
+                Some(ref network) => self.find(network.as_slice()),
+                None => 0,
+            };
+            let info = &self.networks[net].info;
+            let writer = &writer.on(info.network);
+            for plugin in self.plugins.iter_mut() {
+                let name = plugin.name();
+                if name != timer.plugin.as_slice()
+                    || self.supervisor.is_disabled(name) {
+                    continue;
+                }
+                let what = format!("timer `{}`", timer.id);
+                self.supervisor.run(name, what.as_slice(), || {
+                    plugin.timer(timer.id.as_slice(), writer, info);
+                });
+            }
+        }
+    }
+
+    /// Swap a plugin loaded from a library for a fresh copy of the library.
+    /// The old instance is shut down first, so it can save its state for the
+    /// new one. If anything goes wrong the old instance is started back up
+    /// and stays in place.
+    pub fn reload_plugin(&mut self, name: &str,
+                         writer: &IrcWriter) -> Result<(), String> {
+        let path = match self.reloader.path(name) {
+            Some(path) => path,
+            None => return Err(format!("{} isn't from a library", name)),
+        };
+        let pos = match self.plugins.iter().position(|p| p.name() == name) {
+            Some(pos) => pos,
+            None => return Err(format!("{} isn't registered", name)),
+        };
+        let new = try!(DynamicPlugin::open(&path));
+        if new.name() != name {
+            return Err(format!("{} now calls itself {}",
+                               path.display(), new.name()));
+        }
+        let mut new = box new as Box<Plugin + 'a>;
+
+        // Plugins start up and shut down on the first network.
+        let info = &self.networks[0].info;
+        let writer = &writer.on(info.network);
+        let saved = {
+            let old = &mut self.plugins[pos];
+            self.supervisor.run(name, "shutdown", || old.shutdown(info))
+        };
+        if !saved {
+            return Err("the old version panicked while shutting down"
+                       .to_string());
+        }
+        if !self.supervisor.run(name, "startup", || new.startup(writer, info)) {
+            let old = &mut self.plugins[pos];
+            self.supervisor.run(name, "startup", || old.startup(writer, info));
+            return Err("the new version panicked while starting up"
+                       .to_string());
+        }
+
+        self.bus.subscribe(name, new.subscriptions());
+        drop(mem::replace(&mut self.plugins[pos], new));
+        self.supervisor.enable(name);
+        self.reloader.seen(name);
+        Ok(())
+    }
+
+    /// A plugin's priority, from the config or else the plugin itself.
+    pub fn priority(&self, plugin: &Box<Plugin + 'a>) -> int {
+        match self.priorities.get(&plugin.name().to_string()) {
+            Some(p) => *p,
+            None => plugin.priority(),
         }
+    }
+
+    /// Called when a plugin asks to change something about the bot. Whoever
+    /// asked is told how it went.
+    pub fn handle_control(&mut self, control: &Control, reply_to: &str,
+                          writer: &IrcWriter) {
+        match *control {
+            Control::Plugins => {
+                let names: Vec<String> = self.plugins.iter().map(|p| {
+                    if self.supervisor.is_disabled(p.name()) {
+                        format!("{} (disabled)", p.name())
+                    } else {
+                        p.name().to_string()
+                    }
+                }).collect();
+                let names: Vec<&str> = names.iter().map(|x| {
+                    x.as_slice()
+                }).collect();
+                writer.msg(reply_to, join(&names, ", ").as_slice());
+            },
+            Control::Enable(ref name) => {
+                let reply = if self.supervisor.enable(name.as_slice()) {
+                    format!("Enabled {}.", name)
+                } else {
+                    format!("{} isn't disabled.", name)
+                };
+                writer.msg(reply_to, reply.as_slice());
+            },
+            Control::Disable(ref name) => {
+                let name = name.as_slice();
+                let reply = if !self.plugins.iter().any(|p| p.name() == name) {
+                    format!("There's no plugin called {}.", name)
+                } else if self.supervisor.disable(name) {
+                    format!("Disabled {}.", name)
+                } else {
+                    format!("{} is already disabled.", name)
+                };
+                writer.msg(reply_to, reply.as_slice());
+            },
+            Control::Reload(ref name) => {
+                let reply = match self.reload_plugin(name.as_slice(), writer) {
+                    Ok(()) => format!("Reloaded {}.", name),
+                    Err(e) => format!("Couldn't reload {}: {}", name, e),
+                };
+                writer.msg(reply_to, reply.as_slice());
+            },
+            Control::ReloadConfig => {
+                let reply = match self.reload_config() {
+                    Ok(()) => "Reloaded the owners, admins, priorities and \
+                               outbound filters. Anything else needs a \
+                               restart.".to_string(),
+                    Err(e) => format!("Couldn't reload the config: {}", e),
+                };
+                writer.msg(reply_to, reply.as_slice());
+            },
+            Control::Status => {
+                let up = now() - self.started;
+                let disabled = self.plugins.iter().filter(|p| {
+                    self.supervisor.is_disabled(p.name())
+                }).count();
+                let mut reply = format!("Up {}h{:02}m on {} networks, {} \
+                                         plugins ({} disabled), {} jobs \
+                                         running, {} timers.",
+                                        up / 3600, up % 3600 / 60,
+                                        self.networks.len(),
+                                        self.plugins.len(), disabled,
+                                        self.workers.jobs(),
+                                        self.scheduler.len());
+                for network in self.networks.iter() {
+                    let server = match network.info.server {
+                        Some(ref server) => server.as_slice(),
+                        None => "not connected",
+                    };
+                    reply.push_str(format!("\n{}: {}", network.name(),
+                                           server).as_slice());
+                }
+                writer.msg(reply_to, reply.as_slice());
+            },
+            Control::Handled => {
+                if self.dispatches.is_empty() {
+                    writer.msg(reply_to, "No commands yet.");
+                }
+                for d in self.dispatches.iter() {
+                    writer.msg(reply_to, d.describe().as_slice());
+                }
+            },
+        }
+    }
+
+    /// Re-read the parts of the config that can change while running: the
+    /// owners and admins, plugin priorities and outbound filters.
+    fn reload_config(&mut self) -> Result<(), String> {
+        let conf = try!(JsonConfig::load(self.config_file.as_slice()));
+
+        {
+            let networks = conf.networks();
+            for network in self.networks.iter_mut() {
+                network.info.set_grants(&networks);
+            }
+        }
+
+        self.priorities.clear();
+        for (name, priority) in conf.priorities.unwrap_or(TreeMap::new())
+                                               .into_iter() {
+            self.priorities.insert(name, priority);
+        }
+        let priorities: HashMap<String, int> = self.plugins.iter().map(|p| {
+            (p.name().to_string(), self.priority(p))
+        }).collect();
+        // Sorting is stable, so plugins with the same priority keep their
+        // order.
+        self.plugins.sort_by(|a, b| {
+            let a = priorities.get(&a.name().to_string());
+            let b = priorities.get(&b.name().to_string());
+            b.cmp(&a)
+        });
+
+        self.config_filters = match conf.outbound {
+            Some(ref o) => o.filters(),
+            None => Vec::new(),
+        };
+        Ok(())
+    }
+
+    /// Give every plugin a chance to save its state before we quit.
+    pub fn shutdown_plugins(&mut self) {
+        let info = &self.networks[0].info;
+        for plugin in self.plugins.iter_mut() {
+            let name = plugin.name();
+            self.supervisor.run(name, "shutdown", || plugin.shutdown(info));
+        }
+    }
+
+    /// Pass a message down the middleware chain. Returns whether it made it
+    /// all the way through. A layer that panics is skipped. The network's own
+    /// layers come before the registered ones.
+    fn run_middleware(&mut self, net: uint, msg: &mut IrcMsg,
+                      writer: &IrcWriter) -> bool {
+        let network = &mut self.networks[net];
+        let info = &network.info;
+        let layers = network.middleware.iter_mut()
+                                       .chain(self.middleware.iter_mut());
+        for layer in layers {
+            // Kept apart from the plugins, so a layer can share a name with
+            // one without either being disabled for the other's panics.
+            let key = format!("middleware/{}", layer.name());
+            if self.supervisor.is_disabled(key.as_slice()) {
+                continue;
+            }
+            let mut flow = Flow::Continue;
+            let ok = self.supervisor.run(key.as_slice(), "middleware", || {
+                flow = layer.incoming(msg, writer, info);
+            });
+            if !ok && layer.guards() {
+                flow = Flow::Stop;
+            }
+            if flow == Flow::Stop {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Let every layer a message went through know the plugins answered it.
+    fn run_answered(&mut self, net: uint, msg: &IrcMsg) {
+        let network = &mut self.networks[net];
+        let layers = network.middleware.iter_mut()
+                                       .chain(self.middleware.iter_mut());
+        for layer in layers {
+            let key = format!("middleware/{}", layer.name());
+            if self.supervisor.is_disabled(key.as_slice()) {
+                continue;
+            }
+            self.supervisor.run(key.as_slice(), "answered", || {
+                layer.answered(msg);
+            });
+        }
+    }
+
+    /// Called when a plugin publishes an event.
+    pub fn handle_event(&mut self, event: &Event, writer: &IrcWriter) {
+        let info = &self.networks[self.net(writer)].info;
+        for name in self.bus.subscribers(event).iter() {
+            let name = name.as_slice();
+            if self.supervisor.is_disabled(name) {
+                continue;
+            }
+            for plugin in self.plugins.iter_mut() {
+                if plugin.name() != name {
+                    continue;
+                }
+                let what = format!("event `{}`", event.topic);
+                self.supervisor.run(name, what.as_slice(), || {
+                    plugin.event(event, writer, info);
+                });
+                self.bus.delivered(event, name);
+            }
+        }
+    }
+
+    /// Called when someone joins a channel.
+    fn handle_join(&mut self, net: uint, msg: &IrcMsg, writer: &IrcWriter) {
+        let nick = match msg.match_sender() {
+            Some((nick, _)) => nick,
+            None => return,
+        };
+        let channel = msg.param.as_slice().trim_left_chars(':');
+        let info = &self.networks[net].info;
         for plugin in self.plugins.iter_mut() {
-            match plugin.privmsg(msg, writer, &self.info) {
-                Handler::Accepted => break,
-                Handler::Passed => {}
+            let name = plugin.name();
+            if self.supervisor.is_disabled(name) {
+                continue;
+            }
+            self.supervisor.run(name, "join", || {
+                plugin.join(channel, nick.as_slice(), writer, info);
+            });
+        }
+    }
+
+    /// Called when we see a PRIVMSG. Returns whether any plugin did something
+    /// with it.
+    fn handle_priv_msg(&mut self, net: uint, msg: &IrcPrivMsg,
+                       writer: &IrcWriter) -> bool {
+        let info = &self.networks[net].info;
+        let mut answered = false;
+        for plugin in self.plugins.iter_mut() {
+            let name = plugin.name();
+            if self.supervisor.is_disabled(name) {
+                continue;
+            }
+            let mut handled = Handler::Passed;
+            let ok = self.supervisor.run(name, "privmsg", || {
+                handled = plugin.privmsg(msg, writer, info);
+            });
+            if !ok {
+                break;
+            }
+            match handled {
+                Handler::Accepted => return true,
+                Handler::Observed => answered = true,
+                Handler::Passed => {},
+                Handler::Async(job) => {
+                    self.workers.submit(name, info.network, job, now());
+                    return true;
+                }
             }
         }
+        answered
     }
 
-    /// Called when we receive a command from irc.
-    fn handle_cmd(&mut self, cmd: &IrcCommand, writer: &IrcWriter) {
-        // Irc cmd callbacks.
+    /// Called when we receive a command from irc. Returns whether it was
+    /// answered. Anyone sending commands too fast isn't, as the limiter is
+    /// already ignoring them.
+    fn handle_cmd(&mut self, net: uint, cmd: &IrcCommand,
+                  writer: &IrcWriter) -> bool {
         let c = cmd.name.to_string();
 
-        // Return the list of command callbacks if requested.
+        // Return the list of plugins if requested.
         if c.as_slice() == "cmds" {
-            let cmds: Vec<&str> = self.cmd_cb.keys().map(|x| {
-                x.as_slice()
-            }).collect();
+            let names: Vec<&str> = self.plugins.iter().map(|p| {
+                p.name()
+            }).filter(|name| !self.supervisor.is_disabled(*name)).collect();
 
-            let response = join(&cmds, ", ");
+            let response = join(&names, ", ");
             writer.msg(cmd.channel.as_slice(), response.as_slice());
         }
 
-        if self.cmd_cb.contains_key(&c) {
-            let cbs = self.cmd_cb.get_mut(&c).unwrap();
-            for cb in cbs.iter_mut() {
-                (*cb)(cmd, writer, &self.info);
+        // Admins and owners are trusted not to flood.
+        let role = self.networks[net].info.role(cmd);
+        let limited = role < Role::Admin;
+        let who = who(self.networks[net].info.network, cmd);
+        if limited {
+            match self.limiter.throttle(who.as_slice(), now()) {
+                Limit::Allowed => (),
+                Limit::Throttled(secs) => {
+                    let reply = format!("You're sending commands too fast, \
+                                         so I'll ignore you for {}s.", secs);
+                    writer.notice(cmd.sender, reply.as_slice());
+                    return false;
+                },
+                Limit::Quiet => return false,
             }
         }
+
+        let mut dispatch = Dispatch {
+            cmd: cmd.name.to_string(),
+            sender: cmd.sender.to_string(),
+            accepted: None,
+            observed: Vec::new(),
+            denied: Vec::new(),
+        };
+        // The highest role any plugin wanted that the sender doesn't have.
+        let mut refused = None;
+        let info = &self.networks[net].info;
         for plugin in self.plugins.iter_mut() {
-            match plugin.cmd(cmd, writer, &self.info) {
-                Handler::Accepted => break,
-                Handler::Passed => {}
+            let name = plugin.name();
+            if self.supervisor.is_disabled(name) {
+                continue;
+            }
+            // Someone else may still take a command this plugin won't let
+            // the sender use, but if nobody does they should hear why rather
+            // than get the fallback's answer.
+            let needed = plugin.required_role(cmd.name);
+            if role < needed {
+                let what = format!("denied command `{}`", cmd.name);
+                self.supervisor.run(name, what.as_slice(), || {
+                    plugin.denied(cmd, info);
+                });
+                dispatch.denied.push(name.to_string());
+                if refused.map_or(true, |r| needed > r) {
+                    refused = Some(needed);
+                }
+                continue;
+            }
+            if refused.is_some() && plugin.fallback() {
+                break;
+            }
+            let cooldown = plugin.cooldown(cmd.name);
+            let key = cooldown.as_ref().map(|c| {
+                c.key(info.network, name, cmd)
+            });
+            let cooling = match key {
+                Some(ref key) if limited => {
+                    self.limiter.cooling(key.as_slice(), who.as_slice(), now())
+                },
+                _ => Limit::Allowed,
+            };
+            if cooling != Limit::Allowed {
+                match cooling {
+                    Limit::Throttled(secs) => {
+                        let reply = format!("`{}` was just used, try again \
+                                             in {}s.", cmd.name, secs);
+                        writer.notice(cmd.sender, reply.as_slice());
+                    },
+                    _ => (),
+                }
+                dispatch.accepted = Some(format!("{} (cooling down)", name));
+                break;
+            }
+
+            let what = format!("command `{}`", cmd.name);
+            let mut handled = Handler::Passed;
+            let ok = self.supervisor.run(name, what.as_slice(), || {
+                handled = plugin.cmd(cmd, writer, info);
+            });
+            if !ok {
+                writer.msg(cmd.sender, PANIC_REPLY);
+                break;
+            }
+            let accepted = match handled {
+                Handler::Accepted => true,
+                Handler::Observed => {
+                    dispatch.observed.push(name.to_string());
+                    false
+                },
+                Handler::Passed => false,
+                Handler::Async(job) => {
+                    self.workers.submit(name, info.network, job, now());
+                    true
+                }
+            };
+            if accepted {
+                match (cooldown, key) {
+                    (Some(ref c), Some(ref key)) => {
+                        self.limiter.used(key.as_slice(), c, now());
+                    },
+                    _ => (),
+                }
+                dispatch.accepted = Some(name.to_string());
+                break;
             }
         }
-    }
 
-    /// Called when we have a properly formatted irc message.
-    fn handle_msg(&mut self, msg: &IrcMsg, writer: &IrcWriter) {
-        // Print received message if it's not blacklisted.
-        let code = msg.code.clone();
-        if !self.in_blacklist.contains(&code) {
-            println!("< {}", msg.orig);
+        match refused {
+            Some(needed) if dispatch.accepted.is_none() => {
+                let reply = format!("Sorry, `{}` is only for {}s.",
+                                    cmd.name, needed.name());
+                writer.msg(cmd.sender, reply.as_slice());
+            },
+            _ => (),
+        }
+
+        println!("{}", dispatch.describe());
+        let answered = dispatch.accepted.is_some()
+            || !dispatch.observed.is_empty() || refused.is_some();
+        if self.dispatches.len() == DISPATCH_HISTORY {
+            self.dispatches.pop_front();
         }
+        self.dispatches.push_back(dispatch);
+        answered
+    }
 
+    /// Called when we have a properly formatted irc message. Returns whether
+    /// any plugin answered it.
+    fn handle_msg(&mut self, net: uint, msg: &IrcMsg,
+                  writer: &IrcWriter) -> bool {
+        let user = match msg.match_sender() {
+            Some((nick, _)) => nick,
+            None => String::new(),
+        };
         // Get information about all known commands.
-        if msg.param.as_slice() == "cleese :help" {
-            let start = msg.orig.find(':').unwrap();
-            let end = msg.orig.find('!').unwrap();
-            let user = msg.orig.slice(start + 1u, end);
+        if msg.param.as_slice() == "cleese :help" && !user.is_empty() {
             for p in self.plugins.iter() {
+                if self.supervisor.is_disabled(p.name()) {
+                    continue;
+                }
                 let reply = format!("`cleese {}`: {}", p.name(), p.help());
-                writer.msg(user, reply.as_slice());
+                writer.msg(user.as_slice(), reply.as_slice());
             }
         }
 
-        // Irc msg callbacks.
-        let c = msg.code.clone();
-        if self.code_cb.contains_key(&c) {
-            let cbs = self.code_cb.get_mut(&c).unwrap();
-            for cb in cbs.iter_mut() {
-                (*cb)(msg, writer, &self.info);
-            }
+        if msg.code.as_slice() == "JOIN" {
+            self.handle_join(net, msg, writer);
         }
 
         // Should be able to avoid nesting like this.
         match IrcPrivMsg::new(msg) {
             Some(msg) => {
-                self.handle_priv_msg(&msg, writer);
+                let answered = self.handle_priv_msg(net, &msg, writer);
 
-                match IrcCommand::new(&msg, self.info.cmd_prefix) {
-                    Some(cmd) => self.handle_cmd(&cmd, writer),
-                    None => ()
+                let prefix = self.networks[net].info.cmd_prefix;
+                match IrcCommand::new(&msg, prefix) {
+                    Some(cmd) => self.handle_cmd(net, &cmd, writer) || answered,
+                    None => answered
                 }
             },
-            None => ()
+            None => false
         }
     }
 }
+
+
+// Who a message says is leaving which channels. Worked out before the state
+// is updated, while we still know where someone quitting was.
+fn departures(state: &ChannelState, msg: &IrcMsg) -> Vec<Departure> {
+    let nick = match msg.match_sender() {
+        Some((nick, _)) => nick,
+        None => return Vec::new(),
+    };
+    let nick = nick.as_slice();
+    let params = msg.params();
+    match (msg.code.as_slice(), params.as_slice()) {
+        ("PART", [chan, ..]) => vec![Departure::new(chan, nick)],
+        ("KICK", [chan, kicked, ..]) => vec![Departure::new(chan, kicked)],
+        ("QUIT", _) => state.channels().iter().filter(|c| {
+            state.modes(**c, nick).is_some()
+        }).map(|c| Departure::new(*c, nick)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Passwords and keys aren't logged.
+fn hide_secrets(line: &str) -> String {
+    let words: Vec<&str> = line.words().collect();
+    let shown = match words.as_slice() {
+        ["PASS", ..] => 1,
+        ["OPER", _, ..] => 2,
+        ["JOIN", _, _, ..] => 2,
+        _ => return line.to_string(),
+    };
+    let mut hidden = words.slice_to(shown).connect(" ");
+    hidden.push_str(" ***");
+    hidden
+}
@@ -0,0 +1,343 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Ignore
+//!
+//! Keeps the bot from answering people it shouldn't, and from getting stuck in
+//! a reply loop with another bot. Messages from anyone ignored are dropped by
+//! middleware before any plugin sees them.
+//!
+//! Admins ignore people by nick, by hostmask pattern like `*!*@spam.example`
+//! or by account, written `account:name`, either everywhere or in one channel.
+//! The rules are kept in storage, and only apply on the network they were made
+//! on.
+//!
+//! Other bots are ignored everywhere on a network once they're spotted. A bot
+//! is anyone whose messages carry the IRCv3 `bot` tag, or anyone WHOIS says is
+//! a bot (the +B mode). Spotted bots are kept in storage too, so an admin can
+//! see them and `unignore` one spotted by mistake.
+//!
+//! Anyone else who looks like they might be a bot is only ignored for a while:
+//! people who say the same thing several times in a row, and people whose
+//! messages the bot answers in a burst, which is what two bots answering each
+//! other looks like.
+
+use std::cmp::max;
+use std::collections::HashMap;
+
+use irc::{IrcMsg, IrcWriter, BotInfo, Storage};
+use irc::middleware::{Middleware, Flow};
+use irc::auth::glob;
+use irc::scheduler::now;
+
+
+// The same message this many times in a row looks like a bot.
+static REPEATS: uint = 3;
+
+// Repeats further apart than this many seconds don't count.
+static REPEAT_WINDOW: i64 = 60;
+
+// Messages closer together than this many seconds count as a burst.
+static RAPID: i64 = 2;
+
+// This many messages in a burst breaks the loop.
+static BURST: uint = 5;
+
+// How long to ignore someone after a burst or repeats, in seconds.
+static COOLDOWN: i64 = 300;
+
+// How many people to keep track of at once.
+static MAX_RECENT: uint = 1000;
+
+
+/// Someone to ignore on a network, everywhere or in one channel.
+#[deriving(Clone, PartialEq, Show, Encodable, Decodable)]
+pub struct IgnoreRule {
+    pub who: String,
+    pub network: String,
+    pub channel: Option<String>,
+}
+
+impl IgnoreRule {
+    /// Does this rule cover a message from someone on the rule's network,
+    /// sent to `target`?
+    pub fn applies(&self, nick: &str, account: Option<&str>, hostmask: &str,
+                   target: &str) -> bool {
+        let here = match self.channel {
+            Some(ref c) => c.as_slice().to_lowercase() == target.to_lowercase(),
+            None => true,
+        };
+        let who = self.who.as_slice().to_lowercase();
+        let who = who.as_slice();
+        let matched = if who.starts_with("account:") {
+            match account {
+                Some(a) => a.to_lowercase().as_slice() == who.slice_from(8),
+                None => false,
+            }
+        } else if who.contains_char('!') || who.contains_char('@') {
+            glob(who, hostmask.to_lowercase().as_slice())
+        } else {
+            glob(who, nick.to_lowercase().as_slice())
+        };
+        here && matched
+    }
+
+    /// Every rule added by an admin on a network.
+    pub fn all(storage: &Storage, network: &str) -> Vec<IgnoreRule> {
+        let rules: Vec<IgnoreRule> = storage.namespace("ignore")
+                                            .get("rules")
+                                            .unwrap_or(Vec::new());
+        rules.into_iter().filter(|r| r.network.as_slice() == network).collect()
+    }
+
+    /// Keep this rule, unless there's one just like it already.
+    pub fn save(&self, storage: &Storage) {
+        storage.namespace("ignore").transaction(|tx| {
+            let mut rules: Vec<IgnoreRule> = tx.get("rules")
+                                               .unwrap_or(Vec::new());
+            if rules.contains(self) {
+                return false;
+            }
+            rules.push(self.clone());
+            tx.set("rules", &rules);
+            true
+        });
+    }
+
+    /// Stop ignoring someone on a network in a channel, or everywhere if
+    /// `channel` is None. Bots spotted there with that nick are forgotten too.
+    /// Returns whether there was anything to remove.
+    pub fn remove(storage: &Storage, network: &str, who: &str,
+                  channel: Option<&str>) -> bool {
+        storage.namespace("ignore").transaction(|tx| {
+            let mut rules: Vec<IgnoreRule> = tx.get("rules")
+                                               .unwrap_or(Vec::new());
+            let key = bots_key(network);
+            let mut bots: Vec<String> = tx.get(key.as_slice())
+                                          .unwrap_or(Vec::new());
+            let before = rules.len() + bots.len();
+            rules.retain(|r| {
+                r.who.as_slice().to_lowercase() != who.to_lowercase()
+                    || r.network.as_slice() != network
+                    || r.channel.as_ref().map(|c| c.as_slice()) != channel
+            });
+            if channel.is_none() {
+                bots.retain(|b| b.as_slice() != who.to_lowercase().as_slice());
+            }
+            if rules.len() + bots.len() == before {
+                return false;
+            }
+            tx.set("rules", &rules);
+            tx.set(key.as_slice(), &bots);
+            true
+        })
+    }
+
+    /// The nicks of every bot that's been spotted on a network.
+    pub fn bots(storage: &Storage, network: &str) -> Vec<String> {
+        storage.namespace("ignore").get(bots_key(network).as_slice())
+                                   .unwrap_or(Vec::new())
+    }
+}
+
+// Nicks are only someone's on one network, so each keeps its own bots.
+fn bots_key(network: &str) -> String {
+    format!("bots/{}", network)
+}
+
+/// Remember a bot on a network, by lowercased nick.
+fn spotted(storage: &Storage, network: &str, nick: &str, why: &str) {
+    let nick = nick.to_lowercase();
+    let key = bots_key(network);
+    let added = storage.namespace("ignore").transaction(|tx| {
+        let mut bots: Vec<String> = tx.get(key.as_slice())
+                                      .unwrap_or(Vec::new());
+        if bots.contains(&nick) {
+            return false;
+        }
+        bots.push(nick.clone());
+        tx.set(key.as_slice(), &bots);
+        true
+    });
+    if added {
+        println!("Ignoring {} on {} from now on, as {}", nick, network, why);
+    }
+}
+
+
+// What we know about how someone's been talking lately.
+struct Recent {
+    text: String,
+    repeats: uint,
+    said: i64,
+    burst: uint,
+    answered: i64,
+    quiet_until: i64,
+}
+
+impl Recent {
+    fn new() -> Recent {
+        Recent {
+            text: String::new(),
+            repeats: 0,
+            said: 0,
+            burst: 0,
+            answered: 0,
+            quiet_until: 0,
+        }
+    }
+
+    // Nothing here matters any more.
+    fn is_stale(&self, at: i64) -> bool {
+        at - self.said > REPEAT_WINDOW && at - self.answered > RAPID
+            && at >= self.quiet_until
+    }
+}
+
+
+/// Drops messages from anyone ignored. Needs the notes from `Identify`, so
+/// it comes after it in the chain.
+pub struct Ignore {
+    recent: HashMap<String, Recent>,
+}
+
+impl Ignore {
+    pub fn new() -> Ignore {
+        Ignore { recent: HashMap::new() }
+    }
+
+    /// What we know about someone, making room for them if they're new.
+    fn recent(&mut self, nick: &String, at: i64) -> &mut Recent {
+        if !self.recent.contains_key(nick) {
+            if self.recent.len() >= MAX_RECENT {
+                let stale: Vec<String> = self.recent.iter().filter(|&(_, r)| {
+                    r.is_stale(at)
+                }).map(|(k, _)| k.clone()).collect();
+                for k in stale.iter() {
+                    self.recent.remove(k);
+                }
+            }
+            if self.recent.len() >= MAX_RECENT {
+                let oldest = self.recent.iter().min_by(|&(_, r)| {
+                    max(r.said, r.answered)
+                }).map(|(k, _)| k.clone());
+                match oldest {
+                    Some(k) => { self.recent.remove(&k); },
+                    None => (),
+                }
+            }
+            self.recent.insert(nick.clone(), Recent::new());
+        }
+        self.recent.get_mut(nick).unwrap()
+    }
+
+    /// Keep track of what someone says. Returns whether they're being ignored
+    /// for a while.
+    fn track(&mut self, nick: &String, text: &str, at: i64) -> bool {
+        let recent = self.recent(nick, at);
+        if recent.text.as_slice() == text && at - recent.said <= REPEAT_WINDOW {
+            recent.repeats += 1;
+        } else {
+            recent.text = text.to_string();
+            recent.repeats = 1;
+        }
+        recent.said = at;
+        if recent.repeats >= REPEATS {
+            recent.quiet_until = at + COOLDOWN;
+            recent.repeats = 0;
+            println!("Ignoring {} for {}s, as they keep repeating themselves",
+                     nick, COOLDOWN);
+        }
+        at < recent.quiet_until
+    }
+
+    /// Keep track of the bot answering someone, and start ignoring them for a
+    /// while if it's been answering them too quickly.
+    fn answer(&mut self, nick: &String, at: i64) {
+        let recent = self.recent(nick, at);
+        recent.burst = if at - recent.answered <= RAPID {
+            recent.burst + 1
+        } else {
+            1
+        };
+        recent.answered = at;
+        if recent.burst >= BURST {
+            recent.quiet_until = at + COOLDOWN;
+            recent.burst = 0;
+            println!("Ignoring {} for {}s, to break a loop", nick, COOLDOWN);
+        }
+    }
+}
+
+impl Middleware for Ignore {
+    fn connected(&mut self) {
+        self.recent.clear();
+    }
+
+    fn answered(&mut self, msg: &IrcMsg) {
+        if msg.code.as_slice() != "PRIVMSG" {
+            return;
+        }
+        match msg.match_sender() {
+            Some((nick, _)) => {
+                self.answer(&nick.as_slice().to_lowercase(), now());
+            },
+            None => (),
+        }
+    }
+
+    fn incoming(&mut self, msg: &mut IrcMsg,
+                _: &IrcWriter, info: &BotInfo) -> Flow {
+        // :server 335 me them :is a bot
+        if msg.code.as_slice() == "335" {
+            let words: Vec<&str> = msg.param.as_slice().words().collect();
+            if words.len() >= 2 {
+                spotted(&info.storage, info.network, words[1],
+                        "WHOIS says it's a bot");
+            }
+            return Flow::Continue;
+        }
+        let nick = match msg.match_sender() {
+            Some((nick, _)) => nick,
+            None => return Flow::Continue,
+        };
+        if msg.code.as_slice() == "QUIT" {
+            self.recent.remove(&nick.as_slice().to_lowercase());
+        }
+        if msg.code.as_slice() != "PRIVMSG" {
+            return Flow::Continue;
+        }
+        let (target, text) = match msg.param.as_slice().find_str(" :") {
+            Some(i) => (msg.param.as_slice().slice_to(i),
+                        msg.param.as_slice().slice_from(i + 2)),
+            None => return Flow::Continue,
+        };
+        let key = nick.as_slice().to_lowercase();
+
+        if msg.tags.contains_key(&"bot".to_string()) {
+            spotted(&info.storage, info.network, key.as_slice(),
+                    "it's tagged as a bot");
+        }
+        let quiet = self.track(&key, text, now());
+        let bots = IgnoreRule::bots(&info.storage, info.network);
+        if quiet || bots.contains(&key) {
+            return Flow::Stop;
+        }
+
+        let account = msg.notes.get(&"account".to_string())
+                               .map(|a| a.as_slice());
+        let hostmask = msg.notes.get(&"hostmask".to_string())
+                                .map(|h| h.as_slice())
+                                .unwrap_or("");
+        let rules = IgnoreRule::all(&info.storage, info.network);
+        let ignored = rules.iter().any(|r| {
+            r.applies(nick.as_slice(), account, hostmask, target)
+        });
+        if ignored { Flow::Stop } else { Flow::Continue }
+    }
+
+    /// Don't let past anything that might have been from someone ignored.
+    fn guards(&self) -> bool { true }
+
+    fn name(&self) -> &'static str { "ignore" }
+}
@@ -3,30 +3,156 @@
 
 //! # Info
 //!
-//! This is where the information about the bot is maintained. The bot has a
-//! nick, description, vector of channels, and a command prefix. The command
-//! prefix is how commands are identified by the bot.
+//! This is where the information about the bot is maintained. There's one for
+//! each network the bot is on, named by `network`, along with the `server`
+//! it's connected to, if any, and the names of all its `networks`. The bot has
+//! a nick, description, vector of channels and their keys, and a command
+//! prefix. The command prefix is how commands are identified by the bot. The
+//! data directory is where anything that should survive a restart is kept,
+//! and the owners and admins from the config are granted their roles here.
+//! Plugins also reach the persistent storage through here, and the programs
+//! to run as plugins are listed here by name, and what's known about the
+//! channels the bot is in is kept here.
 
-use irc::config::IrcConfig;
+use irc::config::{IrcConfig, NetworkConf};
+use irc::storage::Storage;
+use irc::command::IrcCommand;
+use irc::auth::{Role, Grant};
+use irc::channels::ChannelState;
+use irc::policy::remembered_key;
 
 
-/// Information about our bot.
+/// Information about our bot, on one network.
 pub struct BotInfo<'a> {
+    pub network: &'a str,
+    pub server: Option<String>,
+    pub networks: Vec<&'a str>,
     pub nick: &'a str,
     pub descr: &'a str,
     pub channels: Vec<&'a str>,
+    pub keys: Vec<(&'a str, &'a str)>,
     pub cmd_prefix: &'a str,
+    pub data_dir: &'a str,
+    pub grants: Vec<Grant>,
+    pub plugin_dir: Option<&'a str>,
+    pub scripts_dir: Option<&'a str>,
+    pub wasm_dir: Option<&'a str>,
+    pub processes: Vec<(&'a str, Vec<&'a str>)>,
+    pub storage: Storage,
+    pub state: ChannelState,
 }
 
 impl<'a> BotInfo<'a> {
-    /// Construct a new BotInfo.
-    pub fn new(conf: &IrcConfig<'a>) -> BotInfo<'a> {
-        BotInfo {
-            nick: conf.nick,
+    /// Construct a new BotInfo for a network, sharing the store with the
+    /// other networks.
+    pub fn new(conf: &IrcConfig<'a>, net: &NetworkConf<'a>,
+               storage: Storage) -> BotInfo<'a> {
+        let mut info = BotInfo {
+            network: net.name,
+            server: None,
+            networks: conf.networks.iter().map(|n| n.name).collect(),
+            nick: net.nick,
             descr: conf.descr,
-            channels: conf.channels.clone(),
-            cmd_prefix: conf.cmd_prefix,
+            channels: net.channels.clone(),
+            keys: net.keys.clone(),
+            cmd_prefix: net.cmd_prefix,
+            data_dir: conf.data_dir,
+            grants: Vec::new(),
+            plugin_dir: conf.plugin_dir,
+            scripts_dir: conf.scripts_dir,
+            wasm_dir: conf.wasm_dir,
+            processes: conf.processes.clone(),
+            storage: storage,
+            state: ChannelState::new(net.nick),
+        };
+        info.set_grants(&conf.networks);
+        info
+    }
+
+    /// Grant the owners and admins from the config their roles on this
+    /// network, replacing whoever had them before. That's the ones listed
+    /// under this network, and anyone listed as `network/who` with its name.
+    pub fn set_grants(&mut self, networks: &Vec<NetworkConf>) {
+        self.grants.clear();
+        for net in networks.iter() {
+            let lists = [(&net.owners, Role::Owner),
+                         (&net.admins, Role::Admin)];
+            for &(list, ref role) in lists.iter() {
+                for entry in list.iter() {
+                    match listed_for(*entry, net.name, self.network) {
+                        Some(who) => {
+                            let grant = Grant::everywhere(who, role.clone(),
+                                                          self.network);
+                            self.grants.push(grant);
+                        },
+                        None => (),
+                    }
+                }
+            }
+        }
+    }
+
+    /// The key for a channel, if there is one: the last one an admin gave
+    /// when joining it, or else the one from the config.
+    pub fn key(&self, chan: &str) -> Option<String> {
+        match remembered_key(&self.storage, self.network, chan) {
+            Some(key) => return Some(key),
+            None => (),
         }
+        self.keys.iter().find(|&&(c, _)| {
+            c.to_lowercase() == chan.to_lowercase()
+        }).map(|&(_, key)| key.to_string())
+    }
+
+    /// The role of whoever sent a command, in the channel it was sent to.
+    pub fn role(&self, cmd: &IrcCommand) -> Role {
+        let account = cmd.notes.get(&"account".to_string())
+                               .map(|a| a.as_slice());
+        match cmd.notes.get(&"hostmask".to_string()) {
+            Some(h) => self.role_of(account, h.as_slice(), cmd.channel),
+            None => Role::Everyone,
+        }
+    }
+
+    /// The role of someone with an account (if known) and hostmask, in a
+    /// channel.
+    pub fn role_of(&self, account: Option<&str>, hostmask: &str,
+                   channel: &str) -> Role {
+        let mut role = Role::Everyone;
+        let runtime = Grant::all(&self.storage, self.network);
+        for g in self.grants.iter().chain(runtime.iter()) {
+            if g.role > role
+                && g.applies(self.network, account, hostmask, channel) {
+                role = g.role.clone();
+            }
+        }
+        role
+    }
+
+    /// Is whoever sent a command at least an admin?
+    pub fn is_admin(&self, cmd: &IrcCommand) -> bool {
+        self.role(cmd) >= Role::Admin
     }
 }
 
+
+
+// Who an owner or admin listed under the network `under` is, if they're meant
+// for `network`. Anyone written `network/who` is meant for the network named,
+// and everyone else for the one they're listed under. Hostmasks can have a
+// `/` in them too, but never before their `!` or `@`.
+fn listed_for<'b>(entry: &'b str, under: &str,
+                  network: &str) -> Option<&'b str> {
+    match entry.find('/') {
+        Some(i) if !entry.slice_to(i).contains_char('!')
+            && !entry.slice_to(i).contains_char('@') => {
+            if entry.slice_to(i) == network {
+                Some(entry.slice_from(i + 1))
+            } else {
+                None
+            }
+        },
+        _ if under == network => Some(entry),
+        _ => None,
+    }
+}
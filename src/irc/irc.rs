@@ -3,172 +3,312 @@
 
 //! # IRC
 //!
-//! This is the meat of the project. The IRC object has a connection and data.
+//! This is the meat of the project. The IRC object has a connection to each
+//! network and data, and one event loop handles all of them. A network that
+//! loses its server moves on to the next one in its list.
 
 #![macro_escape]
 
-use std::io::*;
-use std::time::Duration;
-
 use irc::config::*;
 use irc::connection::*;
-use irc::msg::IrcMsg;
-use irc::privmsg::IrcPrivMsg;
 use irc::writer::*;
-use irc::info::BotInfo;
-use irc::command::*;
 use irc::data::*;
-use irc::Plugin;
+use irc::network::Network;
+use irc::middleware::Middleware;
+use irc::outbound::Filter;
+use irc::scheduler::Timer;
+use irc::loader;
+use irc::process::ProcessPlugin;
+use irc::{Plugin, Storage};
 
 pub struct Irc<'a> {
-    // Connections to irc server and over internal channel.
-    conn: ServerConnection,
+    // Connections to the irc servers, in the same order as the networks in
+    // the data.
+    conns: Vec<ServerConnection>,
+
+    // The internal channel everything feeds the event loop through.
+    tx: Sender<ConnectionEvent>,
+    rx: Receiver<ConnectionEvent>,
 
     // All irc data.
     data: IrcData<'a>,
 }
 
 impl<'a> Irc<'a> {
-    // Create a new irc instance and connect to the server, but don't act on it.
-    pub fn connect<'b>(conf: IrcConfig<'b>) -> Irc<'b> {
+    // Create a new irc instance with a connection for every network, all
+    // sharing `storage`. They connect once it runs.
+    pub fn connect<'b>(conf: IrcConfig<'b>, storage: Storage) -> Irc<'b> {
 
         // Split into connection and data,
         // so we can read from an rx and still
         // iterate over callbacks, which needs to be mut_iter
+        let (tx, rx) = channel();
+        let conns = conf.networks.iter().map(|net| {
+            ServerConnection::new(net.name, net.servers.clone())
+        }).collect();
         let mut irc = Irc {
-            conn: ServerConnection::new(conf.host, conf.port),
-            data: IrcData::new(conf),
+            conns: conns,
+            tx: tx.clone(),
+            rx: rx,
+            data: IrcData::new(conf, storage, tx),
         };
 
         irc.init_callbacks();
         irc
     }
 
-    // Register a callback for a specific command.
-    pub fn register_cmd_cb(&mut self, cmd: &str,
-                           cb: |&IrcCommand, &IrcWriter, &BotInfo|:'a) {
-        let c = cmd.to_string();
-        if !self.data.cmd_cb.contains_key(&c) {
-            self.data.cmd_cb.insert(c.clone(), Vec::new());
+    // Add a filter to the end of the outbound chain.
+    pub fn register_filter(&mut self, filter: Box<Filter>) {
+        self.data.filters.push(filter);
+    }
+
+    // Add a layer to the end of the middleware chain.
+    pub fn register_middleware(&mut self, layer: Box<Middleware + 'a>) {
+        self.data.middleware.push(layer);
+    }
+
+    // Register a plugin, placing it after every plugin with the same or a
+    // higher priority. It starts up on the first network.
+    pub fn register_plugin(&mut self, mut plugin: Box<Plugin + 'a>) {
+        let name = plugin.name();
+        let writer = self.writer();
+        let info = &self.data.networks[0].info;
+        self.data.supervisor.run(name, "startup", || {
+            plugin.startup(&writer, info)
+        });
+        self.data.bus.subscribe(name, plugin.subscriptions());
+
+        let priority = self.data.priority(&plugin);
+        let pos = self.data.plugins.iter().position(|p| {
+            self.data.priority(p) < priority
+        });
+        match pos {
+            Some(pos) => self.data.plugins.insert(pos, plugin),
+            None => self.data.plugins.push(plugin),
         }
-        let cbs = self.data.cmd_cb.get_mut(&c).unwrap();
-        cbs.push(cb);
     }
 
-    // Register a callback for a specific irc msg code.
-    pub fn register_code_cb(&mut self, code: &str,
-                            cb: |&IrcMsg, &IrcWriter, &BotInfo|:'a) {
-        let c = code.to_string();
-        if !self.data.code_cb.contains_key(&c) {
-            self.data.code_cb.insert(c.clone(), Vec::new());
+    // Start a timer before the bot is running, e.g. while registering plugins.
+    // Unless it says otherwise, it's on the first network.
+    pub fn schedule(&mut self, timer: Timer) {
+        let timer = timer.on(self.data.networks[0].name());
+        self.data.scheduler.add(timer);
+    }
+
+    // Load every plugin library in the configured plugin directory. They still
+    // have to be registered, so the caller can decide where they go in the
+    // dispatch order.
+    pub fn dynamic_plugins(&mut self) -> Vec<Box<Plugin + 'a>> {
+        let dir = match self.data.networks[0].info.plugin_dir {
+            Some(dir) => Path::new(dir),
+            None => return Vec::new(),
+        };
+        let mut plugins = Vec::new();
+        for p in loader::load_dir(&dir).into_iter() {
+            self.data.reloader.track(p.name(), p.path());
+            plugins.push(box p as Box<Plugin + 'a>);
         }
-        let cbs = self.data.code_cb.get_mut(&c).unwrap();
-        cbs.push(cb);
+        plugins
     }
 
-    // Register a callback for a PRIVMSG.
-    pub fn register_privmsg_cb(&mut self,
-                               cb: |&IrcPrivMsg, &IrcWriter, &BotInfo|:'a) {
-        self.data.privmsg_cb.push(cb);
+    // Make a plugin for every program listed in the config. Like library
+    // plugins, they still have to be registered.
+    pub fn process_plugins(&self) -> Vec<Box<Plugin + 'a>> {
+        self.data.networks[0].info.processes.iter().map(|&(name, ref argv)| {
+            box ProcessPlugin::new(name, argv) as Box<Plugin + 'a>
+        }).collect()
     }
 
-    pub fn register_plugin(&mut self, plugin: Box<Plugin + 'a>) {
-        self.data.plugins.push(plugin);
+    // The directory WebAssembly plugins are loaded from, if there is one.
+    pub fn wasm_dir(&self) -> Option<&'a str> {
+        self.data.networks[0].info.wasm_dir
     }
 
     fn init_callbacks(&mut self) {
-        self.register_code_cb("PING", |msg: &IrcMsg, writer: &IrcWriter, _| {
-            writer.output(format!("PONG {}", msg.param));
-        });
-
-        // Key 004, should be fine as it's usually in the beginning I believe?
-        self.register_code_cb("004", |_, writer: &IrcWriter, info: &BotInfo| {
-            for chan in info.channels.iter() {
-                writer.join(*chan);
-            }
-        });
+        // Drives the scheduler.
+        self.data.spawn_funcs.push(ticker);
     }
 
     // Run irc client and block until done.
-    pub fn run(self) {
-        // Spawn reader which reads from our tcp.
-        self.spawn_reader(self.conn.tx.clone());
+    pub fn run(mut self) {
+        // Start connecting every network, each reading from its tcp once it
+        // gets through.
+        for conn in self.conns.iter_mut() {
+            conn.connect(0, self.tx.clone());
+        }
+
+        // Spawn anything else that wants to feed the event loop.
+        for f in self.data.spawn_funcs.iter() {
+            let f = *f;
+            let tx = self.tx.clone();
+            spawn(proc() { f(tx) });
+        }
 
-        let tx = self.conn.tx.clone();
+        let tx = self.tx.clone();
         self.run_handler(tx);
     }
 
-    // Return a handle we can write through irc with.
+    // Return a handle we can write through irc with, on the first network.
+    // `IrcWriter::on` gives one for any other network.
     pub fn writer(&self) -> IrcWriter {
-        IrcWriter::new(self.conn.tx.clone())
-    }
-
-    // Spawn a proc reader which listens to incoming messages from irc.
-    fn spawn_reader(&self, tx: Sender<ConnectionEvent>) {
-        println!("Spawning irc reader");
-        let tcp = self.conn.tcp.clone(); // Workaround to avoid irc capture
-        spawn(proc() {
-            let mut reader = BufferedReader::new(tcp);
-            let mut attempt = 0u;
-            loop {
-                match reader.read_line() {
-                    Ok(x) => {
-                        tx.send(ConnectionEvent::Received(x));
-                        if attempt > 0 {
-                            println!("Attempt {} successful!", attempt);
-                        }
-                        attempt = 0;
-                    },
-
-                    Err(e) => {
-                        println!("Error! {}", e);
-                        attempt += 1;
-                    },
-                }
-
-                // If we fail, only attempt it 5 times.
-                if attempt == 5 {
-                    break;
-                // Use a 5 second delay between possible attempts.
-                } else if attempt > 0 {
-                    println!("Waiting 5 seconds before next attempt...");
-                    timer::sleep(Duration::seconds(5));
-                }
-            }
-            println!("Quitting irc reader");
-        });
+        IrcWriter::new(self.tx.clone(), self.data.networks[0].name())
     }
 
     // Run event handler. Will block.
     fn run_handler(self, tx: Sender<ConnectionEvent>) {
         println!("Running event handler");
 
-        let tcp = self.conn.tcp.clone();
-        let mut stream = LineBufferedWriter::new(tcp.clone());
-        let writer = IrcWriter::new(tx);
+        let writer = IrcWriter::new(tx.clone(), self.data.networks[0].name());
+        let writers: Vec<IrcWriter> = self.data.networks.iter().map(|n| {
+            writer.on(n.name())
+        }).collect();
 
-        // Start with identifying
-        writer.identify(self.data.info.nick, self.data.info.descr);
-
-        let conn = self.conn;
+        let mut conns = self.conns;
+        let rx = self.rx;
         let mut data = self.data;
 
         // Loop and handle in and output events.
         // Quit is a special case to allow us to close the program.
-        for x in conn.rx.iter() {
+        for x in rx.iter() {
             match x {
-                ConnectionEvent::Output(ref s) => {
-                    data.handle_write(s, &mut stream);
+                ConnectionEvent::Output(ref net, ref s) => {
+                    let net = data.find(net.as_slice());
+                    write(&mut data, &mut conns[net], s);
+                },
+                ConnectionEvent::Received(ref net, id, ref s) => {
+                    // Lines still queued from a connection we've dropped
+                    // aren't about the one we're on now.
+                    let net = data.find(net.as_slice());
+                    if !conns[net].is_current(id) {
+                        continue;
+                    }
+                    data.handle_received(s, &writers[net]);
+
+                    // The server is closing the link, so don't wait for it.
+                    if s.as_slice().starts_with("ERROR") {
+                        println!("{} closed the link, trying the next server",
+                                 conns[net].server());
+                        data.disconnected(net);
+                        conns[net].next(tx.clone());
+                    }
+                },
+                ConnectionEvent::Connected(ref net, id, server, ref tcp) => {
+                    let net = data.find(net.as_slice());
+                    if conns[net].connected(id, server, tcp) {
+                        data.connected(net, conns[net].server());
+                        register(&data.networks[net], &writers[net]);
+                    }
+                },
+                ConnectionEvent::Disconnected(ref net, id) => {
+                    let net = data.find(net.as_slice());
+                    if conns[net].is_current(id) {
+                        data.disconnected(net);
+                        conns[net].next(tx.clone());
+                    }
+                },
+                ConnectionEvent::Jump(ref net, ref server, ref to) => {
+                    let net = data.find(net.as_slice());
+                    let conn = &mut conns[net];
+                    let next = match *server {
+                        Some(ref name) => conn.servers.iter().position(|s| {
+                            s.is(name.as_slice())
+                        }),
+                        None => Some((conn.current + 1) % conn.servers.len()),
+                    };
+                    let next = match next {
+                        Some(next) => next,
+                        None => {
+                            let reply = format!("I don't know a server \
+                                                 called {}.",
+                                                server.as_ref().unwrap());
+                            writers[net].msg(to.as_slice(), reply.as_slice());
+                            continue;
+                        },
+                    };
+                    // Say so while we're still connected to say it.
+                    let reply = format!("PRIVMSG {} :Jumping to {}.",
+                                        to, conn.servers[next]);
+                    let quit = "QUIT :Jumping servers".to_string();
+                    write(&mut data, conn, &reply);
+                    write(&mut data, conn, &quit);
+                    data.disconnected(net);
+                    conn.connect(next, tx.clone());
+                },
+                ConnectionEvent::Schedule(timer) => {
+                    data.scheduler.add(timer);
+                },
+                ConnectionEvent::Cancel(ref net, ref plugin, ref id) => {
+                    data.scheduler.cancel(plugin.as_slice(), id.as_slice(),
+                                          Some(net.as_slice()));
+                },
+                ConnectionEvent::Tick => {
+                    data.handle_tick(&writer);
+                },
+                ConnectionEvent::JobOutput(id, ref s) => {
+                    // Drop output from jobs that timed out.
+                    let net = match data.workers.network(id) {
+                        Some(network) => data.find(network),
+                        None => continue,
+                    };
+                    write(&mut data, &mut conns[net], s);
+                },
+                ConnectionEvent::JobDone(id) => {
+                    data.workers.finished(id);
+                },
+                ConnectionEvent::Publish(ref net, ref event) => {
+                    let net = data.find(net.as_slice());
+                    data.handle_event(event, &writers[net]);
                 },
-                ConnectionEvent::Received(ref s) => {
-                    data.handle_received(s, &writer);
+                ConnectionEvent::Control(ref net, ref control, ref to) => {
+                    let net = data.find(net.as_slice());
+                    data.handle_control(control, to.as_slice(),
+                                        &writers[net]);
                 },
-                ConnectionEvent::Quit => {
+                ConnectionEvent::Quit(ref msg) => {
+                    let quit = format!("QUIT :{}", msg);
+                    for conn in conns.iter_mut() {
+                        write(&mut data, conn, &quit);
+                    }
+                    data.workers.shutdown();
+                    data.shutdown_plugins();
                     break;
                 },
             }
         }
-        conn.close();
+        for conn in conns.iter_mut() {
+            conn.close();
+        }
         println!("Exiting irc writer");
     }
 }
+
+
+// Introduce ourselves to a network's server once we've got through to it.
+fn register(network: &Network, writer: &IrcWriter) {
+    // The password has to come before anything else.
+    match network.password {
+        Some(ref password) => writer.pass(password.as_slice()),
+        None => (),
+    }
+
+    // Ask for account tags, joins and notifications, so we know who's logged
+    // in to what, and message tags, so we know who's a bot. They're asked for
+    // apart, as a server turns down the whole request if it lacks any of them.
+    // Servers that don't know CAP just ignore it.
+    for cap in ["account-tag", "extended-join", "account-notify",
+                "message-tags"].iter() {
+        writer.output(format!("CAP REQ :{}", cap));
+    }
+
+    // Start with identifying
+    writer.identify(network.info.nick, network.info.descr);
+}
+
+// Write a line to a network, if it's connected.
+fn write(data: &mut IrcData, conn: &mut ServerConnection, s: &String) {
+    match conn.stream {
+        Some(ref mut stream) => data.handle_write(s, stream),
+        None => println!("Not connected to {}, dropping a line",
+                         conn.network),
+    }
+}
@@ -0,0 +1,267 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Limits
+//!
+//! Keeps anyone from flooding a channel through the bot. The dispatcher checks
+//! two things before a plugin runs a command.
+//!
+//! First, everyone gets a handful of commands in a short window. Going over it
+//! gets them ignored for a timeout, which doubles each time it happens again,
+//! and is forgotten after an hour of good behaviour.
+//!
+//! Second, a plugin can give a command a cooldown with `Plugin::cooldown`,
+//! during which the command can't be used again, either by the same person,
+//! in the same channel or by anyone at all:
+//!
+//! ```
+//! fn cooldown(&self, cmd: &str) -> Option<Cooldown> {
+//!     match cmd {
+//!         "excuse" => Some(Cooldown::per_channel(30)),
+//!         _ => None,
+//!     }
+//! }
+//! ```
+//!
+//! Either way the person held back is told once, and after that ignored until
+//! they can go again. Admins and owners aren't limited. People and channels
+//! are only ever the same one on the same network, so limits and cooldowns
+//! are kept per network, apart from global cooldowns.
+
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::{Occupied, Vacant};
+
+use irc::IrcCommand;
+
+
+// How many commands someone gets per window.
+static COMMANDS: uint = 5;
+
+// How long the window is, in seconds.
+static WINDOW: i64 = 30;
+
+// The first timeout for going over, in seconds. Each one after is doubled.
+static TIMEOUT: i64 = 30;
+
+// The longest timeout, in seconds.
+static MAX_TIMEOUT: i64 = 30 * 60;
+
+// How long without going over before timeouts start from scratch.
+static FORGIVE: i64 = 60 * 60;
+
+
+/// Who a cooldown holds back.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Scope {
+    // Just whoever used the command.
+    User,
+    // Everyone in the channel it was used in.
+    Channel,
+    // Everyone, everywhere, on every network.
+    Global,
+}
+
+
+/// How long a command can't be used for after it's been used.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Cooldown {
+    pub seconds: i64,
+    pub scope: Scope,
+}
+
+impl Cooldown {
+    pub fn per_user(seconds: i64) -> Cooldown {
+        Cooldown { seconds: seconds, scope: Scope::User }
+    }
+
+    pub fn per_channel(seconds: i64) -> Cooldown {
+        Cooldown { seconds: seconds, scope: Scope::Channel }
+    }
+
+    pub fn global(seconds: i64) -> Cooldown {
+        Cooldown { seconds: seconds, scope: Scope::Global }
+    }
+
+    /// What the cooldown is kept under, for a plugin's command on a
+    /// network.
+    pub fn key(&self, network: &str, plugin: &str,
+               cmd: &IrcCommand) -> String {
+        let scope = match self.scope {
+            Scope::User => who(network, cmd),
+            Scope::Channel => {
+                format!("{}/{}", network, cmd.channel.to_lowercase())
+            },
+            Scope::Global => String::new(),
+        };
+        format!("{}/{}/{}", plugin, cmd.name, scope)
+    }
+}
+
+
+/// Who sent a command on a network, as best we know: their account, or else
+/// their hostmask, or else their nick.
+pub fn who(network: &str, cmd: &IrcCommand) -> String {
+    let account = cmd.notes.get(&"account".to_string());
+    let hostmask = cmd.notes.get(&"hostmask".to_string());
+    let who = match account.or(hostmask) {
+        Some(who) => who.as_slice().to_lowercase(),
+        None => cmd.sender.to_lowercase(),
+    };
+    format!("{}/{}", network, who)
+}
+
+
+// The timeout after this many strikes, doubling until it reaches the longest.
+fn timeout_after(strikes: uint) -> i64 {
+    let mut timeout = TIMEOUT;
+    for _ in range(0, strikes) {
+        if timeout >= MAX_TIMEOUT {
+            break;
+        }
+        timeout *= 2;
+    }
+    min(timeout, MAX_TIMEOUT)
+}
+
+
+/// Whether something may go ahead.
+#[deriving(PartialEq, Show)]
+pub enum Limit {
+    Allowed,
+    // Held back for this many more seconds, and should be told.
+    Throttled(i64),
+    // Held back, and has already been told.
+    Quiet,
+}
+
+
+// How someone's been using commands.
+struct Usage {
+    window_start: i64,
+    count: uint,
+    strikes: uint,
+    last_strike: i64,
+    until: i64,
+    told: bool,
+}
+
+
+// A command cooling down, and who's been told so.
+struct Cooling {
+    until: i64,
+    told: HashSet<String>,
+}
+
+
+/// Tracks command use for the dispatcher.
+pub struct Limiter {
+    users: HashMap<String, Usage>,
+    cooldowns: HashMap<String, Cooling>,
+}
+
+impl Limiter {
+    pub fn new() -> Limiter {
+        Limiter {
+            users: HashMap::new(),
+            cooldowns: HashMap::new(),
+        }
+    }
+
+    /// Count a command from someone, and say whether they're over the limit.
+    pub fn throttle(&mut self, who: &str, at: i64) -> Limit {
+        let usage = match self.users.entry(who.to_string()) {
+            Vacant(entry) => entry.set(Usage {
+                window_start: at,
+                count: 0,
+                strikes: 0,
+                last_strike: 0,
+                until: 0,
+                told: false,
+            }),
+            Occupied(entry) => entry.into_mut(),
+        };
+        if at < usage.until {
+            if usage.told {
+                return Limit::Quiet;
+            }
+            usage.told = true;
+            return Limit::Throttled(usage.until - at);
+        }
+        if at - usage.window_start >= WINDOW {
+            usage.window_start = at;
+            usage.count = 0;
+        }
+        if usage.strikes > 0 && at - usage.last_strike >= FORGIVE {
+            usage.strikes = 0;
+        }
+        usage.count += 1;
+        if usage.count <= COMMANDS {
+            return Limit::Allowed;
+        }
+        let timeout = timeout_after(usage.strikes);
+        usage.strikes += 1;
+        usage.last_strike = at;
+        usage.until = at + timeout;
+        usage.count = 0;
+        usage.told = true;
+        println!("Throttling {} for {}s", who, timeout);
+        Limit::Throttled(timeout)
+    }
+
+    /// Say whether a command is cooling down for someone.
+    pub fn cooling(&mut self, key: &str, who: &str, at: i64) -> Limit {
+        let c = match self.cooldowns.get_mut(&key.to_string()) {
+            Some(c) => c,
+            None => return Limit::Allowed,
+        };
+        if at >= c.until {
+            Limit::Allowed
+        } else if c.told.insert(who.to_string()) {
+            Limit::Throttled(c.until - at)
+        } else {
+            Limit::Quiet
+        }
+    }
+
+    /// Start a command's cooldown, once it's been used.
+    pub fn used(&mut self, key: &str, cooldown: &Cooldown, at: i64) {
+        self.cooldowns.insert(key.to_string(), Cooling {
+            until: at + cooldown.seconds,
+            told: HashSet::new(),
+        });
+    }
+
+    /// Forget about anything that no longer holds anyone back.
+    pub fn expire(&mut self, at: i64) {
+        let cooled: Vec<String> = self.cooldowns.iter().filter(|&(_, c)| {
+            at >= c.until
+        }).map(|(k, _)| k.clone()).collect();
+        for key in cooled.iter() {
+            self.cooldowns.remove(key);
+        }
+
+        let idle: Vec<String> = self.users.iter().filter(|&(_, u)| {
+            at >= u.until && at - u.window_start >= WINDOW
+                && (u.strikes == 0 || at - u.last_strike >= FORGIVE)
+        }).map(|(k, _)| k.clone()).collect();
+        for key in idle.iter() {
+            self.users.remove(key);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::uint;
+    use super::{timeout_after, TIMEOUT, MAX_TIMEOUT};
+
+    #[test]
+    fn timeouts_double_up_to_the_longest() {
+        assert_eq!(timeout_after(0), TIMEOUT);
+        assert_eq!(timeout_after(1), TIMEOUT * 2);
+        assert_eq!(timeout_after(100), MAX_TIMEOUT);
+        assert_eq!(timeout_after(uint::MAX), MAX_TIMEOUT);
+    }
+}
@@ -0,0 +1,392 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Loader
+//!
+//! Loads plugins built as separate shared libraries, so adding a plugin
+//! doesn't mean recompiling the whole bot. Every library in the configured
+//! `plugin_dir` is opened on startup and wrapped in a `DynamicPlugin`, which
+//! implements the usual `Plugin` trait and is registered like any other.
+//!
+//! Rust trait objects don't have a stable layout between compiler versions, so
+//! libraries talk to the bot over a plain C interface instead. A library
+//! exports a single function,
+//!
+//! ```
+//! #[no_mangle]
+//! pub extern "C" fn cleese_plugin_init() -> *const CleesePlugin
+//! ```
+//!
+//! returning a table with the ABI version it was built against, its name and
+//! help text, and the functions to call for commands and private messages.
+//! Those functions get a `CleeseHost`, which is how they reply. Libraries
+//! built against a different `ABI_VERSION` are refused.
+//!
+//! A library can be swapped out while the bot is running. Before the old one
+//! is unloaded its `save` function is asked for its state as a string, which
+//! is kept in storage and handed to the new one's `restore`. Libraries are
+//! loaded from a copy in the temp directory, so the original file can be
+//! rebuilt in place without pulling the code out from under the bot.
+
+use libc::{c_char, c_int, c_void, size_t};
+use std::c_str::{CString, ToCStr};
+use std::collections::HashMap;
+use std::dynamic_lib::DynamicLibrary;
+use std::io::fs;
+use std::mem;
+use std::os;
+use std::os::consts::DLL_EXTENSION;
+use std::sync::atomic::{AtomicUint, SeqCst, INIT_ATOMIC_UINT};
+
+use irc::{IrcPrivMsg, IrcWriter, IrcCommand, BotInfo, Plugin, Handler};
+use util::leak;
+
+
+/// Bumped whenever any of the structures below change.
+pub static ABI_VERSION: u32 = 2;
+
+/// Returned by a library's `cmd` or `privmsg` when it handled the input.
+pub static ACCEPTED: c_int = 1;
+
+/// Returned by a library's `cmd` or `privmsg` when it ignored the input.
+pub static PASSED: c_int = 0;
+
+/// Returned by a library's `cmd` or `privmsg` when it acted on the input but
+/// the next plugin should still see it.
+pub static OBSERVED: c_int = 2;
+
+
+/// What a plugin library describes itself with.
+#[repr(C)]
+pub struct CleesePlugin {
+    pub abi_version: u32,
+    pub name: *const c_char,
+    pub help: *const c_char,
+    /// Passed back as the first argument to every function below.
+    pub state: *mut c_void,
+    pub cmd: Option<extern "C" fn(*mut c_void, *const CleeseCommand,
+                                  *const CleeseHost) -> c_int>,
+    pub privmsg: Option<extern "C" fn(*mut c_void, *const CleeseMessage,
+                                      *const CleeseHost) -> c_int>,
+    /// Called before the plugin is unloaded. The returned string is the state
+    /// to hand to the next version, and must stay valid until `destroy`.
+    pub save: Option<extern "C" fn(*mut c_void) -> *const c_char>,
+    /// Called after the plugin is loaded with whatever the last version saved.
+    pub restore: Option<extern "C" fn(*mut c_void, *const c_char)>,
+    /// Called once when the plugin is unloaded.
+    pub destroy: Option<extern "C" fn(*mut c_void)>,
+}
+
+/// A command, as seen by a plugin library.
+#[repr(C)]
+pub struct CleeseCommand {
+    pub name: *const c_char,
+    pub args: *const *const c_char,
+    pub nargs: size_t,
+    pub channel: *const c_char,
+    pub sender: *const c_char,
+}
+
+/// A private message, as seen by a plugin library.
+#[repr(C)]
+pub struct CleeseMessage {
+    pub sender_nick: *const c_char,
+    pub sender_info: *const c_char,
+    pub channel: *const c_char,
+    pub txt: *const c_char,
+}
+
+/// What a plugin library replies through.
+#[repr(C)]
+pub struct CleeseHost {
+    pub ctx: *mut c_void,
+    pub bot_nick: *const c_char,
+    pub msg: extern "C" fn(*mut c_void, *const c_char, *const c_char),
+}
+
+
+/// A plugin loaded from a shared library.
+pub struct DynamicPlugin {
+    plugin: *const CleesePlugin,
+    name: &'static str,
+    help: &'static str,
+    path: Path,
+    // Kept last so the library is closed after everything else is dropped.
+    lib: DynamicLibrary,
+}
+
+impl DynamicPlugin {
+    /// Open the library at `path` and check that it's a plugin built for this
+    /// version of the bot.
+    pub fn open(path: &Path) -> Result<DynamicPlugin, String> {
+        let copy = try!(shadow_copy(path));
+        let lib = DynamicLibrary::open(Some(&copy));
+        // Once it's open the copy isn't needed. This fails harmlessly on
+        // systems that won't remove open files.
+        let _ = fs::unlink(&copy);
+        let lib = try!(lib);
+        let init: extern "C" fn() -> *const CleesePlugin = unsafe {
+            match lib.symbol::<u8>("cleese_plugin_init") {
+                Ok(f) => mem::transmute(f),
+                Err(e) => return Err(e),
+            }
+        };
+
+        let plugin = init();
+        if plugin.is_null() {
+            return Err("cleese_plugin_init returned nothing".to_string());
+        }
+        let p = unsafe { &*plugin };
+        if p.abi_version != ABI_VERSION {
+            return Err(format!("built for ABI version {}, but this is {}",
+                               p.abi_version, ABI_VERSION));
+        }
+
+        Ok(DynamicPlugin {
+            plugin: plugin,
+            // Plugin names and help have to outlive the plugin, as they end
+            // up in the command table. They're copied out of the library and
+            // leaked, so each reload costs a few bytes.
+            name: leak(from_c(p.name)),
+            help: leak(from_c(p.help)),
+            path: path.clone(),
+            lib: lib,
+        })
+    }
+
+    /// Where the plugin was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn table(&self) -> &CleesePlugin {
+        unsafe { &*self.plugin }
+    }
+}
+
+impl Drop for DynamicPlugin {
+    fn drop(&mut self) {
+        let p = self.table();
+        match p.destroy {
+            Some(f) => f(p.state),
+            None => (),
+        }
+    }
+}
+
+impl Plugin for DynamicPlugin {
+    /// Hand the library whatever state its last version saved.
+    fn startup(&mut self, _: &IrcWriter, info: &BotInfo) {
+        let p = self.table();
+        let f = match p.restore {
+            Some(f) => f,
+            None => return,
+        };
+        let state: Option<String> = info.storage.namespace(self.name())
+                                                .get("state");
+        match state {
+            Some(s) => f(p.state, s.to_c_str().as_ptr()),
+            None => (),
+        }
+    }
+
+    /// Ask the library for its state and keep it for the next version.
+    fn shutdown(&mut self, info: &BotInfo) {
+        let p = self.table();
+        let f = match p.save {
+            Some(f) => f,
+            None => return,
+        };
+        let state = from_c(f(p.state));
+        info.storage.namespace(self.name()).set("state", &state);
+    }
+
+    /// Hand private messages to the library, if it wants them.
+    fn privmsg(&mut self, msg: &IrcPrivMsg,
+               writer: &IrcWriter, info: &BotInfo) -> Handler {
+        let p = self.table();
+        let f = match p.privmsg {
+            Some(f) => f,
+            None => return Handler::Passed,
+        };
+        let sender_nick = msg.sender_nick.to_c_str();
+        let sender_info = msg.sender_info.to_c_str();
+        let channel = msg.channel.to_c_str();
+        let txt = msg.txt.to_c_str();
+        let m = CleeseMessage {
+            sender_nick: sender_nick.as_ptr(),
+            sender_info: sender_info.as_ptr(),
+            channel: channel.as_ptr(),
+            txt: txt.as_ptr(),
+        };
+        with_host(writer, info, |host| handler(f(p.state, &m, host)))
+    }
+
+    /// Hand commands to the library, if it wants them.
+    fn cmd(&mut self, cmd: &IrcCommand,
+           writer: &IrcWriter, info: &BotInfo) -> Handler {
+        let p = self.table();
+        let f = match p.cmd {
+            Some(f) => f,
+            None => return Handler::Passed,
+        };
+        let name = cmd.name.to_c_str();
+        let channel = cmd.channel.to_c_str();
+        let sender = cmd.sender.to_c_str();
+        let args: Vec<CString> = cmd.args.iter().map(|a| {
+            a.to_c_str()
+        }).collect();
+        let arg_ptrs: Vec<*const c_char> = args.iter().map(|a| {
+            a.as_ptr()
+        }).collect();
+        let c = CleeseCommand {
+            name: name.as_ptr(),
+            args: arg_ptrs.as_ptr(),
+            nargs: arg_ptrs.len() as size_t,
+            channel: channel.as_ptr(),
+            sender: sender.as_ptr(),
+        };
+        with_host(writer, info, |host| handler(f(p.state, &c, host)))
+    }
+
+    /// Return the library's help text.
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    /// Return the library's plugin name.
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+
+/// Remembers where each library plugin came from, so it can be reloaded, and
+/// when its file last changed, so it can be reloaded automatically.
+pub struct Reloader {
+    libs: HashMap<String, (Path, u64)>,
+    /// Reload plugins as soon as their files change.
+    pub watch: bool,
+}
+
+impl Reloader {
+    /// Construct a reloader that isn't tracking anything yet.
+    pub fn new(watch: bool) -> Reloader {
+        Reloader {
+            libs: HashMap::new(),
+            watch: watch,
+        }
+    }
+
+    /// Start tracking a plugin loaded from `path`.
+    pub fn track(&mut self, name: &str, path: &Path) {
+        self.libs.insert(name.to_string(), (path.clone(), modified(path)));
+    }
+
+    /// Where the named plugin was loaded from, if it came from a library.
+    pub fn path(&self, name: &str) -> Option<Path> {
+        self.libs.get(&name.to_string()).map(|&(ref path, _)| path.clone())
+    }
+
+    /// Note the current modification time of a plugin's file, whether or not
+    /// reloading it worked, so a broken build isn't retried every second.
+    pub fn seen(&mut self, name: &str) {
+        match self.libs.get_mut(&name.to_string()) {
+            Some(&(ref path, ref mut time)) => *time = modified(path),
+            None => (),
+        }
+    }
+
+    /// The plugins whose files have changed since they were last seen.
+    pub fn changed(&self) -> Vec<String> {
+        self.libs.iter().filter(|&(_, &(ref path, time))| {
+            modified(path) != time
+        }).map(|(name, _)| name.clone()).collect()
+    }
+}
+
+
+/// Load every plugin library in `dir`. Libraries that fail to load are
+/// reported and skipped.
+pub fn load_dir(dir: &Path) -> Vec<DynamicPlugin> {
+    let paths = match fs::readdir(dir) {
+        Ok(paths) => paths,
+        Err(e) => {
+            println!("Couldn't read plugin directory {}: {}", dir.display(), e);
+            return Vec::new();
+        }
+    };
+
+    let mut plugins = Vec::new();
+    for path in paths.iter() {
+        if path.extension_str() != Some(DLL_EXTENSION) {
+            continue;
+        }
+        match DynamicPlugin::open(path) {
+            Ok(p) => {
+                println!("Loaded plugin {} from {}", p.name, path.display());
+                plugins.push(p);
+            },
+            Err(e) => println!("Couldn't load {}: {}", path.display(), e),
+        }
+    }
+    plugins
+}
+
+
+/// When a file was last modified, or 0 if it can't be read.
+fn modified(path: &Path) -> u64 {
+    match fs::stat(path) {
+        Ok(stat) => stat.modified,
+        Err(_) => 0,
+    }
+}
+
+/// Copy a library into the temp directory and return where it went. Each
+/// copy gets a new name, as the dynamic loader won't open the same file twice.
+fn shadow_copy(path: &Path) -> Result<Path, String> {
+    static COPIES: AtomicUint = INIT_ATOMIC_UINT;
+    let stem = path.filestem_str().unwrap_or("plugin");
+    let name = format!("cleese-{}-{}-{}.{}", stem, os::getpid(),
+                       COPIES.fetch_add(1, SeqCst), DLL_EXTENSION);
+    let copy = os::tmpdir().join(name);
+    match fs::copy(path, &copy) {
+        Ok(()) => Ok(copy),
+        Err(e) => Err(format!("couldn't copy library: {}", e)),
+    }
+}
+
+/// Build a host for the library to reply through, and run `f` with it.
+fn with_host<T>(writer: &IrcWriter, info: &BotInfo,
+                f: |&CleeseHost| -> T) -> T {
+    let nick = info.nick.to_c_str();
+    let host = CleeseHost {
+        ctx: writer as *const IrcWriter as *mut c_void,
+        bot_nick: nick.as_ptr(),
+        msg: host_msg,
+    };
+    f(&host)
+}
+
+/// Lets a library send a PRIVMSG.
+extern "C" fn host_msg(ctx: *mut c_void, target: *const c_char,
+                       text: *const c_char) {
+    let writer = unsafe { &*(ctx as *const IrcWriter) };
+    writer.msg(from_c(target).as_slice(), from_c(text).as_slice());
+}
+
+fn handler(result: c_int) -> Handler {
+    match result {
+        r if r == ACCEPTED => Handler::Accepted,
+        r if r == OBSERVED => Handler::Observed,
+        _ => Handler::Passed,
+    }
+}
+
+/// Copy a C string owned by someone else.
+fn from_c(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    unsafe { CString::new(s, false) }.as_str().unwrap_or("").to_string()
+}
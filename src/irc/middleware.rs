@@ -0,0 +1,242 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Middleware
+//!
+//! Everything received from the server passes through a chain of middleware
+//! before it reaches the plugins. Each layer sees the message in turn and can
+//! look at it, change it, add notes to it for the layers and plugins after it,
+//! or stop it from going any further. This is where anything that applies to
+//! every message belongs: logging, ignore lists, rate limits and permission
+//! checks.
+//!
+//! Answering PINGs, joining channels and OPERing up once connected, logging
+//! what comes in, working out who people are, ignoring people and taking up
+//! invites are all middleware too, and always come first in the chain. More
+//! layers are added with `Irc::register_middleware`, and run in the order they
+//! were registered.
+//!
+//! Notes are plain strings kept on the message, and carried over to the
+//! `IrcPrivMsg` and `IrcCommand` the plugins get:
+//!
+//! ```
+//! msg.notes.insert("account".to_string(), account);
+//! ```
+
+use std::collections::HashSet;
+
+use irc::{IrcMsg, IrcWriter, BotInfo};
+use irc::command::Command;
+use irc::policy::remembered;
+
+
+/// Whether a message should carry on down the chain.
+#[deriving(PartialEq, Show)]
+pub enum Flow {
+    Continue,
+    Stop,
+}
+
+
+/// A layer in the middleware chain.
+pub trait Middleware {
+    /// Handle a message on its way in. Returning `Flow::Stop` drops it, so
+    /// no later layer or plugin sees it.
+    fn incoming(&mut self, msg: &mut IrcMsg,
+                writer: &IrcWriter, info: &BotInfo) -> Flow;
+
+    /// Called when the network gets through to a server, before anything is
+    /// received from it. Anything learned from the last connection, like who
+    /// people are or what the server has agreed to, should be forgotten here.
+    /// Only a network's own layers are told.
+    fn connected(&mut self) {}
+
+    /// Called once the plugins have answered a message that got through the
+    /// whole chain, whether with a reply or by doing something. Does nothing
+    /// by default.
+    fn answered(&mut self, _msg: &IrcMsg) {}
+
+    /// Whether this layer keeps anything out, like people who are ignored.
+    /// If it panics, the message it was looking at is dropped rather than let
+    /// past it. False by default.
+    fn guards(&self) -> bool { false }
+
+    /// Provide the middleware name, used when logging panics.
+    fn name(&self) -> &'static str;
+}
+
+
+/// Prints everything received, except for the codes in the input blacklist.
+/// Channel keys are starred out, whether they're in an admin's `join` command
+/// or the server telling us a channel's modes.
+pub struct Log {
+    blacklist: HashSet<String>,
+}
+
+impl Log {
+    /// Construct the logger with the codes it shouldn't print.
+    pub fn new(blacklist: &Vec<&str>) -> Log {
+        Log {
+            blacklist: blacklist.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+}
+
+impl Middleware for Log {
+    fn incoming(&mut self, msg: &mut IrcMsg,
+                _: &IrcWriter, info: &BotInfo) -> Flow {
+        if !self.blacklist.contains(&msg.code) {
+            println!("< {}", hide_keys(msg, info));
+        }
+        Flow::Continue
+    }
+
+    fn name(&self) -> &'static str { "log" }
+}
+
+// The line as received, with any channel keys in it starred out.
+fn hide_keys(msg: &IrcMsg, info: &BotInfo) -> String {
+    let params = msg.params();
+    let orig = msg.orig.clone();
+    match msg.code.as_slice() {
+        // :nick!user@host PRIVMSG #chan :cleese join #chan key
+        "PRIVMSG" => {
+            let text = match params.get(1) {
+                Some(text) => *text,
+                None => return orig,
+            };
+            let chan = match Command::new(text, info.cmd_prefix) {
+                Some(ref cmd) if cmd.name == "join" && cmd.args.len() >= 2 => {
+                    cmd.args[0]
+                },
+                _ => return orig,
+            };
+            if !orig.as_slice().ends_with(text) {
+                return orig;
+            }
+            let head = orig.as_slice().slice_to(orig.len() - text.len());
+            format!("{}{}join {} ***", head, info.cmd_prefix, chan)
+        },
+        // :nick!user@host MODE #chan +k key
+        // :server 324 me #chan +kl key 10
+        "MODE" | "324" => {
+            let at = if msg.code.as_slice() == "MODE" { 1 } else { 2 };
+            let changes = match params.get(at) {
+                Some(changes) => *changes,
+                None => return orig,
+            };
+            let mut shown: Vec<&str> = params.clone();
+            let mut arg = at + 1;
+            let mut adding = true;
+            let mut hidden = false;
+            for m in changes.chars() {
+                match m {
+                    '+' => adding = true,
+                    '-' => adding = false,
+                    m if info.state.takes_arg(m, adding) => {
+                        if m == 'k' && arg < shown.len() {
+                            shown[arg] = "***";
+                            hidden = true;
+                        }
+                        arg += 1;
+                    },
+                    _ => (),
+                }
+            }
+            if !hidden {
+                return orig;
+            }
+            format!("{} {} {}", msg.prefix, msg.code, shown.connect(" "))
+        },
+        _ => orig,
+    }
+}
+
+
+/// Answers the server's PINGs.
+pub struct Pong;
+
+impl Middleware for Pong {
+    fn incoming(&mut self, msg: &mut IrcMsg,
+                writer: &IrcWriter, _: &BotInfo) -> Flow {
+        if msg.code.as_slice() != "PING" {
+            return Flow::Continue;
+        }
+        writer.output(format!("PONG {}", msg.param));
+        Flow::Stop
+    }
+
+    fn name(&self) -> &'static str { "pong" }
+}
+
+
+/// Joins the configured channels, and the ones joined while running before,
+/// once the server has welcomed us.
+pub struct AutoJoin;
+
+impl Middleware for AutoJoin {
+    fn incoming(&mut self, msg: &mut IrcMsg,
+                writer: &IrcWriter, info: &BotInfo) -> Flow {
+        // Key 004, should be fine as it's usually in the beginning I believe?
+        if msg.code.as_slice() == "004" {
+            for chan in info.channels.iter() {
+                let key = info.key(*chan);
+                writer.join_keyed(*chan, key.as_ref().map(|k| k.as_slice()));
+            }
+            for chan in remembered(&info.storage, info.network).iter() {
+                let configured = info.channels.iter().any(|c| {
+                    c.to_lowercase() == *chan
+                });
+                if !configured {
+                    let key = info.key(chan.as_slice());
+                    writer.join_keyed(chan.as_slice(),
+                                      key.as_ref().map(|k| k.as_slice()));
+                }
+            }
+        }
+        Flow::Continue
+    }
+
+    fn name(&self) -> &'static str { "autojoin" }
+}
+
+
+/// OPERs up and sets the bot's user modes from the config once the server has
+/// welcomed us.
+pub struct Oper {
+    oper: Option<(String, String)>,
+    umodes: Option<String>,
+}
+
+impl Oper {
+    /// Construct the middleware with the oper name and password, and the
+    /// user modes to set.
+    pub fn new(oper: Option<(&str, &str)>, umodes: Option<&str>) -> Oper {
+        Oper {
+            oper: oper.map(|(name, pass)| (name.to_string(), pass.to_string())),
+            umodes: umodes.map(|m| m.to_string()),
+        }
+    }
+}
+
+impl Middleware for Oper {
+    fn incoming(&mut self, msg: &mut IrcMsg,
+                writer: &IrcWriter, info: &BotInfo) -> Flow {
+        if msg.code.as_slice() != "001" {
+            return Flow::Continue;
+        }
+        match self.oper {
+            Some((ref name, ref pass)) => {
+                writer.oper(name.as_slice(), pass.as_slice())
+            },
+            None => (),
+        }
+        match self.umodes {
+            Some(ref modes) => writer.mode(info.state.nick(), modes.as_slice()),
+            None => (),
+        }
+        Flow::Continue
+    }
+
+    fn name(&self) -> &'static str { "oper" }
+}
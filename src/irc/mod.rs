@@ -8,8 +8,9 @@
 #![macro_escape]
 
 // We can reexport what we want to show from this module.
-pub use irc::config::IrcConfig;
-pub use irc::connection::{ConnectionEvent, ServerConnection};
+pub use irc::config::{IrcConfig, NetworkConf};
+pub use irc::connection::{ConnectionEvent, ServerConnection, Control};
+pub use irc::connection::Server;
 pub use irc::msg::IrcMsg;
 pub use irc::privmsg::IrcPrivMsg;
 pub use irc::writer::IrcWriter;
@@ -18,6 +19,20 @@ pub use irc::command::{IrcCommand, Command};
 pub use irc::irc::Irc;
 pub use irc::plugin::{Plugin, Handler};
 pub use irc::config::JsonConfig;
+pub use irc::scheduler::{Timer, Schedule};
+pub use irc::worker::{Job, JobContext};
+pub use irc::storage::{Storage, Namespace, Transaction};
+pub use irc::bus::{Event, Delivery};
+pub use irc::loader::DynamicPlugin;
+pub use irc::process::ProcessPlugin;
+pub use irc::middleware::{Middleware, Flow};
+pub use irc::outbound::Filter;
+pub use irc::auth::{Role, Grant, identifies};
+pub use irc::ignore::IgnoreRule;
+pub use irc::limits::{Cooldown, Scope};
+pub use irc::channels::{ChannelState, Channel, Member, Departure};
+pub use irc::bans::{Ban, BAN, QUIET, ban_mask, parse_duration, format_seconds};
+pub use irc::policy::{Invites, Rejoin, remember_key};
 
 mod config;
 mod connection;
@@ -29,3 +44,19 @@ mod command;
 mod data;
 mod irc;
 mod plugin;
+mod scheduler;
+mod supervisor;
+mod worker;
+mod storage;
+mod bus;
+mod loader;
+mod process;
+mod middleware;
+mod outbound;
+mod auth;
+mod ignore;
+mod limits;
+mod channels;
+mod bans;
+mod policy;
+mod network;
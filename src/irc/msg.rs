@@ -2,32 +2,75 @@
 // project's directory structure.
 
 use core::fmt::{Show, Formatter, Result};
+use std::collections::HashMap;
 
 // A regular irc message sent from the server.
+// Tags are IRCv3 message tags, like `account`, and notes are added by
+// middleware on the way in.
 pub struct IrcMsg {
     pub orig: String,
+    pub tags: HashMap<String, String>,
     pub prefix: String,
     pub code: String,
     pub param: String,
+    pub notes: HashMap<String, String>,
 }
 
 impl IrcMsg {
     pub fn new(s: &str) -> Option<IrcMsg> {
+        let orig = s;
+        let (tags, s) = match s.starts_with("@") {
+            true => match s.find(' ') {
+                Some(i) => (parse_tags(s.slice(1, i)), s.slice_from(i + 1)),
+                None => return None,
+            },
+            false => (HashMap::new(), s),
+        };
         let re = regex!(r"^(:\S+)?\s*(\S+)\s+(.*)\r?$");
         let caps = re.captures(s);
         match caps {
             Some(x) => {
                 Some(IrcMsg {
-                    orig: s.to_string(),
+                    orig: orig.to_string(),
+                    tags: tags,
                     prefix: x.at(1).to_string(),
                     code: x.at(2).to_string(),
                     param: x.at(3).to_string(),
+                    notes: HashMap::new(),
                 })
             },
             None => None
         }
     }
 
+    // Split the parameters into words, keeping everything after a `:` as the
+    // last one.
+    pub fn params(&self) -> Vec<&str> {
+        let mut params = Vec::new();
+        let mut rest = self.param.as_slice();
+        loop {
+            rest = rest.trim_left_chars(' ');
+            if rest.is_empty() {
+                break;
+            }
+            if rest.starts_with(":") {
+                params.push(rest.slice_from(1));
+                break;
+            }
+            match rest.find(' ') {
+                Some(i) => {
+                    params.push(rest.slice_to(i));
+                    rest = rest.slice_from(i);
+                },
+                None => {
+                    params.push(rest);
+                    break;
+                },
+            }
+        }
+        params
+    }
+
     // Fetch nick + sender info
     pub fn match_sender(&self) -> Option<(String, String)> {
         let re = regex!(r":([^!]+)(?:!(.+))?");
@@ -49,9 +92,71 @@ impl IrcMsg {
     }
 }
 
+// Split `a=1;b` into a map. Tags without a value map to an empty string.
+fn parse_tags(s: &str) -> HashMap<String, String> {
+    let mut tags = HashMap::new();
+    for tag in s.split(';') {
+        match tag.find('=') {
+            Some(i) => {
+                tags.insert(tag.slice_to(i).to_string(),
+                            unescape_tag(tag.slice_from(i + 1)));
+            },
+            None => { tags.insert(tag.to_string(), String::new()); },
+        }
+    }
+    tags
+}
+
+// Undo the escaping IRCv3 uses in tag values.
+fn unescape_tag(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    loop {
+        match chars.next() {
+            Some('\\') => match chars.next() {
+                Some(':') => out.push(';'),
+                Some('s') => out.push(' '),
+                Some('r') => out.push('\r'),
+                Some('n') => out.push('\n'),
+                Some(c) => out.push(c),
+                None => break,
+            },
+            Some(c) => out.push(c),
+            None => break,
+        }
+    }
+    out
+}
+
 impl Show for IrcMsg {
     fn fmt(&self, f: &mut Formatter) -> Result {
         write!(f, "prefix: {} code: {} param: {}",
                self.prefix, self.code, self.param)
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::{IrcMsg, unescape_tag};
+
+    #[test]
+    fn unescapes_tags() {
+        assert_eq!(unescape_tag(r"a\sb\:c"), "a b;c".to_string());
+        assert_eq!(unescape_tag(r"\r\n"), "\r\n".to_string());
+        assert_eq!(unescape_tag(r"\\x\y"), r"\xy".to_string());
+        // A trailing backslash is dropped.
+        assert_eq!(unescape_tag(r"end\"), "end".to_string());
+    }
+
+    #[test]
+    fn parses_tags() {
+        let line = r"@account=alice;bot;msg=hi\sthere :n!u@h PRIVMSG #c :hi";
+        let msg = IrcMsg::new(line).unwrap();
+        assert_eq!(msg.tags["account".to_string()], "alice".to_string());
+        assert_eq!(msg.tags["bot".to_string()], "".to_string());
+        assert_eq!(msg.tags["msg".to_string()], "hi there".to_string());
+        assert_eq!(msg.code, "PRIVMSG".to_string());
+        assert_eq!(msg.params(), vec!["#c", "hi"]);
+    }
+}
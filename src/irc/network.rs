@@ -0,0 +1,61 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Network
+//!
+//! What the bot keeps for each network it's on: the `BotInfo` plugins see,
+//! the built-in middleware, which keeps track of people separately on each
+//! network, and when to rejoin the channels it was kicked from. Plugins,
+//! timers, storage and the rest of the core are shared by every network.
+
+use irc::config::{IrcConfig, NetworkConf};
+use irc::info::BotInfo;
+use irc::storage::Storage;
+use irc::middleware::{Middleware, Log, Pong, AutoJoin, Oper};
+use irc::auth::Identify;
+use irc::ignore::Ignore;
+use irc::policy::Rejoin;
+
+
+/// One network the bot is on.
+pub struct Network<'a> {
+    // What plugins get to see, including the network's name.
+    pub info: BotInfo<'a>,
+
+    // Everything received on this network goes through here first, before
+    // the middleware shared by every network.
+    pub middleware: Vec<Box<Middleware + 'a>>,
+
+    // When to rejoin channels we were kicked from.
+    pub rejoin: Rejoin,
+
+    // Sent with PASS when connecting, if the server needs it.
+    pub password: Option<String>,
+}
+
+impl<'a> Network<'a> {
+    /// Set up a network from its part of the config.
+    pub fn new<'b>(conf: &IrcConfig<'b>, net: &NetworkConf<'b>,
+                   storage: Storage) -> Network<'b> {
+        Network {
+            info: BotInfo::new(conf, net, storage),
+            middleware: vec![box Log::new(&conf.in_blacklist)
+                                 as Box<Middleware + 'b>,
+                             box Pong as Box<Middleware + 'b>,
+                             box AutoJoin as Box<Middleware + 'b>,
+                             box Oper::new(net.oper, net.umodes)
+                                 as Box<Middleware + 'b>,
+                             box Identify::new() as Box<Middleware + 'b>,
+                             box Ignore::new() as Box<Middleware + 'b>,
+                             box conf.invites.clone()
+                                 as Box<Middleware + 'b>],
+            rejoin: conf.rejoin.clone(),
+            password: net.password.map(|p| p.to_string()),
+        }
+    }
+
+    /// The network's name.
+    pub fn name(&self) -> &'a str {
+        self.info.network
+    }
+}
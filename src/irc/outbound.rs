@@ -0,0 +1,348 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Outbound
+//!
+//! Everything the bot sends passes through a chain of filters just before it's
+//! written to the server. Each filter gets the raw line, like `PRIVMSG #chan
+//! :hello`, and can change it or drop it. Filters run in the order they were
+//! added, and a dropped line goes no further.
+//!
+//! The filters below are set up from `outbound` in the config:
+//!
+//! ```
+//! "outbound": {
+//!     "redact": ["hunter2", "token=\\S+"],
+//!     "censor": ["darn"],
+//!     "strip_formatting": ["#plain"],
+//!     "block": ["ChanServ"],
+//!     "drop_duplicates": true
+//! }
+//! ```
+//!
+//! Plugins can add their own with `Irc::register_filter`.
+
+use std::collections::{HashMap, HashSet, RingBuf};
+use regex::{Regex, Captures, NoExpand, quote};
+
+
+/// Something that looks at lines on their way out.
+pub trait Filter {
+    /// Return the line to send, changed or not, or None to drop it.
+    fn outgoing(&mut self, line: String) -> Option<String>;
+
+    /// Provide the filter name.
+    fn name(&self) -> &'static str;
+}
+
+
+/// The `outbound` section of the config file.
+#[deriving(Decodable)]
+pub struct OutboundConfig {
+    pub redact: Option<Vec<String>>,
+    pub censor: Option<Vec<String>>,
+    pub strip_formatting: Option<Vec<String>>,
+    pub block: Option<Vec<String>>,
+    pub drop_duplicates: Option<bool>,
+}
+
+impl OutboundConfig {
+    /// Build the filters the config asks for, in the order they should run.
+    pub fn filters(&self) -> Vec<Box<Filter>> {
+        let mut filters = Vec::new();
+        match self.block {
+            Some(ref targets) => {
+                filters.push(box Block::new(targets) as Box<Filter>)
+            },
+            None => (),
+        }
+        match self.redact {
+            Some(ref patterns) => {
+                filters.push(box Redact::new(patterns) as Box<Filter>)
+            },
+            None => (),
+        }
+        match self.censor {
+            Some(ref words) if !words.is_empty() => {
+                filters.push(box Censor::new(words) as Box<Filter>)
+            },
+            _ => (),
+        }
+        match self.strip_formatting {
+            Some(ref channels) => {
+                let strip = StripFormatting::new(channels);
+                filters.push(box strip as Box<Filter>)
+            },
+            None => (),
+        }
+        if self.drop_duplicates.unwrap_or(false) {
+            filters.push(box DropDuplicates::new() as Box<Filter>);
+        }
+        filters
+    }
+}
+
+
+/// Split a PRIVMSG or NOTICE into its command, target and text.
+pub fn split_message(line: &str) -> Option<(&str, &str, &str)> {
+    let re = regex!(r"^(PRIVMSG|NOTICE) (\S+) :(.*)$");
+    match re.captures(line) {
+        Some(caps) => Some((caps.at(1), caps.at(2), caps.at(3))),
+        None => None,
+    }
+}
+
+/// Run `f` over the text of a PRIVMSG or NOTICE, leaving other lines alone.
+fn map_text(line: String, f: |&str, &str| -> String) -> String {
+    let changed = match split_message(line.as_slice()) {
+        Some((cmd, target, text)) => {
+            Some(format!("{} {} :{}", cmd, target, f(target, text)))
+        },
+        None => None,
+    };
+    changed.unwrap_or(line)
+}
+
+
+/// Refuses to message certain nicks or channels.
+pub struct Block {
+    targets: HashSet<String>,
+}
+
+impl Block {
+    pub fn new(targets: &Vec<String>) -> Block {
+        Block {
+            targets: targets.iter().map(|t| t.as_slice().to_lowercase())
+                                   .collect(),
+        }
+    }
+}
+
+impl Filter for Block {
+    fn outgoing(&mut self, line: String) -> Option<String> {
+        let blocked = match split_message(line.as_slice()) {
+            Some((_, target, _)) => {
+                self.targets.contains(&target.to_lowercase())
+            },
+            None => false,
+        };
+        if blocked { None } else { Some(line) }
+    }
+
+    fn name(&self) -> &'static str { "block" }
+}
+
+
+/// Replaces anything in a message matching one of a set of regexes, like
+/// passwords or tokens, with `[redacted]`. Other lines are left alone, so the
+/// bot can still log in and join keyed channels with a password that's also
+/// kept out of its messages.
+pub struct Redact {
+    patterns: Vec<Regex>,
+}
+
+impl Redact {
+    pub fn new(patterns: &Vec<String>) -> Redact {
+        Redact {
+            patterns: patterns.iter().map(|p| {
+                match Regex::new(p.as_slice()) {
+                    Ok(re) => re,
+                    Err(err) => panic!("{}", err),
+                }
+            }).collect(),
+        }
+    }
+}
+
+impl Filter for Redact {
+    fn outgoing(&mut self, line: String) -> Option<String> {
+        let patterns = &self.patterns;
+        Some(map_text(line, |_, text| {
+            let mut text = text.to_string();
+            for re in patterns.iter() {
+                text = re.replace_all(text.as_slice(), NoExpand("[redacted]"));
+            }
+            text
+        }))
+    }
+
+    fn name(&self) -> &'static str { "redact" }
+}
+
+
+/// Stars out whole words, ignoring case.
+pub struct Censor {
+    words: Regex,
+}
+
+impl Censor {
+    pub fn new(words: &Vec<String>) -> Censor {
+        let words: Vec<String> = words.iter().map(|w| {
+            quote(w.as_slice())
+        }).collect();
+        let pattern = format!(r"(?i)\b({})\b", words.connect("|"));
+        Censor {
+            words: Regex::new(pattern.as_slice()).unwrap(),
+        }
+    }
+}
+
+impl Filter for Censor {
+    fn outgoing(&mut self, line: String) -> Option<String> {
+        let words = &self.words;
+        Some(map_text(line, |_, text| {
+            words.replace_all(text, |caps: &Captures| {
+                String::from_char(caps.at(0).char_len(), '*')
+            })
+        }))
+    }
+
+    fn name(&self) -> &'static str { "censor" }
+}
+
+
+/// Removes bold, colours and other formatting from messages to certain
+/// channels.
+pub struct StripFormatting {
+    channels: HashSet<String>,
+}
+
+impl StripFormatting {
+    pub fn new(channels: &Vec<String>) -> StripFormatting {
+        StripFormatting {
+            channels: channels.iter().map(|c| c.as_slice().to_lowercase())
+                                     .collect(),
+        }
+    }
+}
+
+impl Filter for StripFormatting {
+    fn outgoing(&mut self, line: String) -> Option<String> {
+        let channels = &self.channels;
+        Some(map_text(line, |target, text| {
+            if !channels.contains(&target.to_lowercase()) {
+                return text.to_string();
+            }
+            let re =
+                regex!(r"\x03(\d{1,2}(,\d{1,2})?)?|[\x02\x0f\x16\x1d\x1f]");
+            re.replace_all(text, NoExpand(""))
+        }))
+    }
+
+    fn name(&self) -> &'static str { "strip_formatting" }
+}
+
+
+// How many targets `DropDuplicates` remembers the last message to.
+static DUPLICATE_TARGETS: uint = 500;
+
+
+/// Drops a message if it's the same as the last one sent to the same target.
+/// Only the targets messaged most recently are remembered.
+pub struct DropDuplicates {
+    last: HashMap<String, String>,
+    // Targets in the order they were first remembered, oldest first.
+    order: RingBuf<String>,
+}
+
+impl DropDuplicates {
+    pub fn new() -> DropDuplicates {
+        DropDuplicates { last: HashMap::new(), order: RingBuf::new() }
+    }
+}
+
+impl Filter for DropDuplicates {
+    fn outgoing(&mut self, line: String) -> Option<String> {
+        let (target, text) = match split_message(line.as_slice()) {
+            Some((_, target, text)) => (target.to_string(), text.to_string()),
+            None => return Some(line),
+        };
+        if self.last.get(&target) == Some(&text) {
+            return None;
+        }
+        if !self.last.contains_key(&target) {
+            if self.order.len() == DUPLICATE_TARGETS {
+                match self.order.pop_front() {
+                    Some(oldest) => { self.last.remove(&oldest); },
+                    None => (),
+                }
+            }
+            self.order.push_back(target.clone());
+        }
+        self.last.insert(target, text);
+        Some(line)
+    }
+
+    fn name(&self) -> &'static str { "drop_duplicates" }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Filter, Block, Redact, Censor, StripFormatting,
+                DropDuplicates, DUPLICATE_TARGETS};
+
+    fn send(filter: &mut Filter, line: &str) -> Option<String> {
+        filter.outgoing(line.to_string())
+    }
+
+    fn strings(v: Vec<&str>) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn blocks_targets() {
+        let mut f = Block::new(&strings(vec!["ChanServ"]));
+        assert_eq!(send(&mut f, "PRIVMSG chanserv :op"), None);
+        assert!(send(&mut f, "PRIVMSG #chan :op").is_some());
+        assert!(send(&mut f, "JOIN #chan").is_some());
+    }
+
+    #[test]
+    fn redacts_only_message_text() {
+        let mut f = Redact::new(&strings(vec!["hunter2", r"token=\S+"]));
+        assert_eq!(send(&mut f, "PRIVMSG #c :pw hunter2 token=abc ok"),
+                   Some("PRIVMSG #c :pw [redacted] [redacted] ok".to_string()));
+        assert_eq!(send(&mut f, "PASS hunter2"),
+                   Some("PASS hunter2".to_string()));
+        assert_eq!(send(&mut f, "JOIN #c hunter2"),
+                   Some("JOIN #c hunter2".to_string()));
+    }
+
+    #[test]
+    fn censors_whole_words() {
+        let mut f = Censor::new(&strings(vec!["darn"]));
+        assert_eq!(send(&mut f, "NOTICE #c :Darn it, darnit"),
+                   Some("NOTICE #c :**** it, darnit".to_string()));
+    }
+
+    #[test]
+    fn strips_formatting_in_some_channels() {
+        let mut f = StripFormatting::new(&strings(vec!["#plain"]));
+        assert_eq!(send(&mut f, "PRIVMSG #Plain :\x02hi\x0f \x034,5red\x03"),
+                   Some("PRIVMSG #Plain :hi red".to_string()));
+        assert_eq!(send(&mut f, "PRIVMSG #fancy :\x02hi"),
+                   Some("PRIVMSG #fancy :\x02hi".to_string()));
+    }
+
+    #[test]
+    fn drops_duplicates_per_target() {
+        let mut f = DropDuplicates::new();
+        assert!(send(&mut f, "PRIVMSG #a :hi").is_some());
+        assert!(send(&mut f, "PRIVMSG #b :hi").is_some());
+        assert_eq!(send(&mut f, "PRIVMSG #a :hi"), None);
+        assert!(send(&mut f, "PRIVMSG #a :bye").is_some());
+        assert!(send(&mut f, "PRIVMSG #a :hi").is_some());
+    }
+
+    #[test]
+    fn forgets_old_targets() {
+        let mut f = DropDuplicates::new();
+        for i in range(0, DUPLICATE_TARGETS + 1) {
+            send(&mut f, format!("PRIVMSG #{} :hi", i).as_slice());
+        }
+        assert_eq!(f.last.len(), DUPLICATE_TARGETS);
+        // The first target was forgotten, so the same line goes out again.
+        assert!(send(&mut f, "PRIVMSG #0 :hi").is_some());
+    }
+}
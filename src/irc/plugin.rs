@@ -2,10 +2,22 @@
 // project's directory structure.
 
 use irc::{IrcWriter, IrcCommand, BotInfo, IrcPrivMsg};
+use irc::worker::Job;
+use irc::bus::Event;
+use irc::auth::Role;
+use irc::limits::Cooldown;
 
+/// What a plugin did with a command or message.
+///
+/// `Accepted` consumes it, so no plugin after this one sees it. `Observed`
+/// means the plugin acted on it but is happy for the next plugin to have it
+/// too. `Async` accepts it, but hands the actual work to the worker pool so a
+/// slow command doesn't block the event loop.
 pub enum Handler {
     Accepted,
-    Passed
+    Observed,
+    Passed,
+    Async(Job)
 }
 
 /// Trait defining all cleese plugins.
@@ -28,6 +40,61 @@ pub trait Plugin {
     fn cmd(&mut self, cmd: &IrcCommand,
            writer: &IrcWriter, info: &BotInfo) -> Handler;
 
+    /// Set up after being registered or reloaded, e.g. by restoring state from
+    /// storage or starting timers. This can run before the bot has connected,
+    /// so it shouldn't send any messages. Does nothing by default.
+    fn startup(&mut self, _writer: &IrcWriter, _info: &BotInfo) {}
+
+    /// Clean up before being unloaded, e.g. by saving state to storage. Does
+    /// nothing by default.
+    fn shutdown(&mut self, _info: &BotInfo) {}
+
+    /// Respond to one of this plugin's timers firing.
+    ///
+    /// Timers are started with `IrcWriter::schedule` or `Irc::schedule`, and
+    /// the id is whatever the plugin gave the timer. Does nothing by default.
+    fn timer(&mut self, _id: &str, _writer: &IrcWriter, _info: &BotInfo) {}
+
+    /// Respond to someone, possibly the bot itself, joining a channel. Does
+    /// nothing by default.
+    fn join(&mut self, _channel: &str, _nick: &str,
+            _writer: &IrcWriter, _info: &BotInfo) {}
+
+    /// Provide the role needed to run a command. The dispatcher checks it
+    /// before calling `cmd`, and turns away anyone without it. Open to
+    /// everyone by default.
+    fn required_role(&self, _cmd: &str) -> Role { Role::Everyone }
+
+    /// Hear about someone being turned away from one of this plugin's
+    /// commands for not having the role it needs, e.g. to keep a record.
+    /// Does nothing by default.
+    fn denied(&mut self, _cmd: &IrcCommand, _info: &BotInfo) {}
+
+    /// Provide the cooldown for a command, if it has one. The dispatcher won't
+    /// pass the command on again until it's over. None by default.
+    fn cooldown(&self, _cmd: &str) -> Option<Cooldown> { None }
+
+    /// Provide the plugin's priority. Plugins with a higher priority see
+    /// commands and messages first, and plugins with the same priority go in
+    /// the order they were registered. An entry under `priorities` in the
+    /// config takes precedence. 0 by default.
+    fn priority(&self) -> int { 0 }
+
+    /// Whether this plugin only answers commands nothing else wanted. The
+    /// dispatcher won't pass it a command someone was turned away from, so
+    /// they're told why instead. False by default.
+    fn fallback(&self) -> bool { false }
+
+    /// Provide the event topics this plugin wants to hear about.
+    ///
+    /// Read once when the plugin is registered. Subscribes to nothing by
+    /// default.
+    fn subscriptions(&self) -> Vec<&'static str> { Vec::new() }
+
+    /// Respond to an event published by another plugin. Does nothing by
+    /// default.
+    fn event(&mut self, _event: &Event, _writer: &IrcWriter, _info: &BotInfo) {}
+
     /// Provide help text.
     fn help(&self) -> &'static str;
 
@@ -0,0 +1,373 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Policy
+//!
+//! How the bot reacts to being invited to a channel or kicked out of one.
+//!
+//! An invite is only taken up when whoever sent it has a high enough role, or
+//! the channel is on the allow list. Kicked, the bot waits a little and tries
+//! to rejoin, waiting twice as long each time it's kicked again or can't get
+//! back in, and gives up after a few tries. Both are set up from the config:
+//!
+//! ```
+//! "invites": {
+//!     "from": "officer",
+//!     "channels": ["##cse-*"]
+//! },
+//! "rejoin": {
+//!     "delay": 5,
+//!     "max_delay": 300,
+//!     "attempts": 5
+//! }
+//! ```
+//!
+//! Without them, invites are taken up from admins only, and the bot rejoins
+//! with the defaults above. `"attempts": 0` turns rejoining off.
+//!
+//! Channels the bot joins while it's running, whether it was invited or an
+//! admin told it to, are kept in storage for each network and joined again
+//! after a restart, along with the channels from the config. So is any key an
+//! admin gave for one, which is used to get back in after a kick too. Leaving
+//! one, or giving up on getting back in, forgets it.
+
+use std::cmp::min;
+use std::collections::HashMap;
+
+use irc::{IrcMsg, IrcWriter, BotInfo, Storage};
+use irc::middleware::{Middleware, Flow};
+use irc::auth::{Role, glob};
+
+
+// How long to wait before the first rejoin, in seconds.
+static DELAY: i64 = 5;
+
+// The longest wait between rejoins, in seconds.
+static MAX_DELAY: i64 = 5 * 60;
+
+// How many times to try to rejoin before giving up.
+static ATTEMPTS: uint = 5;
+
+// How long without a kick before the waits start from scratch.
+static FORGIVE: i64 = 60 * 60;
+
+
+/// The `invites` section of the config file.
+#[deriving(Decodable)]
+pub struct InviteConfig {
+    pub from: Option<String>,
+    pub channels: Option<Vec<String>>,
+}
+
+impl InviteConfig {
+    /// Build the middleware that takes up invites.
+    pub fn invites(&self) -> Invites {
+        let role = match self.from {
+            Some(ref name) => match Role::parse(name.as_slice()) {
+                Some(role) => role,
+                None => panic!("There's no role called {}", name),
+            },
+            None => Role::Admin,
+        };
+        Invites::new(role, self.channels.clone().unwrap_or(Vec::new()))
+    }
+}
+
+
+/// The `rejoin` section of the config file.
+#[deriving(Decodable)]
+pub struct RejoinConfig {
+    pub delay: Option<i64>,
+    pub max_delay: Option<i64>,
+    pub attempts: Option<uint>,
+}
+
+impl RejoinConfig {
+    /// Build the rejoin tracker.
+    pub fn rejoin(&self) -> Rejoin {
+        Rejoin::new(self.delay.unwrap_or(DELAY),
+                    self.max_delay.unwrap_or(MAX_DELAY),
+                    self.attempts.unwrap_or(ATTEMPTS))
+    }
+}
+
+
+/// Joins channels the bot is invited to, when the invite is allowed.
+#[deriving(Clone)]
+pub struct Invites {
+    // Anyone with this role can invite the bot anywhere.
+    role: Role,
+    // Channel patterns anyone can invite the bot to.
+    channels: Vec<String>,
+}
+
+impl Invites {
+    /// Construct the middleware with the role needed to invite the bot, and
+    /// the channels anyone can invite it to.
+    pub fn new(role: Role, channels: Vec<String>) -> Invites {
+        Invites {
+            role: role,
+            channels: channels.iter().map(|c| {
+                c.as_slice().to_lowercase()
+            }).collect(),
+        }
+    }
+
+    /// Only take up invites from admins.
+    pub fn defaults() -> Invites {
+        Invites::new(Role::Admin, Vec::new())
+    }
+
+    /// Is the channel on the allow list?
+    fn allowed(&self, chan: &str) -> bool {
+        let chan = chan.to_lowercase();
+        self.channels.iter().any(|c| glob(c.as_slice(), chan.as_slice()))
+    }
+}
+
+impl Middleware for Invites {
+    fn incoming(&mut self, msg: &mut IrcMsg,
+                writer: &IrcWriter, info: &BotInfo) -> Flow {
+        if msg.code.as_slice() != "INVITE" {
+            return Flow::Continue;
+        }
+        // :nick!user@host INVITE me :#chan
+        let chan = match msg.params().get(1) {
+            Some(chan) => chan.to_string(),
+            None => return Flow::Continue,
+        };
+        let account = msg.notes.get(&"account".to_string())
+                               .map(|a| a.as_slice());
+        let hostmask = match msg.notes.get(&"hostmask".to_string()) {
+            Some(h) => h.as_slice(),
+            None => return Flow::Continue,
+        };
+        let role = info.role_of(account, hostmask, chan.as_slice());
+        if role >= self.role || self.allowed(chan.as_slice()) {
+            println!("Invited to {} by {}, joining", chan, hostmask);
+            writer.join(chan.as_slice());
+        } else {
+            println!("Invited to {} by {}, not allowed", chan, hostmask);
+        }
+        Flow::Continue
+    }
+
+    /// Don't act on an invite this couldn't check.
+    fn guards(&self) -> bool { true }
+
+    fn name(&self) -> &'static str { "invites" }
+}
+
+
+// A channel the bot was kicked from.
+#[deriving(Clone)]
+struct Kicked {
+    // Tries so far since the waits last started from scratch.
+    attempts: uint,
+    // When we were last kicked or turned away.
+    last: i64,
+    // When to try next, or None while waiting to hear how a try went.
+    next: Option<i64>,
+}
+
+
+/// Works out when to rejoin the channels the bot was kicked from. Each
+/// network has its own.
+#[deriving(Clone)]
+pub struct Rejoin {
+    delay: i64,
+    max_delay: i64,
+    attempts: uint,
+    // By lowercased channel name.
+    kicked: HashMap<String, Kicked>,
+}
+
+impl Rejoin {
+    /// Construct the tracker, waiting `delay` seconds before the first try,
+    /// at most `max_delay` between tries, and trying `attempts` times.
+    pub fn new(delay: i64, max_delay: i64, attempts: uint) -> Rejoin {
+        Rejoin {
+            delay: delay,
+            max_delay: max_delay,
+            attempts: attempts,
+            kicked: HashMap::new(),
+        }
+    }
+
+    /// Rejoin with the waits and tries given at the top of this file.
+    pub fn defaults() -> Rejoin {
+        Rejoin::new(DELAY, MAX_DELAY, ATTEMPTS)
+    }
+
+    /// Note being kicked from a channel, or turned away when trying to get
+    /// back in. Returns how long until the next try, or None if we've given
+    /// up on it.
+    pub fn kicked(&mut self, chan: &str, now: i64) -> Option<i64> {
+        let key = chan.to_lowercase();
+        let attempts = match self.kicked.get(&key) {
+            Some(k) if now - k.last < FORGIVE => k.attempts,
+            _ => 0,
+        };
+        if attempts >= self.attempts {
+            self.kicked.remove(&key);
+            return None;
+        }
+        // Double the wait for each try so far, stopping at the longest, as
+        // `attempts` comes from the config and could be anything.
+        let mut wait = self.delay;
+        for _ in range(0, attempts) {
+            if wait <= 0 || wait >= self.max_delay {
+                break;
+            }
+            wait *= 2;
+        }
+        let wait = min(wait, self.max_delay);
+        self.kicked.insert(key, Kicked {
+            attempts: attempts + 1,
+            last: now,
+            next: Some(now + wait),
+        });
+        Some(wait)
+    }
+
+    /// Were we turned away from a channel we're trying to get back in to?
+    /// Anything else we couldn't join isn't ours to retry.
+    pub fn is_rejoining(&self, chan: &str) -> bool {
+        match self.kicked.get(&chan.to_lowercase()) {
+            Some(k) => k.next.is_none(),
+            None => false,
+        }
+    }
+
+    /// The channels due another try. They aren't due again until they're
+    /// kicked or turned away again.
+    pub fn due(&mut self, now: i64) -> Vec<String> {
+        let mut due = Vec::new();
+        for (chan, k) in self.kicked.iter_mut() {
+            match k.next {
+                Some(t) if t <= now => {
+                    k.next = None;
+                    due.push(chan.clone());
+                },
+                _ => (),
+            }
+        }
+        due
+    }
+
+    /// Stop trying to rejoin a channel, e.g. once we've left it on purpose.
+    pub fn forget(&mut self, chan: &str) {
+        self.kicked.remove(&chan.to_lowercase());
+    }
+}
+
+/// The channels joined on a network while running, to join again after a
+/// restart.
+pub fn remembered(storage: &Storage, network: &str) -> Vec<String> {
+    let key = format!("joined:{}", network);
+    storage.namespace("channels").get(key.as_slice()).unwrap_or(Vec::new())
+}
+
+/// Keep a channel to join again after a restart.
+pub fn remember(storage: &Storage, network: &str, chan: &str) {
+    let key = format!("joined:{}", network);
+    let key = key.as_slice();
+    storage.namespace("channels").transaction(|tx| {
+        let mut joined: Vec<String> = tx.get(key).unwrap_or(Vec::new());
+        let chan = chan.to_lowercase();
+        if joined.contains(&chan) {
+            return false;
+        }
+        joined.push(chan);
+        tx.set(key, &joined);
+        true
+    });
+}
+
+/// Keep the key for a channel joined while running, to join it with again.
+/// Keys are secrets, so they're kept apart from the list of channels, and
+/// never printed.
+pub fn remember_key(storage: &Storage, network: &str, chan: &str, key: &str) {
+    let name = format!("key:{}:{}", network, chan.to_lowercase());
+    storage.namespace("channels").set(name.as_slice(), &key.to_string());
+}
+
+/// The key given for a channel joined while running, if there was one.
+pub fn remembered_key(storage: &Storage, network: &str,
+                      chan: &str) -> Option<String> {
+    let name = format!("key:{}:{}", network, chan.to_lowercase());
+    storage.namespace("channels").get(name.as_slice())
+}
+
+/// Stop joining a channel after a restart, and forget its key.
+pub fn forget(storage: &Storage, network: &str, chan: &str) {
+    let name = format!("key:{}:{}", network, chan.to_lowercase());
+    storage.namespace("channels").delete(name.as_slice());
+    let key = format!("joined:{}", network);
+    let key = key.as_slice();
+    storage.namespace("channels").transaction(|tx| {
+        let mut joined: Vec<String> = tx.get(key).unwrap_or(Vec::new());
+        let before = joined.len();
+        joined.retain(|c| c.as_slice() != chan.to_lowercase().as_slice());
+        if joined.len() == before {
+            return false;
+        }
+        tx.set(key, &joined);
+        true
+    });
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Rejoin, FORGIVE};
+
+    #[test]
+    fn backs_off_then_gives_up() {
+        let mut r = Rejoin::new(5, 15, 4);
+        assert_eq!(r.kicked("#chan", 0), Some(5));
+        assert_eq!(r.kicked("#chan", 10), Some(10));
+        assert_eq!(r.kicked("#Chan", 30), Some(15));
+        assert_eq!(r.kicked("#chan", 50), Some(15));
+        assert_eq!(r.kicked("#chan", 70), None);
+        // Giving up forgets the channel, so the next kick starts over.
+        assert_eq!(r.kicked("#chan", 80), Some(5));
+    }
+
+    #[test]
+    fn starts_over_after_a_quiet_spell() {
+        let mut r = Rejoin::new(5, 300, 5);
+        r.kicked("#chan", 0);
+        r.kicked("#chan", 10);
+        assert_eq!(r.kicked("#chan", 10 + FORGIVE), Some(5));
+    }
+
+    #[test]
+    fn channels_are_due_once() {
+        let mut r = Rejoin::new(5, 300, 5);
+        r.kicked("#chan", 0);
+        assert!(r.due(4).is_empty());
+        assert!(!r.is_rejoining("#chan"));
+        assert_eq!(r.due(5), vec!["#chan".to_string()]);
+        assert!(r.due(100).is_empty());
+        assert!(r.is_rejoining("#CHAN"));
+        r.forget("#chan");
+        assert!(!r.is_rejoining("#chan"));
+    }
+
+    #[test]
+    fn waits_stay_bounded() {
+        let mut r = Rejoin::new(5, 300, 1000);
+        let mut wait = None;
+        for i in range(0, 100) {
+            wait = r.kicked("#chan", i);
+        }
+        assert_eq!(wait, Some(300));
+    }
+
+    #[test]
+    fn zero_attempts_never_rejoins() {
+        let mut r = Rejoin::new(5, 300, 0);
+        assert_eq!(r.kicked("#chan", 0), None);
+    }
+}
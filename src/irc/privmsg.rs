@@ -2,6 +2,7 @@
 // project's directory structure.
 
 use core::fmt::{Show, Formatter, Result};
+use std::collections::HashMap;
 use irc::msg::*;
 
 // A privmsg sent from the server.
@@ -11,6 +12,7 @@ pub struct IrcPrivMsg {
     pub sender_info: String,
     pub channel: String,
     pub txt: String,
+    pub notes: HashMap<String, String>,
 }
 
 impl IrcPrivMsg {
@@ -24,6 +26,7 @@ impl IrcPrivMsg {
                         sender_info: info,
                         channel: channel,
                         txt: txt,
+                        notes: msg.notes.clone(),
                     }),
                 _ => None,
             }
@@ -0,0 +1,600 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Process
+//!
+//! Plugins that run as their own program, so they can be written in any
+//! language. Each one is listed under `processes` in the config as a name and
+//! the command line to start it with. The bot talks to the program over its
+//! stdin and stdout, one json object per line, in the style of JSON-RPC.
+//!
+//! When the program starts the bot sends it a `hello` request, and it has a
+//! few seconds to answer with its help text and the commands it handles. No
+//! commands are passed on until it has:
+//!
+//! ```
+//! > {"jsonrpc":"2.0","id":0,"method":"hello","params":{"nick":"cleese"}}
+//! < {"jsonrpc":"2.0","id":0,"result":{"help":"Weather.",
+//!    "commands":["weather"]}}
+//! ```
+//!
+//! Each of those commands is then sent as a `command` request, and the answer
+//! is a list of actions to take. Actions are `message` and `notice`, with a
+//! `target` and `text`, and `join`, with a `target`. Targets with spaces or a
+//! leading `:` are ignored, and a carriage return in the text starts a new
+//! line:
+//!
+//! ```
+//! > {"jsonrpc":"2.0","id":3,"method":"command","params":{"name":"weather",
+//!    "args":["oslo"],"channel":"#cleese","sender":"bob",
+//!    "network":"freenode"}}
+//! < {"jsonrpc":"2.0","id":3,"result":{"actions":[{"action":"message",
+//!    "target":"#cleese","text":"Rain, as usual."}]}}
+//! ```
+//!
+//! Private messages and joins are sent as `privmsg` and `join` notifications,
+//! which don't get an answer, and the program can send a `send` notification
+//! with a list of actions whenever it likes. `shutdown` is sent before the bot
+//! stops the program.
+//!
+//! Commands, private messages and joins all say which `network` they came
+//! from. Actions answering a command go to the same network, and any other
+//! action goes to the first network, unless it gives a `network` of its own.
+//!
+//! Nothing ever waits on the program. If it doesn't answer a command in time
+//! the sender is told so and the program is restarted, and if it exits it's
+//! started again after a delay that doubles with every crash.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::io::{BufferedReader, IoError, PipeStream};
+use std::io::process::{Command, Process, InheritFd};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, SeqCst};
+use std::time::Duration;
+use serialize::{json, Encodable};
+
+use irc::{IrcPrivMsg, IrcWriter, IrcCommand, BotInfo, Plugin, Handler};
+use irc::scheduler::{Timer, now};
+use util::{clean_text, leak};
+
+
+/// How long, in seconds, a program gets to answer `hello`.
+static HELLO_TIMEOUT: i64 = 5;
+
+/// How long, in seconds, a program gets to answer a command.
+static COMMAND_TIMEOUT: i64 = 10;
+
+/// How long, in milliseconds, a program gets to exit after `shutdown`.
+static EXIT_TIMEOUT: u64 = 2000;
+
+/// The longest, in seconds, we wait before restarting a crashed program.
+static MAX_BACKOFF: i64 = 5 * 60;
+
+/// A program that stays up this long, in seconds, is no longer considered to
+/// be crashing.
+static STABLE_AFTER: i64 = 60;
+
+/// The id of the `hello` request. Other requests count up from 1.
+static HELLO_ID: uint = 0;
+
+
+/// A request or notification from the bot.
+#[deriving(Encodable)]
+struct Request<T> {
+    jsonrpc: &'static str,
+    id: Option<uint>,
+    method: &'static str,
+    params: T,
+}
+
+#[deriving(Encodable)]
+struct HelloParams<'a> {
+    nick: &'a str,
+    cmd_prefix: &'a str,
+}
+
+#[deriving(Encodable)]
+struct CommandParams<'a> {
+    name: &'a str,
+    args: &'a Vec<&'a str>,
+    channel: &'a str,
+    sender: &'a str,
+    network: &'a str,
+}
+
+#[deriving(Encodable)]
+struct PrivMsgParams<'a> {
+    sender_nick: &'a str,
+    sender_info: &'a str,
+    channel: &'a str,
+    txt: &'a str,
+    network: &'a str,
+}
+
+#[deriving(Encodable)]
+struct JoinParams<'a> {
+    channel: &'a str,
+    nick: &'a str,
+    network: &'a str,
+}
+
+
+/// A line from the program: the answer to a request, or a notification.
+#[deriving(Decodable)]
+struct Reply {
+    id: Option<uint>,
+    method: Option<String>,
+    result: Option<Answer>,
+    params: Option<Answer>,
+    error: Option<RpcError>,
+}
+
+#[deriving(Decodable)]
+struct Answer {
+    help: Option<String>,
+    commands: Option<Vec<String>>,
+    actions: Option<Vec<Action>>,
+}
+
+#[deriving(Decodable)]
+struct RpcError {
+    message: String,
+}
+
+/// Something the program wants the bot to do.
+#[deriving(Decodable)]
+struct Action {
+    action: String,
+    target: String,
+    text: Option<String>,
+    network: Option<String>,
+}
+
+
+/// A command sent to the program that hasn't been answered yet.
+struct Pending {
+    sender: String,
+    name: String,
+    network: String,
+    deadline: i64,
+}
+
+
+/// A running copy of the program.
+struct Child {
+    process: Process,
+    stdin: PipeStream,
+    started: i64,
+    alive: Arc<AtomicBool>,
+}
+
+
+/// A plugin running as a separate program.
+pub struct ProcessPlugin {
+    name: &'static str,
+    argv: Vec<String>,
+    help: &'static str,
+    commands: Vec<String>,
+    child: Option<Child>,
+    // The answer to `hello`, until it comes, and when to give up on it.
+    hello: Option<Receiver<Answer>>,
+    hello_by: i64,
+    pending: Arc<Mutex<HashMap<uint, Pending>>>,
+    next_id: uint,
+    crashes: uint,
+    restart_at: i64,
+}
+
+impl ProcessPlugin {
+    /// Construct a plugin that runs `argv`. The program isn't started until
+    /// the plugin is registered.
+    pub fn new(name: &str, argv: &Vec<&str>) -> ProcessPlugin {
+        // Plugins are made once, from the config, so leaking the name is
+        // fine.
+        ProcessPlugin {
+            name: leak(name.to_string()),
+            argv: argv.iter().map(|a| a.to_string()).collect(),
+            help: "Not running.",
+            commands: Vec::new(),
+            child: None,
+            hello: None,
+            hello_by: 0,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            next_id: HELLO_ID + 1,
+            crashes: 0,
+            restart_at: 0,
+        }
+    }
+
+    /// Start the program and send it `hello`. The answer is picked up by
+    /// `greeted` once it comes, so nothing waits on it.
+    fn start(&mut self, writer: &IrcWriter,
+             info: &BotInfo) -> Result<(), String> {
+        if self.argv.is_empty() {
+            return Err("no command given".to_string());
+        }
+        let mut command = Command::new(self.argv[0].as_slice());
+        command.args(self.argv.slice_from(1)).stderr(InheritFd(2));
+        let mut process = match command.spawn() {
+            Ok(p) => p,
+            Err(e) => return Err(format!("couldn't start: {}", e)),
+        };
+        let stdin = process.stdin.take().unwrap();
+        let stdout = process.stdout.take().unwrap();
+
+        let alive = Arc::new(AtomicBool::new(true));
+        let (hello_tx, hello_rx) = channel();
+        let reader_alive = alive.clone();
+        let pending = self.pending.clone();
+        let writer = writer.clone();
+        let name = self.name.to_string();
+        spawn(proc() {
+            read_replies(name, stdout, hello_tx, pending, writer);
+            reader_alive.store(false, SeqCst);
+        });
+
+        let mut child = Child {
+            process: process,
+            stdin: stdin,
+            started: now(),
+            alive: alive,
+        };
+        let params = HelloParams {
+            nick: info.nick,
+            cmd_prefix: info.cmd_prefix,
+        };
+        match send(&mut child.stdin, Some(HELLO_ID), "hello", &params) {
+            Ok(()) => (),
+            Err(e) => {
+                stop(child);
+                return Err(format!("couldn't send hello: {}", e));
+            }
+        }
+        self.child = Some(child);
+        self.hello = Some(hello_rx);
+        self.hello_by = now() + HELLO_TIMEOUT;
+        Ok(())
+    }
+
+    /// Check whether the program has answered `hello`, giving up on it if it
+    /// has taken too long.
+    fn greeted(&mut self) {
+        let hello = match self.hello {
+            Some(ref rx) => rx.try_recv().ok(),
+            None => return,
+        };
+        let hello = match hello {
+            Some(hello) => hello,
+            None => {
+                if now() >= self.hello_by {
+                    println!("{}: didn't answer hello", self.name);
+                    self.crashed();
+                }
+                return;
+            }
+        };
+        self.hello = None;
+        // The help is only leaked when it changes, which a program restarted
+        // after a crash hardly ever does.
+        let help = hello.help.unwrap_or("No help given.".to_string());
+        if help.as_slice() != self.help {
+            self.help = leak(help);
+        }
+        self.commands = hello.commands.unwrap_or(Vec::new());
+        println!("Started {} ({} commands)", self.name, self.commands.len());
+    }
+
+    /// Start the program again once its backoff has passed.
+    fn restart(&mut self, writer: &IrcWriter, info: &BotInfo) {
+        match self.start(writer, info) {
+            Ok(()) => return,
+            Err(e) => println!("{}: {}", self.name, e),
+        }
+        self.crashed();
+    }
+
+    /// Note that the program died, and work out when to try again.
+    fn crashed(&mut self) {
+        self.hello = None;
+        match self.child.take() {
+            Some(child) => {
+                if now() - child.started >= STABLE_AFTER {
+                    self.crashes = 0;
+                }
+                stop(child);
+            },
+            None => (),
+        }
+        let delay = match self.crashes {
+            n if n >= 9 => MAX_BACKOFF,
+            n => cmp::min(MAX_BACKOFF, 1i64 << n),
+        };
+        self.crashes += 1;
+        self.restart_at = now() + delay;
+        println!("{} stopped, restarting in {} seconds", self.name, delay);
+    }
+
+    /// Tell anyone whose command ran out of time. Returns whether any did.
+    fn expire(&mut self, writer: &IrcWriter) -> bool {
+        let now = now();
+        let mut pending = self.pending.lock();
+        let expired: Vec<uint> = pending.iter().filter(|&(_, p)| {
+            p.deadline <= now
+        }).map(|(id, _)| *id).collect();
+        for id in expired.iter() {
+            let p = pending.remove(id).unwrap();
+            let reply = format!("Sorry, `{}` took too long.", p.name);
+            writer.on(p.network.as_slice()).msg(p.sender.as_slice(),
+                                                reply.as_slice());
+        }
+        !expired.is_empty()
+    }
+
+    /// Send a notification, treating a closed pipe as a crash.
+    fn notify<'b, T>(&mut self, method: &'static str, params: &T)
+        where T: Encodable<json::Encoder<'b>, IoError> {
+        let failed = match self.child {
+            Some(ref mut child) => {
+                send(&mut child.stdin, None, method, params).is_err()
+            },
+            None => return,
+        };
+        if failed {
+            self.crashed();
+        }
+    }
+
+    /// Is the program still going, whether or not it has answered `hello`?
+    fn is_alive(&self) -> bool {
+        match self.child {
+            Some(ref child) => child.alive.load(SeqCst),
+            None => false,
+        }
+    }
+
+    /// Is the program up and ready for commands?
+    fn is_running(&self) -> bool {
+        self.is_alive() && self.hello.is_none()
+    }
+}
+
+impl Plugin for ProcessPlugin {
+    /// Start the program, and a timer to keep an eye on it.
+    fn startup(&mut self, writer: &IrcWriter, info: &BotInfo) {
+        let timer = Timer::every(self.name(), "check", Duration::seconds(1));
+        writer.schedule(timer);
+        match self.start(writer, info) {
+            Ok(()) => (),
+            Err(e) => {
+                println!("{}: {}", self.name, e);
+                self.crashed();
+            },
+        }
+    }
+
+    /// Ask the program to exit.
+    fn shutdown(&mut self, _: &BotInfo) {
+        self.notify("shutdown", &());
+        match self.child.take() {
+            Some(child) => stop(child),
+            None => (),
+        }
+    }
+
+    /// Pick up the answer to `hello`, time out slow commands, and restart
+    /// the program if it has died. The reader sets off the `hello` timer as
+    /// soon as the answer comes, and `check` goes off every second.
+    fn timer(&mut self, _: &str, writer: &IrcWriter, info: &BotInfo) {
+        self.greeted();
+        if self.expire(writer) {
+            // Something is stuck, so start over.
+            self.crashed();
+        }
+        if self.child.is_some() && !self.is_alive() {
+            self.crashed();
+        }
+        if self.child.is_none() && now() >= self.restart_at {
+            self.restart(writer, info);
+        }
+    }
+
+    /// Pass private messages on. The program can't accept them, as that would
+    /// mean waiting for it.
+    fn privmsg(&mut self, msg: &IrcPrivMsg,
+               _: &IrcWriter, info: &BotInfo) -> Handler {
+        let params = PrivMsgParams {
+            sender_nick: msg.sender_nick.as_slice(),
+            sender_info: msg.sender_info.as_slice(),
+            channel: msg.channel.as_slice(),
+            txt: msg.txt.as_slice(),
+            network: info.network,
+        };
+        self.notify("privmsg", &params);
+        Handler::Passed
+    }
+
+    /// Accept the commands the program said it handles. The answer comes back
+    /// later.
+    fn cmd(&mut self, cmd: &IrcCommand,
+           writer: &IrcWriter, info: &BotInfo) -> Handler {
+        if !self.commands.iter().any(|c| c.as_slice() == cmd.name) {
+            return Handler::Passed;
+        }
+        if !self.is_running() {
+            let reply = format!("Sorry, {} isn't running right now.",
+                                self.name);
+            writer.msg(cmd.sender, reply.as_slice());
+            return Handler::Accepted;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.lock().insert(id, Pending {
+            sender: cmd.sender.to_string(),
+            name: cmd.name.to_string(),
+            network: info.network.to_string(),
+            deadline: now() + COMMAND_TIMEOUT,
+        });
+        let params = CommandParams {
+            name: cmd.name,
+            args: &cmd.args,
+            channel: cmd.channel,
+            sender: cmd.sender,
+            network: info.network,
+        };
+        let sent = match self.child {
+            Some(ref mut child) => {
+                send(&mut child.stdin, Some(id), "command", &params)
+            },
+            None => unreachable!(),
+        };
+        if sent.is_err() {
+            self.pending.lock().remove(&id);
+            self.crashed();
+        }
+        Handler::Accepted
+    }
+
+    /// Pass joins on.
+    fn join(&mut self, channel: &str, nick: &str,
+            _: &IrcWriter, info: &BotInfo) {
+        let params = JoinParams {
+            channel: channel,
+            nick: nick,
+            network: info.network,
+        };
+        self.notify("join", &params);
+    }
+
+    /// Return the help text the program gave.
+    fn help(&self) -> &'static str { self.help }
+
+    /// Return the name the program was given in the config.
+    fn name(&self) -> &'static str { self.name }
+}
+
+
+/// Write one request or notification to the program.
+fn send<'b, T>(stdin: &mut PipeStream, id: Option<uint>,
+               method: &'static str, params: &T) -> Result<(), IoError>
+    where T: Encodable<json::Encoder<'b>, IoError> {
+    let request = Request {
+        jsonrpc: "2.0",
+        id: id,
+        method: method,
+        params: params,
+    };
+    let line = json::encode(&request);
+    try!(stdin.write_str(line.as_slice()));
+    try!(stdin.write_char('\n'));
+    stdin.flush()
+}
+
+/// Ask the program to exit, and kill it if it won't. The waiting is done on
+/// a task of its own, so the bot carries on in the meantime.
+fn stop(child: Child) {
+    let Child { mut process, stdin, .. } = child;
+    drop(stdin);
+    spawn(proc() {
+        process.set_timeout(Some(EXIT_TIMEOUT));
+        if process.wait().is_err() {
+            let _ = process.signal_kill();
+            process.set_timeout(None);
+            let _ = process.wait();
+        }
+    });
+}
+
+/// Read what the program says until it exits. The answer to `hello` goes back
+/// to whoever started the program, and actions are carried out right here.
+/// The writer is for the first network.
+fn read_replies(name: String, stdout: PipeStream, hello: Sender<Answer>,
+                pending: Arc<Mutex<HashMap<uint, Pending>>>,
+                writer: IrcWriter) {
+    let mut reader = BufferedReader::new(stdout);
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let reply: Reply = match json::decode(line.as_slice().trim()) {
+            Ok(reply) => reply,
+            Err(e) => {
+                println!("{} sent something odd ({}): {}", name, e, line);
+                continue;
+            }
+        };
+        match reply.error {
+            Some(e) => println!("{} reported an error: {}", name, e.message),
+            None => (),
+        }
+
+        // Answers go to the network the command came from.
+        let (answer, writer) = match reply.id {
+            Some(id) if id == HELLO_ID => {
+                match reply.result {
+                    Some(answer) => {
+                        if hello.send_opt(answer).is_ok() {
+                            let soon = Duration::zero();
+                            writer.schedule(Timer::once(name.as_slice(),
+                                                        "hello", soon));
+                        }
+                    },
+                    None => (),
+                }
+                continue;
+            },
+            // Drop answers to commands that already timed out.
+            Some(id) => match pending.lock().remove(&id) {
+                Some(p) => (reply.result, writer.on(p.network.as_slice())),
+                None => continue,
+            },
+            None => match reply.method {
+                Some(ref m) if m.as_slice() == "send" => {
+                    (reply.params, writer.clone())
+                },
+                _ => continue,
+            },
+        };
+        match answer.and_then(|a| a.actions) {
+            Some(actions) => act(name.as_slice(), &actions, &writer),
+            None => (),
+        }
+    }
+    println!("{} closed its output", name);
+}
+
+/// Carry out the program's actions, on the writer's network unless they say
+/// otherwise.
+fn act(name: &str, actions: &Vec<Action>, writer: &IrcWriter) {
+    for a in actions.iter() {
+        let writer = match a.network {
+            Some(ref network) => writer.on(network.as_slice()),
+            None => writer.clone(),
+        };
+        let target = a.target.as_slice();
+        if !valid_target(target) {
+            println!("{} asked to {} {}, which isn't a valid target", name,
+                     a.action, target.escape_default());
+            continue;
+        }
+        let text = match a.text {
+            Some(ref t) => clean_text(t.as_slice()),
+            None => String::new(),
+        };
+        match a.action.as_slice() {
+            "message" => writer.msg(target, text.as_slice()),
+            "notice" => writer.notice(target, text.as_slice()),
+            "join" => writer.join(target),
+            other => println!("{} asked for unknown action {}", name, other),
+        }
+    }
+}
+
+/// Can a program send to this? Anything with whitespace or a NUL in it, or a
+/// leading `:`, could sneak a command of its own onto the line.
+fn valid_target(target: &str) -> bool {
+    !target.is_empty() && !target.starts_with(":")
+        && !target.chars().any(|c| c.is_whitespace() || c == '\0')
+}
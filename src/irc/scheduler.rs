@@ -0,0 +1,458 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Scheduler
+//!
+//! Lets plugins run something later or periodically. Timers are owned by the
+//! core and checked on every tick of the event loop, so a plugin's `timer`
+//! method runs on the same thread as its `cmd` and `privmsg` methods and gets
+//! the same writer and bot info.
+//!
+//! Three kinds of schedule are supported: a one-shot delay, a fixed interval,
+//! and a cron-style schedule using the usual five fields:
+//!
+//!     minute hour day-of-month month day-of-week
+//!
+//! As in cron, when both day fields are restricted a day matching either one
+//! will do, so `0 9 1 * 1` runs on the first of the month and every Monday.
+//!
+//! Timers marked as persistent are written to `timers.json` in the data
+//! directory and loaded again on startup.
+//!
+//! A timer belongs to the network it was started on, and when it fires its
+//! plugin gets that network's writer and bot info. Timers started before the
+//! bot is running, and ones saved before there were networks, belong to the
+//! first network in the config.
+
+use std::mem;
+use std::io::{File, IoResult, USER_RWX};
+use std::io::fs;
+use std::time::Duration;
+use serialize::json;
+use time;
+use time::Timespec;
+
+
+/// How often a timer should fire.
+#[deriving(Clone, Encodable, Decodable, PartialEq)]
+pub enum Schedule {
+    /// Fire once, after the given number of seconds.
+    Once(i64),
+    /// Fire repeatedly, every given number of seconds.
+    Every(i64),
+    /// Fire whenever the cron expression matches the current minute.
+    Cron(String),
+}
+
+
+/// A single timer owned by a plugin.
+///
+/// Timers are identified by the name of the plugin that owns them along with
+/// an id chosen by the plugin and their network. Adding a timer with an id
+/// that is already in use on the same network replaces the old one.
+#[deriving(Clone, Encodable, Decodable)]
+pub struct Timer {
+    pub plugin: String,
+    pub id: String,
+    pub schedule: Schedule,
+    pub persistent: bool,
+    pub network: Option<String>,
+    next: i64,
+}
+
+impl Timer {
+    /// A timer that fires once after the given delay.
+    pub fn once(plugin: &str, id: &str, delay: Duration) -> Timer {
+        Timer::new(plugin, id, Schedule::Once(delay.num_seconds()))
+    }
+
+    /// A timer that fires every time the given interval passes.
+    pub fn every(plugin: &str, id: &str, interval: Duration) -> Timer {
+        Timer::new(plugin, id, Schedule::Every(interval.num_seconds()))
+    }
+
+    /// A timer that fires according to a cron expression. Returns None if the
+    /// expression can't be parsed.
+    pub fn cron(plugin: &str, id: &str, spec: &str) -> Option<Timer> {
+        match CronSpec::parse(spec) {
+            Some(_) => Some(Timer::new(plugin, id,
+                                       Schedule::Cron(spec.to_string()))),
+            None => None,
+        }
+    }
+
+    /// Mark the timer as persistent, so it survives a restart.
+    pub fn persistent(mut self) -> Timer {
+        self.persistent = true;
+        self
+    }
+
+    /// Put the timer on a network, unless it's on one already.
+    pub fn on(mut self, network: &str) -> Timer {
+        if self.network.is_none() {
+            self.network = Some(network.to_string());
+        }
+        self
+    }
+
+    fn new(plugin: &str, id: &str, schedule: Schedule) -> Timer {
+        let mut timer = Timer {
+            plugin: plugin.to_string(),
+            id: id.to_string(),
+            schedule: schedule,
+            persistent: false,
+            network: None,
+            next: 0,
+        };
+        timer.next = timer.next_after(now());
+        timer
+    }
+
+    /// Work out the next time this timer should fire after `t`.
+    fn next_after(&self, t: i64) -> i64 {
+        match self.schedule {
+            Schedule::Once(delay) => t + delay,
+            Schedule::Every(interval) => t + if interval > 0 {
+                interval
+            } else {
+                1
+            },
+            Schedule::Cron(ref spec) => match CronSpec::parse(spec.as_slice()) {
+                Some(cron) => cron.next_after(t),
+                None => t + 60,
+            },
+        }
+    }
+}
+
+
+/// Keeps track of every registered timer.
+pub struct Scheduler {
+    timers: Vec<Timer>,
+    file: Path,
+}
+
+impl Scheduler {
+    /// Construct a scheduler, loading any persisted timers from `file`.
+    pub fn new(file: Path) -> Scheduler {
+        let timers = match load(&file) {
+            Ok(timers) => timers,
+            Err(e) => {
+                if file.exists() {
+                    println!("Couldn't load timers from {}: {}",
+                             file.display(), e);
+                }
+                Vec::new()
+            }
+        };
+        Scheduler {
+            timers: timers,
+            file: file,
+        }
+    }
+
+    /// Add a timer, replacing any timer with the same plugin and id on the
+    /// same network.
+    pub fn add(&mut self, timer: Timer) {
+        self.remove(timer.plugin.as_slice(), timer.id.as_slice(),
+                    timer.network.as_ref().map(|n| n.as_slice()));
+        let persistent = timer.persistent;
+        self.timers.push(timer);
+        if persistent {
+            self.save();
+        }
+    }
+
+    /// Cancel a timer. Returns whether there was a timer to cancel.
+    pub fn cancel(&mut self, plugin: &str, id: &str,
+                  network: Option<&str>) -> bool {
+        match self.remove(plugin, id, network) {
+            Some(timer) => {
+                if timer.persistent {
+                    self.save();
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Collect every timer due at `now`. Repeating timers are rescheduled and
+    /// one-shot timers are dropped.
+    pub fn due(&mut self, now: i64) -> Vec<Timer> {
+        let mut fired = Vec::new();
+        let mut kept = Vec::new();
+        let mut dirty = false;
+
+        let timers = mem::replace(&mut self.timers, Vec::new());
+        for mut timer in timers.into_iter() {
+            if timer.next > now {
+                kept.push(timer);
+                continue;
+            }
+            dirty = dirty || timer.persistent;
+            fired.push(timer.clone());
+            match timer.schedule {
+                Schedule::Once(_) => {},
+                _ => {
+                    timer.next = timer.next_after(now);
+                    kept.push(timer);
+                }
+            }
+        }
+        self.timers = kept;
+
+        if dirty {
+            self.save();
+        }
+        fired
+    }
+
+    /// Put every timer that isn't on a network on this one.
+    pub fn adopt(&mut self, network: &str) {
+        for timer in self.timers.iter_mut() {
+            if timer.network.is_none() {
+                timer.network = Some(network.to_string());
+            }
+        }
+    }
+
+    /// How many timers there are.
+    pub fn len(&self) -> uint {
+        self.timers.len()
+    }
+
+    fn remove(&mut self, plugin: &str, id: &str,
+              network: Option<&str>) -> Option<Timer> {
+        let pos = self.timers.iter().position(|t| {
+            t.plugin.as_slice() == plugin && t.id.as_slice() == id
+                && t.network.as_ref().map(|n| n.as_slice()) == network
+        });
+        match pos {
+            Some(i) => self.timers.remove(i),
+            None => None,
+        }
+    }
+
+    /// Write all persistent timers to disk.
+    fn save(&self) {
+        let persistent: Vec<&Timer> = self.timers.iter().filter(|t| {
+            t.persistent
+        }).collect();
+        match save(&self.file, json::encode(&persistent).as_slice()) {
+            Err(e) => println!("Couldn't save timers: {}", e),
+            _ => (),
+        }
+    }
+}
+
+
+/// Current time in seconds since the epoch.
+pub fn now() -> i64 {
+    time::get_time().sec
+}
+
+fn load(file: &Path) -> IoResult<Vec<Timer>> {
+    let s = try!(File::open(file).read_to_string());
+    match json::decode(s.as_slice()) {
+        Ok(timers) => Ok(timers),
+        Err(e) => {
+            println!("Couldn't decode timers: {}", e);
+            Ok(Vec::new())
+        }
+    }
+}
+
+fn save(file: &Path, s: &str) -> IoResult<()> {
+    try!(fs::mkdir_recursive(&file.dir_path(), USER_RWX));
+    File::create(file).write_str(s)
+}
+
+
+/// A parsed cron expression. Each field holds the values it allows.
+struct CronSpec {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    // Whether each day field starts with `*`, which cron counts as not
+    // restricting the day.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSpec {
+    /// Parse a five-field cron expression.
+    fn parse(spec: &str) -> Option<CronSpec> {
+        let fields: Vec<&str> = spec.words().collect();
+        if fields.len() != 5 {
+            return None;
+        }
+        let mut weekdays = match parse_field(fields[4], 0, 7) {
+            Some(w) => w,
+            None => return None,
+        };
+        // Both 0 and 7 mean Sunday.
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        match (parse_field(fields[0], 0, 59),
+               parse_field(fields[1], 0, 23),
+               parse_field(fields[2], 1, 31),
+               parse_field(fields[3], 1, 12)) {
+            (Some(minutes), Some(hours), Some(days), Some(months)) =>
+                Some(CronSpec {
+                    minutes: minutes,
+                    hours: hours,
+                    days: days,
+                    months: months,
+                    weekdays: weekdays,
+                    any_day: fields[2].starts_with("*"),
+                    any_weekday: fields[4].starts_with("*"),
+                }),
+            _ => None,
+        }
+    }
+
+    /// Does the given local time match this expression?
+    fn matches(&self, tm: &time::Tm) -> bool {
+        let day = self.days[tm.tm_mday as uint];
+        let weekday = self.weekdays[tm.tm_wday as uint];
+        // Like cron, either day will do when both are restricted.
+        let day = if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        };
+        self.minutes[tm.tm_min as uint]
+            && self.hours[tm.tm_hour as uint]
+            && self.months[(tm.tm_mon + 1) as uint]
+            && day
+    }
+
+    /// Find the first matching minute after `t`. Gives up after a year and
+    /// a day, which only happens for expressions like "0 0 31 2 *".
+    fn next_after(&self, t: i64) -> i64 {
+        let mut minute = (t / 60 + 1) * 60;
+        let limit = t + 366 * 24 * 60 * 60;
+        while minute < limit {
+            if self.matches(&time::at(Timespec::new(minute, 0))) {
+                return minute;
+            }
+            minute += 60;
+        }
+        limit
+    }
+}
+
+/// Parse a single cron field into a table of allowed values. Supports `*`,
+/// single values, ranges, steps and comma-separated lists of those.
+fn parse_field(field: &str, min: uint, max: uint) -> Option<Vec<bool>> {
+    let mut allowed = Vec::from_elem(max + 1, false);
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(i) => match from_str::<uint>(part.slice_from(i + 1)) {
+                Some(s) if s > 0 => (part.slice_to(i), s),
+                _ => return None,
+            },
+            None => (part, 1u),
+        };
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else {
+            match range.find('-') {
+                Some(i) => match (from_str::<uint>(range.slice_to(i)),
+                                  from_str::<uint>(range.slice_from(i + 1))) {
+                    (Some(lo), Some(hi)) => (lo, hi),
+                    _ => return None,
+                },
+                None => match from_str::<uint>(range) {
+                    Some(v) => (v, v),
+                    None => return None,
+                },
+            }
+        };
+        if lo < min || hi > max || lo > hi {
+            return None;
+        }
+        let mut v = lo;
+        while v <= hi {
+            allowed[v] = true;
+            v += step;
+        }
+    }
+    Some(allowed)
+}
+
+
+#[cfg(test)]
+mod test {
+    use time;
+    use super::{CronSpec, parse_field};
+
+    fn allowed(field: &str, min: uint, max: uint) -> Vec<uint> {
+        let table = parse_field(field, min, max).unwrap();
+        range(min, max + 1).filter(|&v| table[v]).collect()
+    }
+
+    fn tm(min: i32, hour: i32, mday: i32, mon: i32, wday: i32) -> time::Tm {
+        let mut tm = time::empty_tm();
+        tm.tm_min = min;
+        tm.tm_hour = hour;
+        tm.tm_mday = mday;
+        tm.tm_mon = mon - 1;
+        tm.tm_wday = wday;
+        tm
+    }
+
+    #[test]
+    fn parses_fields() {
+        assert_eq!(allowed("5", 0, 59), vec![5]);
+        assert_eq!(allowed("1-3,10", 0, 23), vec![1, 2, 3, 10]);
+        assert_eq!(allowed("*/15", 0, 59), vec![0, 15, 30, 45]);
+        assert_eq!(allowed("10-20/5", 0, 59), vec![10, 15, 20]);
+        assert_eq!(allowed("*", 1, 12).len(), 12);
+    }
+
+    #[test]
+    fn rejects_bad_fields() {
+        assert!(parse_field("60", 0, 59).is_none());
+        assert!(parse_field("0", 1, 31).is_none());
+        assert!(parse_field("5-1", 0, 59).is_none());
+        assert!(parse_field("*/0", 0, 59).is_none());
+        assert!(parse_field("x", 0, 59).is_none());
+        assert!(parse_field("1,", 0, 59).is_none());
+    }
+
+    #[test]
+    fn rejects_bad_expressions() {
+        assert!(CronSpec::parse("* * * *").is_none());
+        assert!(CronSpec::parse("* * * * * *").is_none());
+        assert!(CronSpec::parse("* 24 * * *").is_none());
+    }
+
+    #[test]
+    fn matches_times() {
+        let spec = CronSpec::parse("30 9 * * 1-5").unwrap();
+        assert!(spec.matches(&tm(30, 9, 14, 3, 1)));
+        assert!(!spec.matches(&tm(30, 9, 14, 3, 0)));
+        assert!(!spec.matches(&tm(31, 9, 14, 3, 1)));
+    }
+
+    #[test]
+    fn either_day_will_do() {
+        let spec = CronSpec::parse("0 9 1 * 1").unwrap();
+        assert!(spec.matches(&tm(0, 9, 1, 3, 4)));
+        assert!(spec.matches(&tm(0, 9, 14, 3, 1)));
+        assert!(!spec.matches(&tm(0, 9, 14, 3, 4)));
+        let spec = CronSpec::parse("0 9 1 * *").unwrap();
+        assert!(!spec.matches(&tm(0, 9, 14, 3, 1)));
+    }
+
+    #[test]
+    fn seven_is_sunday() {
+        let spec = CronSpec::parse("0 0 * * 7").unwrap();
+        assert!(spec.matches(&tm(0, 0, 1, 1, 0)));
+    }
+}
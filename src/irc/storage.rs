@@ -0,0 +1,290 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Storage
+//!
+//! A small key-value store for anything that should survive a restart. It's
+//! kept as a single json file (`storage.json` in the data directory) which is
+//! loaded on startup and rewritten whenever something changes.
+//!
+//! Every plugin gets its own namespace, so two plugins can use the same keys
+//! without stepping on each other:
+//!
+//! ```
+//! let store = info.storage.namespace(self.name);
+//! store.set("count", &42u);
+//! let count: Option<uint> = store.get("count");
+//! ```
+//!
+//! Values are anything that can be encoded to json. Several changes can be
+//! made at once with `transaction`, and each namespace has a schema version
+//! which `migrate` uses to bring old data up to date.
+//!
+//! There's one store however many networks the bot is on. Each network's
+//! `BotInfo` has a clone of it, and clones share everything.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::collections::TreeMap;
+use std::io::{File, IoError, IoResult, USER_RWX};
+use std::io::fs;
+use serialize::{json, Encodable, Decodable};
+
+
+// Values are kept encoded, so the file format doesn't depend on any types.
+type Values = TreeMap<String, String>;
+
+
+/// Everything in the store, as it's laid out in the file.
+#[deriving(Clone, Encodable, Decodable)]
+struct Contents {
+    versions: TreeMap<String, uint>,
+    namespaces: TreeMap<String, Values>,
+}
+
+
+/// The whole store. Plugins use it through a `Namespace`.
+#[deriving(Clone)]
+pub struct Storage {
+    file: Path,
+    contents: Rc<RefCell<Contents>>,
+}
+
+impl Storage {
+    /// Open the store kept in `file`, starting empty if it doesn't exist yet.
+    /// Fails if it does exist but can't be read, rather than start over and
+    /// lose what's in it.
+    pub fn open(file: Path) -> Result<Storage, String> {
+        let contents = if file.exists() {
+            match load(&file) {
+                Ok(c) => c,
+                Err(e) => {
+                    return Err(format!("couldn't load storage from {}: {}",
+                                       file.display(), e));
+                }
+            }
+        } else {
+            Contents {
+                versions: TreeMap::new(),
+                namespaces: TreeMap::new(),
+            }
+        };
+        Ok(Storage {
+            file: file,
+            contents: Rc::new(RefCell::new(contents)),
+        })
+    }
+
+    /// Get the namespace with the given name, usually a plugin name.
+    pub fn namespace<'a>(&'a self, name: &str) -> Namespace<'a> {
+        Namespace {
+            storage: self,
+            name: name.to_string(),
+        }
+    }
+
+    /// Write everything to disk. The file is written to the side and then
+    /// moved into place, so a crash never leaves it half written.
+    fn save(&self) {
+        let s = json::encode(&*self.contents.borrow());
+        match save(&self.file, s.as_slice()) {
+            Err(e) => println!("Couldn't save storage: {}", e),
+            _ => (),
+        }
+    }
+}
+
+
+/// One plugin's view of the store.
+pub struct Namespace<'a> {
+    storage: &'a Storage,
+    name: String,
+}
+
+impl<'a> Namespace<'a> {
+    /// Get a value, or None if it isn't set or isn't of the right type.
+    pub fn get<T>(&self, key: &str) -> Option<T>
+        where T: Decodable<json::Decoder, json::DecoderError> {
+        let values = self.values();
+        get(&values, key)
+    }
+
+    /// Set a value.
+    pub fn set<'b, T>(&self, key: &str, value: &T)
+        where T: Encodable<json::Encoder<'b>, IoError> {
+        self.transaction(|tx| {
+            tx.set(key, value);
+            true
+        });
+    }
+
+    /// Delete a value. Returns whether there was anything to delete.
+    pub fn delete(&self, key: &str) -> bool {
+        let mut deleted = false;
+        self.transaction(|tx| {
+            deleted = tx.delete(key);
+            deleted
+        });
+        deleted
+    }
+
+    /// Get every value whose key starts with `prefix`, in key order. Values
+    /// that aren't of the right type are skipped.
+    pub fn scan<T>(&self, prefix: &str) -> Vec<(String, T)>
+        where T: Decodable<json::Decoder, json::DecoderError> {
+        let values = self.values();
+        scan(&values, prefix)
+    }
+
+    /// Make several changes at once. `f` works on a copy of the namespace,
+    /// which is only written back if it returns true.
+    pub fn transaction(&self, f: |&mut Transaction| -> bool) -> bool {
+        let mut tx = Transaction {
+            values: self.values(),
+            version: self.version(),
+        };
+        if !f(&mut tx) {
+            return false;
+        }
+        {
+            let mut contents = self.storage.contents.borrow_mut();
+            contents.namespaces.insert(self.name.clone(), tx.values);
+            contents.versions.insert(self.name.clone(), tx.version);
+        }
+        self.storage.save();
+        true
+    }
+
+    /// The schema version of this namespace. Namespaces start at version 0.
+    pub fn version(&self) -> uint {
+        match self.storage.contents.borrow().versions.get(&self.name) {
+            Some(v) => *v,
+            None => 0,
+        }
+    }
+
+    /// Bring the namespace up to `version`. `step` is called once for each
+    /// version between the current one and the target, with the version being
+    /// migrated to. All the steps happen in a single transaction, so if any of
+    /// them returns false nothing changes.
+    pub fn migrate(&self, version: uint,
+                   step: |uint, &mut Transaction| -> bool) -> bool {
+        let current = self.version();
+        if current >= version {
+            return true;
+        }
+        let migrated = self.transaction(|tx| {
+            for v in range(current + 1, version + 1) {
+                if !step(v, tx) {
+                    println!("Migrating {} to version {} failed", self.name, v);
+                    return false;
+                }
+                tx.version = v;
+            }
+            true
+        });
+        if migrated {
+            println!("Migrated {} from version {} to {}",
+                     self.name, current, version);
+        }
+        migrated
+    }
+
+    fn values(&self) -> Values {
+        match self.storage.contents.borrow().namespaces.get(&self.name) {
+            Some(values) => values.clone(),
+            None => TreeMap::new(),
+        }
+    }
+}
+
+
+/// A set of changes to a namespace that are applied all at once.
+pub struct Transaction {
+    values: Values,
+    version: uint,
+}
+
+impl Transaction {
+    /// Get a value, seeing any changes made so far in this transaction.
+    pub fn get<T>(&self, key: &str) -> Option<T>
+        where T: Decodable<json::Decoder, json::DecoderError> {
+        get(&self.values, key)
+    }
+
+    /// Set a value.
+    pub fn set<'b, T>(&mut self, key: &str, value: &T)
+        where T: Encodable<json::Encoder<'b>, IoError> {
+        self.values.insert(key.to_string(), json::encode(value));
+    }
+
+    /// Delete a value. Returns whether there was anything to delete.
+    pub fn delete(&mut self, key: &str) -> bool {
+        self.values.remove(&key.to_string()).is_some()
+    }
+
+    /// Get every value whose key starts with `prefix`.
+    pub fn scan<T>(&self, prefix: &str) -> Vec<(String, T)>
+        where T: Decodable<json::Decoder, json::DecoderError> {
+        scan(&self.values, prefix)
+    }
+
+    /// Would setting `key` to the string `value` keep the namespace within
+    /// `keys` keys and `bytes` bytes of keys and values? Whatever `key` holds
+    /// now doesn't count, as it would be replaced. Used to hold sandboxed code
+    /// to a quota.
+    pub fn fits(&self, key: &str, value: &str,
+                keys: uint, bytes: uint) -> bool {
+        let mut n = 1;
+        let mut size = key.len() + json::encode(&value.to_string()).len();
+        for (k, v) in self.values.iter() {
+            if k.as_slice() != key {
+                n += 1;
+                size += k.len() + v.len();
+            }
+        }
+        n <= keys && size <= bytes
+    }
+}
+
+
+fn get<T>(values: &Values, key: &str) -> Option<T>
+    where T: Decodable<json::Decoder, json::DecoderError> {
+    match values.get(&key.to_string()) {
+        Some(s) => json::decode(s.as_slice()).ok(),
+        None => None,
+    }
+}
+
+fn scan<T>(values: &Values, prefix: &str) -> Vec<(String, T)>
+    where T: Decodable<json::Decoder, json::DecoderError> {
+    let mut found = Vec::new();
+    for (key, s) in values.iter() {
+        if !key.as_slice().starts_with(prefix) {
+            continue;
+        }
+        match json::decode(s.as_slice()) {
+            Ok(v) => found.push((key.clone(), v)),
+            Err(_) => (),
+        }
+    }
+    found
+}
+
+fn load(file: &Path) -> Result<Contents, String> {
+    let s = match File::open(file).read_to_string() {
+        Ok(s) => s,
+        Err(e) => return Err(format!("{}", e)),
+    };
+    match json::decode(s.as_slice()) {
+        Ok(c) => Ok(c),
+        Err(e) => Err(format!("{}", e)),
+    }
+}
+
+fn save(file: &Path, s: &str) -> IoResult<()> {
+    try!(fs::mkdir_recursive(&file.dir_path(), USER_RWX));
+    let tmp = file.with_extension("tmp");
+    try!(File::create(&tmp).write_str(s));
+    fs::rename(&tmp, file)
+}
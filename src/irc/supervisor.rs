@@ -0,0 +1,101 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Supervisor
+//!
+//! Keeps a misbehaving plugin from taking down the whole bot. Every call into
+//! a plugin is run through the supervisor, which catches any panic, logs it
+//! along with the plugin name and what it was doing, and counts it. A plugin
+//! that panics too often within a short window is disabled until an admin
+//! re-enables it with the `enable` command.
+
+use std::any::{Any, AnyRefExt};
+use std::collections::{HashMap, HashSet};
+use std::rt::unwind;
+
+use irc::scheduler::now;
+
+
+/// How many panics a plugin gets within the window before it's disabled.
+static PANIC_LIMIT: uint = 3;
+
+/// The window, in seconds, panics are counted over.
+static PANIC_WINDOW: i64 = 10 * 60;
+
+
+/// Tracks plugin panics and which plugins have been disabled.
+pub struct Supervisor {
+    panics: HashMap<String, Vec<i64>>,
+    disabled: HashSet<String>,
+}
+
+impl Supervisor {
+    /// Construct a supervisor with no panics recorded.
+    pub fn new() -> Supervisor {
+        Supervisor {
+            panics: HashMap::new(),
+            disabled: HashSet::new(),
+        }
+    }
+
+    /// Has the given plugin been disabled?
+    pub fn is_disabled(&self, plugin: &str) -> bool {
+        self.disabled.contains(&plugin.to_string())
+    }
+
+    /// Re-enable a disabled plugin and forget its panics. Returns whether the
+    /// plugin was actually disabled.
+    pub fn enable(&mut self, plugin: &str) -> bool {
+        self.panics.remove(&plugin.to_string());
+        self.disabled.remove(&plugin.to_string())
+    }
+
+    /// Disable a plugin by hand. Returns whether it was enabled before.
+    pub fn disable(&mut self, plugin: &str) -> bool {
+        self.disabled.insert(plugin.to_string())
+    }
+
+    /// Run `f` on behalf of `plugin`, catching any panic. `what` describes the
+    /// call for the log. Returns whether `f` finished without panicking.
+    pub fn run(&mut self, plugin: &str, what: &str, f: ||) -> bool {
+        match unsafe { unwind::try(f) } {
+            Ok(()) => true,
+            Err(cause) => {
+                println!("Plugin {} panicked on {}: {}",
+                         plugin, what, describe(&cause));
+                self.record(plugin);
+                false
+            }
+        }
+    }
+
+    /// Count a panic, disabling the plugin if it's over the limit.
+    fn record(&mut self, plugin: &str) {
+        let t = now();
+        let key = plugin.to_string();
+        if !self.panics.contains_key(&key) {
+            self.panics.insert(key.clone(), Vec::new());
+        }
+        let times = self.panics.get_mut(&key).unwrap();
+        times.retain(|&x| t - x < PANIC_WINDOW);
+        times.push(t);
+
+        if times.len() >= PANIC_LIMIT && !self.disabled.contains(&key) {
+            println!("Plugin {} panicked {} times in {} seconds, disabling it",
+                     plugin, times.len(), PANIC_WINDOW);
+            self.disabled.insert(key.clone());
+        }
+    }
+}
+
+
+/// Pull a readable message out of a panic.
+fn describe(cause: &Box<Any + Send>) -> String {
+    match cause.downcast_ref::<&'static str>() {
+        Some(s) => s.to_string(),
+        None => match cause.downcast_ref::<String>() {
+            Some(s) => s.clone(),
+            None => "unknown cause".to_string(),
+        },
+    }
+}
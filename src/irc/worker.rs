@@ -0,0 +1,208 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Worker
+//!
+//! A small pool of background tasks for slow plugin commands. Everything else
+//! runs on the single event loop, so a command that reads a big file or talks
+//! to a web server would otherwise hold up PING replies and every other
+//! command until it's done.
+//!
+//! A plugin hands work to the pool by returning `Handler::Async` with a `Job`
+//! from `cmd` or `privmsg`. The job runs on a worker and replies through its
+//! `JobContext`, which routes output back through the event loop. Once a job
+//! runs past its timeout, or the bot shuts down, it is marked as cancelled and
+//! anything else it says is dropped.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, SeqCst};
+use std::task;
+use std::time::Duration;
+
+use irc::connection::ConnectionEvent;
+use irc::writer::IrcWriter;
+use util::newline_split;
+
+
+/// A slow piece of work handed to the pool by a plugin.
+pub struct Job {
+    target: String,
+    timeout: Duration,
+    work: proc(&JobContext):Send,
+}
+
+impl Job {
+    /// Construct a job. Replies go to `target` (usually the command's channel)
+    /// and the job is cancelled if it hasn't finished within `timeout`.
+    pub fn new(target: &str, timeout: Duration,
+               work: proc(&JobContext):Send) -> Job {
+        Job {
+            target: target.to_string(),
+            timeout: timeout,
+            work: work,
+        }
+    }
+}
+
+
+/// What a running job gets to talk to the outside world with.
+pub struct JobContext {
+    id: uint,
+    target: String,
+    tx: Sender<ConnectionEvent>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl JobContext {
+    /// Reply to wherever the job came from.
+    pub fn reply(&self, msg: &str) {
+        let target = self.target.clone();
+        self.msg(target.as_slice(), msg);
+    }
+
+    /// Send a PRIVMSG to the given target.
+    pub fn msg(&self, target: &str, msg: &str) {
+        for line in newline_split(msg).iter() {
+            let out = format!("PRIVMSG {} :{}", target, line);
+            let _ = self.tx.send_opt(ConnectionEvent::JobOutput(self.id, out));
+        }
+    }
+
+    /// Has the job timed out, or is the bot shutting down? Long-running jobs
+    /// should check this now and then and give up early if it's set.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(SeqCst)
+    }
+}
+
+
+/// A job waiting for, or running on, a worker.
+struct Task {
+    ctx: JobContext,
+    work: proc(&JobContext):Send,
+}
+
+/// What the pool remembers about a job until it finishes.
+struct Running {
+    plugin: String,
+    network: String,
+    target: String,
+    deadline: i64,
+    cancelled: Arc<AtomicBool>,
+}
+
+
+/// A fixed number of workers fed from a shared queue.
+pub struct WorkerPool {
+    queue: Option<Sender<Task>>,
+    events: Sender<ConnectionEvent>,
+    running: HashMap<uint, Running>,
+    next_id: uint,
+}
+
+impl WorkerPool {
+    /// Spawn `size` workers. Job output is sent back over `events`.
+    pub fn new(size: uint, events: Sender<ConnectionEvent>) -> WorkerPool {
+        let (tx, rx) = channel();
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in range(0, size) {
+            let rx = rx.clone();
+            spawn(proc() { worker(rx) });
+        }
+        WorkerPool {
+            queue: Some(tx),
+            events: events,
+            running: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Queue a job on behalf of `plugin`. Its output goes to `network`.
+    pub fn submit(&mut self, plugin: &str, network: &str, job: Job,
+                  now: i64) {
+        let queue = match self.queue {
+            Some(ref q) => q,
+            None => return,
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.running.insert(id, Running {
+            plugin: plugin.to_string(),
+            network: network.to_string(),
+            target: job.target.clone(),
+            deadline: now + job.timeout.num_seconds(),
+            cancelled: cancelled.clone(),
+        });
+        queue.send(Task {
+            ctx: JobContext {
+                id: id,
+                target: job.target,
+                tx: self.events.clone(),
+                cancelled: cancelled,
+            },
+            work: job.work,
+        });
+    }
+
+    /// The network a job talks to, while it's still allowed to talk.
+    pub fn network(&self, id: uint) -> Option<&str> {
+        self.running.get(&id).map(|r| r.network.as_slice())
+    }
+
+    /// How many jobs are queued or running.
+    pub fn jobs(&self) -> uint {
+        self.running.len()
+    }
+
+    /// Forget about a job once its worker is done with it.
+    pub fn finished(&mut self, id: uint) {
+        self.running.remove(&id);
+    }
+
+    /// Cancel every job that has run past its deadline, and let the user know.
+    pub fn expire(&mut self, now: i64, writer: &IrcWriter) {
+        let expired: Vec<uint> = self.running.iter().filter(|&(_, r)| {
+            r.deadline <= now
+        }).map(|(id, _)| *id).collect();
+
+        for id in expired.iter() {
+            let r = self.running.remove(id).unwrap();
+            r.cancelled.store(true, SeqCst);
+            println!("Job {} from plugin {} timed out", id, r.plugin);
+            writer.on(r.network.as_slice())
+                  .msg(r.target.as_slice(), "Sorry, that took too long.");
+        }
+    }
+
+    /// Cancel everything and let the workers exit once they're idle.
+    pub fn shutdown(&mut self) {
+        for (_, r) in self.running.iter() {
+            r.cancelled.store(true, SeqCst);
+        }
+        self.running.clear();
+        self.queue = None;
+    }
+}
+
+
+/// Take jobs off the queue and run them until the queue is closed.
+fn worker(queue: Arc<Mutex<Receiver<Task>>>) {
+    loop {
+        let task = match queue.lock().recv_opt() {
+            Ok(t) => t,
+            Err(_) => break,
+        };
+        let Task { ctx, work } = task;
+        let id = ctx.id;
+        let tx = ctx.tx.clone();
+
+        // Run each job in its own task so a panic only loses that job.
+        if task::try(proc() { work(&ctx) }).is_err() {
+            println!("Job {} panicked", id);
+        }
+        let _ = tx.send_opt(ConnectionEvent::JobDone(id));
+    }
+}
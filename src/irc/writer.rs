@@ -2,17 +2,32 @@
 // project's directory structure.
 
 use irc::connection::*;
+use irc::scheduler::Timer;
+use irc::bus::Event;
 use util::*;
 
-// Convenience wrapper to abstract away write commands.
+// Convenience wrapper to abstract away write commands. Everything written
+// goes to one network.
+#[deriving(Clone)]
 pub struct IrcWriter {
     tx: Sender<ConnectionEvent>,
+    network: String,
 }
 
 impl IrcWriter {
     // Wrapping a tx channel.
-    pub fn new(tx: Sender<ConnectionEvent>) -> IrcWriter {
-        IrcWriter { tx: tx.clone() }
+    pub fn new(tx: Sender<ConnectionEvent>, network: &str) -> IrcWriter {
+        IrcWriter { tx: tx.clone(), network: network.to_string() }
+    }
+
+    // The network written to.
+    pub fn network(&self) -> &str {
+        self.network.as_slice()
+    }
+
+    // A writer for another network, e.g. to pass a message on.
+    pub fn on(&self, network: &str) -> IrcWriter {
+        IrcWriter::new(self.tx.clone(), network)
     }
 
     // Join a channel.
@@ -20,12 +35,50 @@ impl IrcWriter {
         self.output(format!("JOIN {}", chan));
     }
 
+    // Join a channel, with its key if it has one.
+    pub fn join_keyed(&self, chan: &str, key: Option<&str>) {
+        match key {
+            Some(key) => self.output(format!("JOIN {} {}", chan, key)),
+            None => self.join(chan),
+        }
+    }
+
+    // Give the server password. Has to come before `identify`.
+    pub fn pass(&self, password: &str) {
+        self.output(format!("PASS {}", password));
+    }
+
+    // Become an IRC operator.
+    pub fn oper(&self, name: &str, password: &str) {
+        self.output(format!("OPER {} {}", name, password));
+    }
+
     // Identify us to the server.
     pub fn identify(&self, nick: &str, descr: &str) {
         self.output(format!("NICK {}", nick));
         self.output(format!("USER {} 8 * :{}", nick, descr));
     }
 
+    // Leave a channel.
+    pub fn part(&self, chan: &str, msg: &str) {
+        self.output(format!("PART {} :{}", chan, msg));
+    }
+
+    // Change channel or user modes.
+    pub fn mode(&self, target: &str, modes: &str) {
+        self.output(format!("MODE {} {}", target, modes));
+    }
+
+    // Ask ChanServ to do something, like `OP #chan nick`.
+    pub fn chanserv(&self, cmd: &str) {
+        self.msg("ChanServ", cmd);
+    }
+
+    // Kick someone from a channel.
+    pub fn kick(&self, chan: &str, nick: &str, reason: &str) {
+        self.output(format!("KICK {} {} :{}", chan, nick, reason));
+    }
+
     // Change nickname.
     pub fn nick(&self, s: &str) {
         self.output(format!("NICK {}", s));
@@ -38,18 +91,68 @@ impl IrcWriter {
         }
     }
 
+    // Send a CTCP ACTION, like /me.
+    pub fn act(&self, target: &str, msg: &str) {
+        for line in newline_split(msg).iter() {
+            self.output(format!("PRIVMSG {} :\x01ACTION {}\x01",
+                                target, line));
+        }
+    }
+
+    // Send a NOTICE.
+    pub fn notice(&self, target: &str, msg: &str) {
+        for line in newline_split(msg).iter() {
+            self.output(format!("NOTICE {} :{}", target, line));
+        }
+    }
+
     // Use for general output.
     pub fn output(&self, s: String) {
         let lines = newline_split(s.as_slice());
         for line in lines.iter() {
-            self.tx.send(ConnectionEvent::Output(line.to_string()));
+            self.tx.send(ConnectionEvent::Output(self.network.clone(),
+                                                 line.to_string()));
         }
     }
 
-    // Use for closing down.
+    // Start a timer on this network, unless it's been put on another one
+    // already, replacing any timer with the same plugin and id there.
+    pub fn schedule(&self, timer: Timer) {
+        let timer = timer.on(self.network.as_slice());
+        self.tx.send(ConnectionEvent::Schedule(timer));
+    }
+
+    // Cancel a timer on this network.
+    pub fn cancel(&self, plugin: &str, id: &str) {
+        self.tx.send(ConnectionEvent::Cancel(self.network.clone(),
+                                             plugin.to_string(),
+                                             id.to_string()));
+    }
+
+    // Publish an event to every plugin subscribed to its topic. They see it
+    // on this network.
+    pub fn publish(&self, event: Event) {
+        self.tx.send(ConnectionEvent::Publish(self.network.clone(), event));
+    }
+
+    // Ask the event loop to change something about the bot. The answer goes
+    // to `reply_to`, on this network.
+    pub fn control(&self, control: Control, reply_to: &str) {
+        self.tx.send(ConnectionEvent::Control(self.network.clone(), control,
+                                              reply_to.to_string()));
+    }
+
+    // Move this network on to another of its servers, by host or host:port,
+    // or the next one in the list. The answer goes to `reply_to`.
+    pub fn jump(&self, server: Option<&str>, reply_to: &str) {
+        self.tx.send(ConnectionEvent::Jump(self.network.clone(),
+                                           server.map(|s| s.to_string()),
+                                           reply_to.to_string()));
+    }
+
+    // Use for closing down. Leaves every network.
     pub fn quit(&self, s: &str) {
-        self.tx.send(ConnectionEvent::Output(format!("QUIT :{}", s)));
-        self.tx.send(ConnectionEvent::Quit);
+        self.tx.send(ConnectionEvent::Quit(s.to_string()));
     }
 }
 
@@ -27,7 +27,9 @@
 //!
 //! ## Multiple Connections
 //!
-//! As it stands, Cleese can only connect to a single server at a time.
+//! Cleese can be on several networks at once, each with its own nick,
+//! channels and command prefix. They all share one set of plugins, which are
+//! told which network a message came from and reply on the same one.
 
 
 #![crate_type = "bin"]
@@ -53,6 +55,7 @@ extern crate serialize;
 extern crate getopts;
 extern crate core;
 extern crate time;
+extern crate libc;
 
 use std::os;
 use std::io::{mod, BufferedReader, File};
@@ -68,6 +71,7 @@ mod plugins;
 // Default file names to be used later. Defined at the top for simplicity.
 static DEFAULT_CONF_FILE: &'static str = "config.json";
 static CARGO_FILE: &'static str = "Cargo.toml";
+static DEFAULT_DATA_DIR: &'static str = "data";
 
 
 /// Parse and respond to the CLI args
@@ -99,14 +103,12 @@ fn main() {
         Some(c) => c,
         None => DEFAULT_CONF_FILE.to_string()
     };
-    let jconf = JsonConfig::new(config_file);
+    let jconf = JsonConfig::new(config_file.clone());
 
     let config = IrcConfig {
-        host:     jconf.host.as_slice(),
-        port:     jconf.port,
-        nick:     jconf.nick.as_slice(),
+        config_file: config_file.as_slice(),
+        networks: jconf.networks(),
         descr:    jconf.descr.as_slice(),
-        channels: jconf.channels.iter().map(|x| x.as_slice()).collect(),
 
         // Input blacklist by code.
         in_blacklist: jconf.in_blacklist.iter().map(|x| x.as_slice()).collect(),
@@ -119,7 +121,38 @@ fn main() {
                     Err(err) => panic!("{}", err),
                 }
             }).collect(),
-        cmd_prefix: jconf.cmd_prefix.as_slice(),
+        data_dir: match jconf.data_dir {
+            Some(ref d) => d.as_slice(),
+            None => DEFAULT_DATA_DIR,
+        },
+        plugin_dir: jconf.plugin_dir.as_ref().map(|x| x.as_slice()),
+        watch_plugins: jconf.watch_plugins.unwrap_or(false),
+        scripts_dir: jconf.scripts_dir.as_ref().map(|x| x.as_slice()),
+        wasm_dir: jconf.wasm_dir.as_ref().map(|x| x.as_slice()),
+        processes: match jconf.processes {
+            Some(ref p) => p.iter().map(|(name, argv)| {
+                (name.as_slice(), argv.iter().map(|x| x.as_slice()).collect())
+            }).collect(),
+            None => Vec::new(),
+        },
+        priorities: match jconf.priorities {
+            Some(ref p) => p.iter().map(|(name, priority)| {
+                (name.as_slice(), *priority)
+            }).collect(),
+            None => Vec::new(),
+        },
+        filters: match jconf.outbound {
+            Some(ref o) => o.filters(),
+            None => Vec::new(),
+        },
+        invites: match jconf.invites {
+            Some(ref i) => i.invites(),
+            None => Invites::defaults(),
+        },
+        rejoin: match jconf.rejoin {
+            Some(ref r) => r.rejoin(),
+            None => Rejoin::defaults(),
+        },
     };
 
     if matches.opt_present("help") {
@@ -134,8 +167,10 @@ fn main() {
 
 /// Run the IRC bot
 ///
-/// This works by loading in the configuration from the config file,
-/// connecting to the server, and initializing all registered plugins.
+/// This works by loading in the configuration from the config file, opening
+/// the storage in the data directory, connecting to the server, and
+/// initializing all registered plugins. If the storage can't be read, it says
+/// why and exits instead.
 ///
 /// ## Example
 ///
@@ -147,7 +182,17 @@ fn main() {
 /// run(config);
 /// ```
 fn run(config: IrcConfig) {
-    let mut irc = Irc::connect(config);
+    let file = Path::new(config.data_dir).join("storage.json");
+    let storage = match Storage::open(file) {
+        Ok(storage) => storage,
+        Err(e) => {
+            println!("Not starting, {}. Fix or move the file and try again.",
+                     e);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+    let mut irc = Irc::connect(config, storage);
     plugins::register(&mut irc);
     irc.run();
 }
@@ -0,0 +1,248 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Admin
+//!
+//! Lets admins run the bot from IRC, without restarting it:
+//!
+//!     cleese join #cse-club
+//!     cleese join #officers s3cret
+//!     cleese part #cse-club Back later
+//!     cleese cycle
+//!     cleese say #cse-club Meeting in five minutes
+//!     cleese act #cse-club waves
+//!     cleese plugins
+//!     cleese disable excuse
+//!     cleese enable excuse
+//!     cleese reload greeter
+//!     cleese reconfig
+//!     cleese status
+//!     cleese handled
+//!     cleese jump
+//!     cleese jump irc.freenode.net:8000
+//!
+//! Middleware and outbound filters that keep panicking are disabled too, and
+//! are enabled again by their names under `middleware/` and `filter/`, e.g.
+//! `cleese enable middleware/ignore`.
+//!
+//! `status` says which server the bot is on for each network, and `jump`
+//! moves the network the command was sent on to another of its servers, or
+//! the next one in its list.
+//!
+//! Changing the bot's nick and quitting are for owners:
+//!
+//!     cleese nick cleese2
+//!     cleese quit Off for maintenance
+//!
+//! Everything done here is written to `audit.log` in the data directory, with
+//! when it happened, who did it and where. So is every attempt that failed or
+//! was turned away for not having the role.
+
+extern crate time;
+
+use std::io::{File, Append, Write, USER_RWX};
+use std::io::fs;
+
+use irc::{IrcPrivMsg, IrcWriter, IrcCommand, BotInfo, Plugin, Handler};
+use irc::{Role, Control, remember_key};
+
+
+/// Only has the minimum fields, as the audit log lives on disk.
+pub struct Admin {
+    description: &'static str,
+    name: &'static str
+}
+
+impl Admin {
+    /// Construct the plugin.
+    pub fn new() -> Admin {
+        Admin {
+            description: "Control the bot. Try `cleese status`.",
+            name: "admin"
+        }
+    }
+
+    /// Carry out a command. Returns what to record in the audit log, or an
+    /// error to tell the sender.
+    fn run(&self, cmd: &IrcCommand, writer: &IrcWriter,
+           info: &BotInfo) -> Result<String, String> {
+        let args = &cmd.args;
+        let rest = |from: uint| -> String {
+            args.slice_from(from).connect(" ")
+        };
+        match cmd.name {
+            "join" => {
+                let chan = try!(arg(cmd, 0, "join <#channel> [key]"));
+                // A key given here is kept for rejoining, e.g. after a
+                // restart or a kick.
+                let key = match cmd.args.get(1) {
+                    Some(key) => {
+                        remember_key(&info.storage, info.network, chan, *key);
+                        Some(key.to_string())
+                    },
+                    None => info.key(chan),
+                };
+                writer.join_keyed(chan, key.as_ref().map(|k| k.as_slice()));
+                Ok(format!("join {}", chan))
+            },
+            "part" => {
+                let chan = try!(arg(cmd, 0, "part <#channel> [message]"));
+                writer.part(chan, rest(1).as_slice());
+                Ok(format!("part {}", chan))
+            },
+            "cycle" => {
+                let chan = cmd.args.get(0).map(|c| *c).unwrap_or(cmd.channel);
+                writer.part(chan, "Cycling");
+                let key = info.key(chan);
+                writer.join_keyed(chan, key.as_ref().map(|k| k.as_slice()));
+                Ok(format!("cycle {}", chan))
+            },
+            "nick" => {
+                let nick = try!(arg(cmd, 0, "nick <nick>"));
+                writer.nick(nick);
+                Ok(format!("nick {}", nick))
+            },
+            "say" | "act" => {
+                let target = try!(arg(cmd, 0, "say|act <target> <text>"));
+                let text = rest(1);
+                if text.is_empty() {
+                    return Err("Say what?".to_string());
+                }
+                if cmd.name == "say" {
+                    writer.msg(target, text.as_slice());
+                } else {
+                    writer.act(target, text.as_slice());
+                }
+                Ok(format!("{} {} {}", cmd.name, target, text))
+            },
+            "quit" => {
+                let msg = rest(0);
+                writer.quit(msg.as_slice());
+                Ok(format!("quit {}", msg))
+            },
+            "reconfig" => {
+                writer.control(Control::ReloadConfig, cmd.sender);
+                Ok("reconfig".to_string())
+            },
+            "jump" => {
+                let server = cmd.args.get(0).map(|s| *s);
+                writer.jump(server, cmd.sender);
+                Ok(format!("jump {}", server.unwrap_or("to the next server")))
+            },
+            "plugins" | "status" | "handled" => {
+                let control = match cmd.name {
+                    "plugins" => Control::Plugins,
+                    "status" => Control::Status,
+                    _ => Control::Handled,
+                };
+                writer.control(control, cmd.sender);
+                Ok(cmd.name.to_string())
+            },
+            "enable" | "disable" | "reload" => {
+                let usage = format!("{} <plugin>", cmd.name);
+                let name = try!(arg(cmd, 0, usage.as_slice())).to_string();
+                if name.as_slice() == self.name {
+                    return Err("I'd rather not.".to_string());
+                }
+                let control = match cmd.name {
+                    "enable" => Control::Enable(name.clone()),
+                    "disable" => Control::Disable(name.clone()),
+                    _ => Control::Reload(name.clone()),
+                };
+                writer.control(control, cmd.sender);
+                Ok(format!("{} {}", cmd.name, name))
+            },
+            _ => Err(String::new()),
+        }
+    }
+
+    /// Append a line to the audit log, making the data directory if there
+    /// isn't one yet. A log that can't be written to is complained about, but
+    /// doesn't stop the command.
+    fn audit(&self, cmd: &IrcCommand, info: &BotInfo, action: &str) {
+        let who = match cmd.notes.get(&"account".to_string()) {
+            Some(account) => account.clone(),
+            None => cmd.sender.to_string(),
+        };
+        let hostmask = cmd.notes.get(&"hostmask".to_string())
+                                .map(|h| h.as_slice())
+                                .unwrap_or("?");
+        let line = format!("{} {} ({}) in {}: {}\n",
+                           time::now_utc().rfc3339(), who, hostmask,
+                           cmd.channel, action);
+        let dir = Path::new(info.data_dir);
+        let path = dir.join("audit.log");
+        let written = fs::mkdir_recursive(&dir, USER_RWX).and_then(|()| {
+            File::open_mode(&path, Append, Write)
+        }).and_then(|mut f| {
+            f.write_str(line.as_slice())
+        });
+        match written {
+            Ok(()) => (),
+            Err(e) => println!("Couldn't write to {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// The `i`th argument to a command, or its usage.
+fn arg<'a>(cmd: &IrcCommand<'a>, i: uint,
+           usage: &str) -> Result<&'a str, String> {
+    match cmd.args.get(i) {
+        Some(a) => Ok(*a),
+        None => Err(format!("Usage: {}", usage)),
+    }
+}
+
+impl Plugin for Admin {
+    /// Respond to private messages.
+    ///
+    /// Called by the plugin subsystem when a private message is received. It
+    /// currently does nothing.
+    fn privmsg(&mut self, _: &IrcPrivMsg,
+               _: &IrcWriter, _: &BotInfo) -> Handler {
+        Handler::Passed
+    }
+
+    /// Respond to received commands.
+    ///
+    /// Called by the plugin subsystem when a command is encountered. Anything
+    /// that goes wrong is told to the sender, and everything is audited.
+    fn cmd(&mut self, cmd: &IrcCommand,
+           writer: &IrcWriter, info: &BotInfo) -> Handler {
+        match self.run(cmd, writer, info) {
+            Ok(action) => self.audit(cmd, info, action.as_slice()),
+            Err(ref e) if e.is_empty() => return Handler::Passed,
+            Err(e) => {
+                let action = format!("{} failed: {}", cmd.name, e);
+                self.audit(cmd, info, action.as_slice());
+                writer.msg(cmd.sender, e.as_slice());
+            },
+        }
+        Handler::Accepted
+    }
+
+    /// Audit anyone turned away. Only the command is written down, as its
+    /// arguments can hold a channel key.
+    fn denied(&mut self, cmd: &IrcCommand, info: &BotInfo) {
+        let action = format!("{} denied", cmd.name);
+        self.audit(cmd, info, action.as_slice());
+    }
+
+    /// Everything here is for admins, and leaving or renaming the bot is for
+    /// owners.
+    fn required_role(&self, cmd: &str) -> Role {
+        match cmd {
+            "quit" | "nick" => Role::Owner,
+            "join" | "part" | "cycle" | "say" | "act" | "reconfig"
+                | "plugins" | "enable" | "disable" | "reload" | "status"
+                | "handled" | "jump" => Role::Admin,
+            _ => Role::Everyone,
+        }
+    }
+
+    /// Return the plugin description.
+    fn help(&self) -> &'static str { self.description }
+
+    /// Return the plugin name.
+    fn name(&self) -> &'static str { self.name }
+}
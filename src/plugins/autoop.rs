@@ -0,0 +1,231 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Auto-op
+//!
+//! Gives trusted members op or voice when they join. Admins keep the access
+//! list, by account or hostmask pattern, per channel:
+//!
+//!     cleese autoop alice ##cse-club
+//!     cleese autovoice *!*@club.example.org ##cse-club
+//!     cleese unauto alice ##cse-club
+//!     cleese autolist
+//!
+//! The channel defaults to the one the command was sent in, and the list is
+//! kept apart for each network, as accounts on one say nothing about another.
+//! When the bot
+//! isn't opped itself it asks ChanServ to do it instead, and once it's opped
+//! it catches up on anyone who's missing their modes. Being deopped just
+//! means going back to asking ChanServ.
+
+use irc::{IrcPrivMsg, IrcWriter, IrcCommand, BotInfo, Plugin, Handler};
+use irc::{Role, Event, identifies};
+
+
+/// Someone who gets a mode in a channel on a network.
+#[deriving(Clone, PartialEq, Encodable, Decodable)]
+struct Access {
+    who: String,
+    network: String,
+    channel: String,
+    // `o` or `v`.
+    mode: String,
+}
+
+
+/// Only has the minimum fields, as the access list lives in storage.
+pub struct AutoOp {
+    description: &'static str,
+    name: &'static str
+}
+
+impl AutoOp {
+    /// Construct the plugin.
+    pub fn new() -> AutoOp {
+        AutoOp {
+            description: "Ops and voices trusted members. Try `cleese \
+                          autolist`.",
+            name: "autoop"
+        }
+    }
+
+    /// The whole access list, for every network.
+    fn access(&self, info: &BotInfo) -> Vec<Access> {
+        info.storage.namespace(self.name).get("access").unwrap_or(Vec::new())
+    }
+
+    /// The access list for the network we're on.
+    fn here(&self, info: &BotInfo) -> Vec<Access> {
+        self.access(info).into_iter().filter(|a| {
+            a.network.as_slice() == info.network
+        }).collect()
+    }
+
+    /// The mode someone should have in a channel, if any. Op beats voice.
+    fn mode_for(&self, chan: &str, nick: &str,
+                info: &BotInfo) -> Option<&'static str> {
+        let account = info.state.account(nick);
+        let hostmask = match info.state.hostmask(nick) {
+            Some(h) => h,
+            None => return None,
+        };
+        let mut mode = None;
+        for a in self.here(info).iter() {
+            let here = a.channel.as_slice().to_lowercase()
+                == chan.to_lowercase();
+            if !here || !identifies(a.who.as_slice(), account,
+                                    hostmask.as_slice()) {
+                continue;
+            }
+            if a.mode.as_slice() == "o" {
+                return Some("o");
+            }
+            mode = Some("v");
+        }
+        mode
+    }
+
+    /// Give someone their mode, or ask ChanServ to if we can't.
+    fn give(&self, chan: &str, nick: &str, writer: &IrcWriter,
+            info: &BotInfo) {
+        let mode = match self.mode_for(chan, nick, info) {
+            Some(mode) => mode,
+            None => return,
+        };
+        let has = info.state.modes(chan, nick).map(|m| {
+            m.contains(mode)
+        }).unwrap_or(false);
+        if has {
+            return;
+        }
+        if info.state.am_op(chan) {
+            writer.mode(chan, format!("+{} {}", mode, nick).as_slice());
+        } else {
+            let cmd = if mode == "o" { "OP" } else { "VOICE" };
+            writer.chanserv(format!("{} {} {}", cmd, chan, nick).as_slice());
+        }
+    }
+
+    /// Add or remove someone from the access list.
+    fn change(&self, cmd: &IrcCommand, info: &BotInfo) -> String {
+        let who = match cmd.args.get(0) {
+            Some(who) => *who,
+            None => return format!("Usage: {} <who> [#channel]", cmd.name),
+        };
+        let chan = cmd.args.get(1).map(|c| *c).unwrap_or(cmd.channel);
+        let mode = match cmd.name {
+            "autoop" => Some("o"),
+            "autovoice" => Some("v"),
+            _ => None,
+        };
+        let mut access = self.access(info);
+        let before = access.len();
+        access.retain(|a| {
+            a.who.as_slice().to_lowercase() != who.to_lowercase()
+                || a.network.as_slice() != info.network
+                || a.channel.as_slice().to_lowercase() != chan.to_lowercase()
+        });
+        let reply = match mode {
+            Some(mode) => {
+                access.push(Access {
+                    who: who.to_string(),
+                    network: info.network.to_string(),
+                    channel: chan.to_lowercase(),
+                    mode: mode.to_string(),
+                });
+                let what = if mode == "o" { "op" } else { "voice" };
+                format!("{} will get {} in {}.", who, what, chan)
+            },
+            None if access.len() == before => {
+                return format!("{} isn't on the list for {}.", who, chan);
+            },
+            None => format!("{} won't get anything in {}.", who, chan),
+        };
+        info.storage.namespace(self.name).set("access", &access);
+        reply
+    }
+
+    /// Show the access list for this network.
+    fn list(&self, info: &BotInfo) -> String {
+        let lines: Vec<String> = self.here(info).iter().map(|a| {
+            let what = if a.mode.as_slice() == "o" { "op" } else { "voice" };
+            format!("{}: {} in {}", a.who, what, a.channel)
+        }).collect();
+        if lines.is_empty() {
+            return "Nobody gets anything.".to_string();
+        }
+        lines.connect("\n")
+    }
+}
+
+impl Plugin for AutoOp {
+    /// Respond to private messages.
+    ///
+    /// Called by the plugin subsystem when a private message is received. It
+    /// currently does nothing.
+    fn privmsg(&mut self, _: &IrcPrivMsg,
+               _: &IrcWriter, _: &BotInfo) -> Handler {
+        Handler::Passed
+    }
+
+    /// Respond to received commands.
+    ///
+    /// Called by the plugin subsystem when a command is encountered. It
+    /// responds to "autoop", "autovoice", "unauto" and "autolist", replying
+    /// to the sender.
+    fn cmd(&mut self, cmd: &IrcCommand,
+           writer: &IrcWriter, info: &BotInfo) -> Handler {
+        let reply = match cmd.name {
+            "autoop" | "autovoice" | "unauto" => self.change(cmd, info),
+            "autolist" => self.list(info),
+            _ => return Handler::Passed,
+        };
+        writer.msg(cmd.sender, reply.as_slice());
+        Handler::Accepted
+    }
+
+    /// Give people their modes as they join.
+    fn join(&mut self, channel: &str, nick: &str,
+            writer: &IrcWriter, info: &BotInfo) {
+        if !info.state.is_me(nick) {
+            self.give(channel, nick, writer, info);
+        }
+    }
+
+    fn subscriptions(&self) -> Vec<&'static str> {
+        vec!["channel.opped", "channel.deopped"]
+    }
+
+    /// Catch up on anyone missing their modes once opped.
+    fn event(&mut self, event: &Event, writer: &IrcWriter, info: &BotInfo) {
+        let chan = match event.payload::<String>() {
+            Some(chan) => chan,
+            None => return,
+        };
+        match event.topic.as_slice() {
+            "channel.opped" => {
+                for nick in info.state.members(chan.as_slice()).iter() {
+                    self.give(chan.as_slice(), *nick, writer, info);
+                }
+            },
+            "channel.deopped" => {
+                println!("Lost ops in {}, using ChanServ for now", chan);
+            },
+            _ => (),
+        }
+    }
+
+    /// Managing the access list is for admins.
+    fn required_role(&self, cmd: &str) -> Role {
+        match cmd {
+            "autoop" | "autovoice" | "unauto" | "autolist" => Role::Admin,
+            _ => Role::Everyone,
+        }
+    }
+
+    /// Return the plugin description.
+    fn help(&self) -> &'static str { self.description }
+
+    /// Return the plugin name.
+    fn name(&self) -> &'static str { self.name }
+}
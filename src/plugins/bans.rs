@@ -0,0 +1,338 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Bans
+//!
+//! Lets officers ban and quiet people in the channel they're in, for a while
+//! or for good:
+//!
+//!     cleese ban alice 2h Spamming links
+//!     cleese ban *!*@spam.example
+//!     cleese quiet bob 30m
+//!     cleese unban alice
+//!     cleese unquiet bob
+//!     cleese bans
+//!
+//! Given a nick, the bot bans everyone from that person's host. Bans are
+//! recorded in storage and lifted when they run out, even across restarts.
+//! Whenever the bot joins a channel it reads the ban list, forgets any of its
+//! bans that someone else has lifted, and lifts any that ran out while it was
+//! away.
+//!
+//! When the bot isn't opped it asks ChanServ for ops, and sets any bans it
+//! couldn't set before once it has them, kicking whoever was banned if
+//! they're still there. Everything here has to be sent in the channel it's
+//! about, not in a private message.
+
+use std::time::Duration;
+
+use irc::{IrcPrivMsg, IrcWriter, IrcCommand, BotInfo, Plugin, Handler};
+use irc::{Role, Timer, Event};
+use irc::{Ban, BAN, QUIET, ban_mask, parse_duration};
+
+
+/// Only has the minimum fields, as the bans live in storage.
+pub struct Bans {
+    description: &'static str,
+    name: &'static str
+}
+
+impl Bans {
+    /// Construct the plugin.
+    pub fn new() -> Bans {
+        Bans {
+            description: "Ban people for a while. Try `cleese bans`.",
+            name: "bans"
+        }
+    }
+
+    /// Work out the mask for a nick or mask, and the nick if there is one.
+    fn target(&self, who: &str,
+              info: &BotInfo) -> Result<(String, Option<String>), String> {
+        if who.contains_char('!') || who.contains_char('@') {
+            return Ok((who.to_string(), None));
+        }
+        match info.state.hostmask(who) {
+            Some(h) => Ok((ban_mask(h.as_slice()), Some(who.to_string()))),
+            None => Err(format!("I haven't seen {}, try a mask.", who)),
+        }
+    }
+
+    /// Ban or quiet someone.
+    fn ban(&self, cmd: &IrcCommand, mode: &str, writer: &IrcWriter,
+           info: &BotInfo) -> String {
+        let chan = match channel(cmd) {
+            Ok(chan) => chan,
+            Err(e) => return e,
+        };
+        let who = match cmd.args.get(0) {
+            Some(who) => *who,
+            None => return format!("Usage: {} <nick|mask> [duration] \
+                                    [reason]", cmd.name),
+        };
+        let (mask, nick) = match self.target(who, info) {
+            Ok(t) => t,
+            Err(e) => return e,
+        };
+        let rest = cmd.args.slice_from(1);
+        let duration = rest.get(0).and_then(|d| parse_duration(*d));
+        let (seconds, reason) = match duration {
+            Some(secs) => (Some(secs), rest.slice_from(1).connect(" ")),
+            None => (None, rest.connect(" ")),
+        };
+        if mode == QUIET && info.state.quiet(mask.as_slice()).is_none() {
+            return format!("I don't know how to quiet anyone on {}.",
+                           info.network);
+        }
+        let mut ban = Ban::new(chan, mask.as_slice(), mode, cmd.sender,
+                               reason.as_slice(), seconds);
+        if !info.state.am_op(chan) {
+            ban.pending = true;
+            if mode == BAN {
+                ban.kick = nick;
+            }
+            ban.save(&info.storage, info.network);
+            writer.chanserv(format!("OP {}", chan).as_slice());
+            return format!("I'm not opped in {}, so I've asked ChanServ. \
+                            I'll set the {} once I am.", chan, cmd.name);
+        }
+        ban.apply(writer, &info.state);
+        ban.save(&info.storage, info.network);
+        match nick {
+            Some(ref nick) if mode == BAN
+                && info.state.modes(chan, nick.as_slice()).is_some() => {
+                let reason = if reason.is_empty() {
+                    "Banned"
+                } else {
+                    reason.as_slice()
+                };
+                writer.kick(chan, nick.as_slice(), reason);
+            },
+            _ => (),
+        }
+        format!("Set {}.", ban)
+    }
+
+    /// Lift a ban or quiet.
+    fn unban(&self, cmd: &IrcCommand, mode: &str, writer: &IrcWriter,
+             info: &BotInfo) -> String {
+        let chan = match channel(cmd) {
+            Ok(chan) => chan,
+            Err(e) => return e,
+        };
+        let who = match cmd.args.get(0) {
+            Some(who) => *who,
+            None => return format!("Usage: {} <nick|mask>", cmd.name),
+        };
+        let (mask, _) = match self.target(who, info) {
+            Ok(t) => t,
+            Err(e) => return e,
+        };
+        if !info.state.am_op(chan) {
+            writer.chanserv(format!("OP {}", chan).as_slice());
+            return format!("I'm not opped in {}, so I've asked ChanServ. \
+                            Try again in a moment.", chan);
+        }
+        let ban = Ban::new(chan, mask.as_slice(), mode, cmd.sender, "", None);
+        ban.lift(writer, &info.state);
+        Ban::remove(&info.storage, info.network, chan, mask.as_slice(),
+                    mode);
+        format!("Lifted {} on {}.", if mode == QUIET { "quiet" } else { "ban" },
+                mask)
+    }
+
+    /// List the bans in the channel.
+    fn list(&self, cmd: &IrcCommand, info: &BotInfo) -> String {
+        let chan = match channel(cmd) {
+            Ok(chan) => chan,
+            Err(e) => return e,
+        };
+        let all = Ban::all(&info.storage, info.network);
+        let bans: Vec<Ban> = all.into_iter().filter(|b| {
+            b.channel.as_slice().to_lowercase() == chan.to_lowercase()
+        }).collect();
+        let mut lines: Vec<String> = bans.iter().map(|b| {
+            format!("{}", b)
+        }).collect();
+        // Bans someone else set, as far as we know.
+        match info.state.channel(chan) {
+            Some(c) => {
+                for mask in c.bans.iter() {
+                    // Quiets set with an extban are on the ban list too.
+                    let mine = bans.iter().any(|b| {
+                        b.same(chan, mask.as_slice(), BAN)
+                            || (b.mode.as_slice() == QUIET
+                                && info.state.quiet(b.mask.as_slice())
+                                       == Some(('b', mask.clone())))
+                    });
+                    if !mine {
+                        lines.push(format!("ban {}, not set by me", mask));
+                    }
+                }
+            },
+            None => (),
+        }
+        if lines.is_empty() {
+            return format!("No bans in {}.", chan);
+        }
+        lines.connect("\n")
+    }
+
+    /// Lift every ban that's run out, where the bot is opped.
+    fn expire(&self, writer: &IrcWriter, info: &BotInfo) {
+        for ban in Ban::all(&info.storage, info.network).iter() {
+            if ban.expired() && !ban.pending
+                && info.state.am_op(ban.channel.as_slice()) {
+                ban.lift(writer, &info.state);
+                Ban::remove(&info.storage, info.network,
+                            ban.channel.as_slice(), ban.mask.as_slice(),
+                            ban.mode.as_slice());
+            }
+        }
+    }
+
+    /// Check our records against a channel's ban list. Bans that aren't on
+    /// the list any more were lifted by someone else, so they're forgotten.
+    fn reconcile(&self, chan: &str, writer: &IrcWriter, info: &BotInfo) {
+        let listed = match info.state.channel(chan) {
+            Some(c) => c.bans.clone(),
+            None => return,
+        };
+        for ban in Ban::all(&info.storage, info.network).iter() {
+            if ban.pending || !ban.same(chan, ban.mask.as_slice(), BAN) {
+                continue;
+            }
+            let on_list = listed.iter().any(|m| {
+                ban.same(chan, m.as_slice(), BAN)
+            });
+            if !on_list {
+                println!("Forgetting {} in {}, it was lifted", ban, chan);
+                Ban::remove(&info.storage, info.network, chan,
+                            ban.mask.as_slice(), BAN);
+            }
+        }
+        self.expire(writer, info);
+    }
+
+    /// Set the bans that were waiting for the bot to be opped in a channel.
+    fn set_pending(&self, chan: &str, writer: &IrcWriter, info: &BotInfo) {
+        for ban in Ban::all(&info.storage, info.network).into_iter() {
+            let here = ban.same(chan, ban.mask.as_slice(),
+                                ban.mode.as_slice());
+            if !ban.pending || !here {
+                continue;
+            }
+            let mut ban = ban;
+            ban.pending = false;
+            if !ban.expired() {
+                ban.apply(writer, &info.state);
+                // Whoever it was for may have left in the meantime.
+                match ban.kick.take() {
+                    Some(nick) => {
+                        let nick = nick.as_slice();
+                        if info.state.modes(chan, nick).is_some() {
+                            let reason = if ban.reason.is_empty() {
+                                "Banned"
+                            } else {
+                                ban.reason.as_slice()
+                            };
+                            writer.kick(chan, nick, reason);
+                        }
+                    },
+                    None => (),
+                }
+                ban.save(&info.storage, info.network);
+            } else {
+                Ban::remove(&info.storage, info.network, chan,
+                            ban.mask.as_slice(), ban.mode.as_slice());
+            }
+        }
+        self.expire(writer, info);
+    }
+}
+
+/// The channel a command was sent in, or what to tell the sender if it was
+/// sent privately.
+fn channel<'a>(cmd: &IrcCommand<'a>) -> Result<&'a str, String> {
+    if cmd.channel.starts_with("#") || cmd.channel.starts_with("&") {
+        Ok(cmd.channel)
+    } else {
+        Err(format!("Send `{}` in the channel it's for.", cmd.name))
+    }
+}
+
+impl Plugin for Bans {
+    /// Respond to private messages.
+    ///
+    /// Called by the plugin subsystem when a private message is received. It
+    /// currently does nothing.
+    fn privmsg(&mut self, _: &IrcPrivMsg,
+               _: &IrcWriter, _: &BotInfo) -> Handler {
+        Handler::Passed
+    }
+
+    /// Respond to received commands.
+    ///
+    /// Called by the plugin subsystem when a command is encountered. It
+    /// responds to "ban", "unban", "quiet", "unquiet" and "bans", in the
+    /// channel the command was sent to. Replies go to the sender.
+    fn cmd(&mut self, cmd: &IrcCommand,
+           writer: &IrcWriter, info: &BotInfo) -> Handler {
+        let reply = match cmd.name {
+            "ban" => self.ban(cmd, BAN, writer, info),
+            "quiet" => self.ban(cmd, QUIET, writer, info),
+            "unban" => self.unban(cmd, BAN, writer, info),
+            "unquiet" => self.unban(cmd, QUIET, writer, info),
+            "bans" => self.list(cmd, info),
+            _ => return Handler::Passed,
+        };
+        writer.msg(cmd.sender, reply.as_slice());
+        Handler::Accepted
+    }
+
+    fn timer(&mut self, _: &str, writer: &IrcWriter, info: &BotInfo) {
+        self.expire(writer, info);
+    }
+
+    /// Ask for the ban list whenever the bot joins a channel, and check for
+    /// expired bans on that network every half a minute.
+    fn join(&mut self, channel: &str, nick: &str,
+            writer: &IrcWriter, info: &BotInfo) {
+        if info.state.is_me(nick) {
+            writer.mode(channel, "+b");
+            writer.schedule(Timer::every(self.name, "expire",
+                                         Duration::seconds(30)));
+        }
+    }
+
+    fn subscriptions(&self) -> Vec<&'static str> {
+        vec!["channel.bans", "channel.opped"]
+    }
+
+    /// Check the ban list once it's in, and set pending bans once opped.
+    fn event(&mut self, event: &Event, writer: &IrcWriter, info: &BotInfo) {
+        let chan = match event.payload::<String>() {
+            Some(chan) => chan,
+            None => return,
+        };
+        match event.topic.as_slice() {
+            "channel.bans" => self.reconcile(chan.as_slice(), writer, info),
+            "channel.opped" => self.set_pending(chan.as_slice(), writer, info),
+            _ => (),
+        }
+    }
+
+    /// Banning is for officers.
+    fn required_role(&self, cmd: &str) -> Role {
+        match cmd {
+            "ban" | "unban" | "quiet" | "unquiet" | "bans" => Role::Officer,
+            _ => Role::Everyone,
+        }
+    }
+
+    /// Return the plugin description.
+    fn help(&self) -> &'static str { self.description }
+
+    /// Return the plugin name.
+    fn name(&self) -> &'static str { self.name }
+}
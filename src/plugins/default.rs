@@ -47,6 +47,12 @@ impl Plugin for Default {
         Handler::Accepted
     }
 
+    /// Come after every other plugin, as this accepts anything.
+    fn priority(&self) -> int { -1000 }
+
+    /// Stand aside for the dispatcher when someone was turned away.
+    fn fallback(&self) -> bool { true }
+
     /// Return the plugin description.
     fn help(&self) -> &'static str { self.description }
 
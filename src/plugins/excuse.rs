@@ -9,6 +9,7 @@
 
 use std::rand;
 use irc::{IrcPrivMsg, IrcWriter, IrcCommand, BotInfo, Plugin, Handler};
+use irc::Cooldown;
 
 
 /// Contains a vector of excuses, defined in the constructor.
@@ -109,6 +110,14 @@ impl Plugin for Excuse {
         }
     }
 
+    /// Excuses wear thin quickly, so a channel gets one every 30 seconds.
+    fn cooldown(&self, cmd: &str) -> Option<Cooldown> {
+        match cmd {
+            "excuse" => Some(Cooldown::per_channel(30)),
+            _ => None,
+        }
+    }
+
     /// Return the plugin description.
     fn help(&self) -> &'static str { self.description }
 
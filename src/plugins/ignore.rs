@@ -0,0 +1,129 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Ignore
+//!
+//! Lets admins choose who the bot ignores, by nick, hostmask pattern or
+//! account, everywhere or in one channel:
+//!
+//!     cleese ignore spammer
+//!     cleese ignore *!*@spam.example #cse-club
+//!     cleese ignore account:otherbot
+//!     cleese unignore spammer
+//!
+//! Rules only apply on the network they were made on. `cleese ignores` lists
+//! everyone ignored there, including bots the bot spotted by itself. The
+//! ignoring itself is done by middleware, see `src/irc/ignore.rs`.
+
+use irc::{IrcPrivMsg, IrcWriter, IrcCommand, BotInfo, Plugin, Handler};
+use irc::{Role, IgnoreRule};
+
+
+/// Only has the minimum fields, as the rules live in storage.
+pub struct Ignores {
+    description: &'static str,
+    name: &'static str
+}
+
+impl Ignores {
+    /// Construct the plugin.
+    pub fn new() -> Ignores {
+        Ignores {
+            description: "Choose who the bot ignores. Try `cleese ignores`.",
+            name: "ignore"
+        }
+    }
+
+    /// Start ignoring someone.
+    fn ignore(&self, cmd: &IrcCommand, info: &BotInfo) -> String {
+        let who = match cmd.args.get(0) {
+            Some(who) => *who,
+            None => return "Usage: ignore <who> [#channel]".to_string(),
+        };
+        let channel = cmd.args.get(1).map(|c| c.to_string());
+        let reply = match channel {
+            Some(ref c) => format!("Ignoring {} in {}.", who, c),
+            None => format!("Ignoring {}.", who),
+        };
+        let rule = IgnoreRule {
+            who: who.to_string(),
+            network: info.network.to_string(),
+            channel: channel,
+        };
+        rule.save(&info.storage);
+        reply
+    }
+
+    /// Stop ignoring someone.
+    fn unignore(&self, cmd: &IrcCommand, info: &BotInfo) -> String {
+        let who = match cmd.args.get(0) {
+            Some(who) => *who,
+            None => return "Usage: unignore <who> [#channel]".to_string(),
+        };
+        let channel = cmd.args.get(1).map(|c| *c);
+        if IgnoreRule::remove(&info.storage, info.network, who, channel) {
+            format!("No longer ignoring {}.", who)
+        } else {
+            format!("{} wasn't being ignored there.", who)
+        }
+    }
+
+    /// List everyone ignored.
+    fn list(&self, info: &BotInfo) -> String {
+        let rules = IgnoreRule::all(&info.storage, info.network);
+        let mut lines: Vec<String> = rules.iter().map(|r| {
+            match r.channel {
+                Some(ref c) => format!("{} in {}", r.who, c),
+                None => r.who.clone(),
+            }
+        }).collect();
+        for bot in IgnoreRule::bots(&info.storage, info.network).iter() {
+            lines.push(format!("{} (spotted as a bot)", bot));
+        }
+        if lines.is_empty() {
+            return "Nobody is being ignored.".to_string();
+        }
+        lines.connect("\n")
+    }
+}
+
+impl Plugin for Ignores {
+    /// Respond to private messages.
+    ///
+    /// Called by the plugin subsystem when a private message is received. It
+    /// currently does nothing.
+    fn privmsg(&mut self, _: &IrcPrivMsg,
+               _: &IrcWriter, _: &BotInfo) -> Handler {
+        Handler::Passed
+    }
+
+    /// Respond to received commands.
+    ///
+    /// Called by the plugin subsystem when a command is encountered. It
+    /// responds to "ignore", "unignore" and "ignores", replying to the sender.
+    fn cmd(&mut self, cmd: &IrcCommand,
+           writer: &IrcWriter, info: &BotInfo) -> Handler {
+        let reply = match cmd.name {
+            "ignore" => self.ignore(cmd, info),
+            "unignore" => self.unignore(cmd, info),
+            "ignores" => self.list(info),
+            _ => return Handler::Passed,
+        };
+        writer.msg(cmd.sender, reply.as_slice());
+        Handler::Accepted
+    }
+
+    /// Managing the ignore list is for admins.
+    fn required_role(&self, cmd: &str) -> Role {
+        match cmd {
+            "ignore" | "unignore" | "ignores" => Role::Admin,
+            _ => Role::Everyone,
+        }
+    }
+
+    /// Return the plugin description.
+    fn help(&self) -> &'static str { self.description }
+
+    /// Return the plugin name.
+    fn name(&self) -> &'static str { self.name }
+}
@@ -0,0 +1,164 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Lua
+//!
+//! The small slice of the Lua 5.1 C API the scripts plugin needs. Several of
+//! these are macros in `lua.h`, and are written out as functions here.
+//!
+//! Lua reports errors by jumping straight out of whatever C (or Rust) it was
+//! called from, skipping any destructors on the way. The helpers here never
+//! own anything while calling into Lua, and never read or write tables in a
+//! way a script's metamethods could hook into.
+
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+
+use libc::{c_char, c_int, c_void, c_double, size_t};
+
+
+pub enum lua_State {}
+
+pub type lua_CFunction = extern "C" fn(*mut lua_State) -> c_int;
+pub type lua_Alloc = extern "C" fn(*mut c_void, *mut c_void,
+                                   size_t, size_t) -> *mut c_void;
+pub type lua_Hook = extern "C" fn(*mut lua_State, *mut c_void);
+
+pub static LUA_REGISTRYINDEX: c_int = -10000;
+pub static LUA_GLOBALSINDEX: c_int = -10002;
+pub static LUA_MULTRET: c_int = -1;
+pub static LUA_TNIL: c_int = 0;
+pub static LUA_TSTRING: c_int = 4;
+pub static LUA_TFUNCTION: c_int = 6;
+pub static LUA_MASKCOUNT: c_int = 1 << 3;
+pub static LUA_NOREF: c_int = -2;
+
+#[link(name = "lua5.1")]
+extern "C" {
+    pub fn lua_newstate(f: lua_Alloc, ud: *mut c_void) -> *mut lua_State;
+    pub fn lua_close(L: *mut lua_State);
+    pub fn lua_getallocf(L: *mut lua_State, ud: *mut *mut c_void) -> lua_Alloc;
+    pub fn lua_sethook(L: *mut lua_State, f: lua_Hook,
+                       mask: c_int, count: c_int) -> c_int;
+
+    pub fn lua_gettop(L: *mut lua_State) -> c_int;
+    pub fn lua_settop(L: *mut lua_State, idx: c_int);
+    pub fn lua_pushvalue(L: *mut lua_State, idx: c_int);
+    pub fn lua_insert(L: *mut lua_State, idx: c_int);
+    pub fn lua_type(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_tolstring(L: *mut lua_State, idx: c_int,
+                         len: *mut size_t) -> *const c_char;
+    pub fn lua_tonumber(L: *mut lua_State, idx: c_int) -> c_double;
+    pub fn lua_toboolean(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_touserdata(L: *mut lua_State, idx: c_int) -> *mut c_void;
+
+    pub fn lua_pushnil(L: *mut lua_State);
+    pub fn lua_pushlstring(L: *mut lua_State, s: *const c_char, len: size_t);
+    pub fn lua_pushcclosure(L: *mut lua_State, f: lua_CFunction, n: c_int);
+    pub fn lua_pushlightuserdata(L: *mut lua_State, p: *mut c_void);
+
+    pub fn lua_createtable(L: *mut lua_State, narr: c_int, nrec: c_int);
+    pub fn lua_getfield(L: *mut lua_State, idx: c_int, k: *const c_char);
+    pub fn lua_setfield(L: *mut lua_State, idx: c_int, k: *const c_char);
+    pub fn lua_rawget(L: *mut lua_State, idx: c_int);
+    pub fn lua_rawset(L: *mut lua_State, idx: c_int);
+    pub fn lua_rawgeti(L: *mut lua_State, idx: c_int, n: c_int);
+    pub fn lua_rawseti(L: *mut lua_State, idx: c_int, n: c_int);
+
+    pub fn lua_call(L: *mut lua_State, nargs: c_int, nresults: c_int);
+    pub fn lua_pcall(L: *mut lua_State, nargs: c_int, nresults: c_int,
+                     errfunc: c_int) -> c_int;
+    pub fn lua_error(L: *mut lua_State) -> c_int;
+
+    pub fn luaL_loadbuffer(L: *mut lua_State, buff: *const c_char,
+                           sz: size_t, name: *const c_char) -> c_int;
+    pub fn luaL_ref(L: *mut lua_State, t: c_int) -> c_int;
+    pub fn luaL_unref(L: *mut lua_State, t: c_int, r: c_int);
+
+    pub fn luaopen_base(L: *mut lua_State) -> c_int;
+    pub fn luaopen_table(L: *mut lua_State) -> c_int;
+    pub fn luaopen_string(L: *mut lua_State) -> c_int;
+    pub fn luaopen_math(L: *mut lua_State) -> c_int;
+}
+
+// The library openers have to be called through Lua, which wants them as
+// plain C functions.
+pub extern "C" fn open_base(L: *mut lua_State) -> c_int {
+    unsafe { luaopen_base(L) }
+}
+
+pub extern "C" fn open_table(L: *mut lua_State) -> c_int {
+    unsafe { luaopen_table(L) }
+}
+
+pub extern "C" fn open_string(L: *mut lua_State) -> c_int {
+    unsafe { luaopen_string(L) }
+}
+
+pub extern "C" fn open_math(L: *mut lua_State) -> c_int {
+    unsafe { luaopen_math(L) }
+}
+
+pub unsafe fn lua_pop(L: *mut lua_State, n: c_int) {
+    lua_settop(L, -n - 1)
+}
+
+pub unsafe fn lua_upvalueindex(i: c_int) -> c_int {
+    LUA_GLOBALSINDEX - i
+}
+
+pub unsafe fn lua_isfunction(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TFUNCTION
+}
+
+/// Push a Rust string, which doesn't have to be null terminated.
+pub unsafe fn push_str(L: *mut lua_State, s: &str) {
+    lua_pushlstring(L, s.as_ptr() as *const c_char, s.len() as size_t)
+}
+
+/// Read a string off the stack, or None if the value there isn't one. A
+/// number is turned into a string in place, which can raise an error.
+pub unsafe fn to_string(L: *mut lua_State, idx: c_int) -> Option<String> {
+    let mut len: size_t = 0;
+    let s = lua_tolstring(L, idx, &mut len);
+    if s.is_null() {
+        None
+    } else {
+        Some(::std::string::raw::from_buf_len(s as *const u8, len as uint))
+    }
+}
+
+/// Read an argument that has to be a string. Unlike `to_string`, numbers
+/// aren't turned into strings, so this never raises an error.
+pub unsafe fn string_arg(L: *mut lua_State, idx: c_int) -> Option<String> {
+    if lua_type(L, idx) == LUA_TSTRING {
+        to_string(L, idx)
+    } else {
+        None
+    }
+}
+
+/// Set `t[k]` to the value on top of the stack, where `t` is at `idx`,
+/// without calling any metamethods.
+pub unsafe fn set_field(L: *mut lua_State, idx: c_int, k: &str) {
+    push_str(L, k);
+    lua_insert(L, -2);
+    lua_rawset(L, shifted(idx, 1))
+}
+
+/// Push `t[k]`, where `t` is at `idx`, without calling any metamethods.
+pub unsafe fn get_field(L: *mut lua_State, idx: c_int, k: &str) {
+    push_str(L, k);
+    lua_rawget(L, shifted(idx, 1))
+}
+
+// Where the value at `idx` ends up once `n` more are pushed. Only indexes
+// from the top of the stack move.
+fn shifted(idx: c_int, n: c_int) -> c_int {
+    if idx < 0 && idx > LUA_REGISTRYINDEX { idx - n } else { idx }
+}
+
+/// Set a global to the value on top of the stack.
+pub unsafe fn set_global(L: *mut lua_State, name: &str) {
+    set_field(L, LUA_GLOBALSINDEX, name)
+}
@@ -12,21 +12,93 @@ pub use plugins::uptime::*;
 pub use plugins::describe::*;
 pub use plugins::excuse::*;
 pub use plugins::officers::*;
+#[cfg(feature = "lua")]
+pub use plugins::scripts::*;
+#[cfg(feature = "wasm")]
+pub use plugins::wasm::*;
+pub use plugins::roles::*;
+pub use plugins::admin::*;
+pub use plugins::ignore::*;
+pub use plugins::moderation::*;
+pub use plugins::bans::*;
+pub use plugins::autoop::*;
+pub use plugins::relay::*;
 pub use plugins::default::*;
 
 mod uptime;
 mod describe;
 mod excuse;
 mod officers;
+#[cfg(feature = "lua")]
+mod lua;
+#[cfg(feature = "lua")]
+mod scripts;
+#[cfg(feature = "wasm")]
+mod wasmtime;
+#[cfg(feature = "wasm")]
+mod wasm;
+mod roles;
+mod admin;
+mod ignore;
+mod moderation;
+mod bans;
+mod autoop;
+mod relay;
 mod default;
 
 
 /// Registers all plugins
+///
+/// Plugins are dispatched to in order of priority, and then in the order
+/// they're registered here. Default accepts everything, so it has the lowest
+/// priority.
 pub fn register(irc: &mut Irc) {
     irc.register_plugin(box Describe::new());
     irc.register_plugin(box Uptime::new());
     irc.register_plugin(box Excuse::new());
     irc.register_plugin(box Officers::new());
+    register_scripts(irc);
+    irc.register_plugin(box Roles::new());
+    irc.register_plugin(box Admin::new());
+    irc.register_plugin(box Ignores::new());
+    irc.register_plugin(box Moderation::new());
+    irc.register_plugin(box Bans::new());
+    irc.register_plugin(box AutoOp::new());
+    irc.register_plugin(box Relay::new());
+    for plugin in irc.dynamic_plugins().into_iter() {
+        irc.register_plugin(plugin);
+    }
+    for plugin in irc.process_plugins().into_iter() {
+        irc.register_plugin(plugin);
+    }
+    register_wasm(irc);
     irc.register_plugin(box Default::new());
 }
 
+
+/// Lua scripts need the Lua library, so they're only built with the `lua`
+/// feature.
+#[cfg(feature = "lua")]
+fn register_scripts(irc: &mut Irc) {
+    irc.register_plugin(box Scripts::new());
+}
+
+#[cfg(not(feature = "lua"))]
+fn register_scripts(_: &mut Irc) {}
+
+
+/// WebAssembly plugins need Wasmtime, so they're only built with the `wasm`
+/// feature.
+#[cfg(feature = "wasm")]
+fn register_wasm(irc: &mut Irc) {
+    let dir = match irc.wasm_dir() {
+        Some(dir) => Path::new(dir),
+        None => return,
+    };
+    for plugin in wasm::load_dir(&dir).into_iter() {
+        irc.register_plugin(box plugin);
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+fn register_wasm(_: &mut Irc) {}
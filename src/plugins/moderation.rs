@@ -0,0 +1,571 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Moderation
+//!
+//! Helps keep a channel civil. In moderated channels every message is checked
+//! for flooding, saying the same thing over and over, highlighting lots of
+//! people at once, bad words and links. Anyone caught gets a strike, and the
+//! more strikes they've had in the last day the firmer the response:
+//!
+//! 1. a warning,
+//! 2. a quiet for ten minutes, or a kick on networks with no way to quiet,
+//! 3. a kick,
+//! 4. a ban for an hour.
+//!
+//! Anything past a warning needs the bot to be opped. When it isn't, it warns
+//! instead and asks ChanServ for ops, setting any quiet or ban once it has
+//! them. Anyone voiced or opped is left alone.
+//!
+//! Admins set it up:
+//!
+//!     cleese moderate ##cse-club
+//!     cleese unmoderate ##cse-club
+//!     cleese badwords add darn
+//!     cleese badwords remove darn
+//!     cleese links on
+//!     cleese links allow github.com
+//!
+//! and officers review what it's done:
+//!
+//!     cleese modlog 10
+//!     cleese strikes alice
+//!     cleese pardon alice
+//!
+//! Strikes, settings and the log are kept in storage. Quiets and bans are
+//! recorded like any other, and the bans plugin lifts them when they're up.
+//! Moderated channels and strikes are kept per network, as the same name
+//! elsewhere is someone or somewhere else.
+
+extern crate time;
+
+use std::collections::{HashMap, RingBuf};
+use std::collections::hash_map::{Occupied, Vacant};
+use regex::{Regex, quote};
+
+use irc::{IrcPrivMsg, IrcWriter, IrcCommand, BotInfo, Plugin, Handler};
+use irc::{Role, Namespace, Ban, BAN, QUIET, ban_mask};
+use util::join;
+
+
+// More than this many lines...
+static FLOOD_LINES: uint = 5;
+
+// ...within this many seconds is flooding.
+static FLOOD_SECONDS: i64 = 3;
+
+// The same line this many times in a row...
+static REPEATS: uint = 3;
+
+// ...with no more than this many seconds between each is repeating yourself.
+static REPEAT_WINDOW: i64 = 60;
+
+// Mentioning this many people in one line is mass highlighting.
+static HIGHLIGHTS: uint = 5;
+
+// Strikes older than this, in seconds, are forgotten.
+static STRIKE_WINDOW: i64 = 24 * 60 * 60;
+
+// How long quiets and bans last.
+static QUIET_MINUTES: i64 = 10;
+static BAN_MINUTES: i64 = 60;
+
+// How many actions the log keeps.
+static LOG_SIZE: uint = 200;
+
+
+/// How the plugin is set up, kept in storage.
+#[deriving(Clone, Encodable, Decodable)]
+struct Settings {
+    // Lowercased, as `network/#channel`.
+    channels: Vec<String>,
+    bad_words: Vec<String>,
+    block_links: bool,
+    // Domains links may still go to.
+    allowed_links: Vec<String>,
+}
+
+
+/// Someone being caught.
+#[deriving(Clone, Encodable, Decodable)]
+struct Strike {
+    at: i64,
+    channel: String,
+    reason: String,
+}
+
+
+/// Something the plugin did, for officers to review.
+#[deriving(Clone, Encodable, Decodable)]
+struct Entry {
+    when: String,
+    channel: String,
+    nick: String,
+    mask: String,
+    action: String,
+    reason: String,
+}
+
+impl Entry {
+    fn describe(&self) -> String {
+        format!("{} {}: {} {} ({}) for {}", self.when, self.channel,
+                self.action, self.nick, self.mask, self.reason)
+    }
+}
+
+
+/// What's to be done about someone, mildest first.
+#[deriving(PartialEq, PartialOrd)]
+enum Action {
+    Warn,
+    Quiet,
+    Kick,
+    Ban,
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match *self {
+            Action::Warn => "warned",
+            Action::Quiet => "quieted",
+            Action::Kick => "kicked",
+            Action::Ban => "banned",
+        }
+    }
+}
+
+
+// What someone's said lately in a channel.
+struct Recent {
+    times: RingBuf<i64>,
+    text: String,
+    repeats: uint,
+    // When they last said anything.
+    said: i64,
+}
+
+
+/// Keeps what people have said lately, for spotting floods. Everything else
+/// lives in storage.
+pub struct Moderation {
+    recent: HashMap<String, Recent>,
+    description: &'static str,
+    name: &'static str
+}
+
+impl Moderation {
+    /// Construct the plugin.
+    pub fn new() -> Moderation {
+        Moderation {
+            recent: HashMap::new(),
+            description: "Keeps moderated channels civil. Officers can try \
+                          `cleese modlog`.",
+            name: "moderation"
+        }
+    }
+
+    fn store<'a>(&self, info: &'a BotInfo) -> Namespace<'a> {
+        info.storage.namespace(self.name)
+    }
+
+    fn settings(&self, info: &BotInfo) -> Settings {
+        self.store(info).get("settings").unwrap_or(Settings {
+            channels: Vec::new(),
+            bad_words: Vec::new(),
+            block_links: false,
+            allowed_links: Vec::new(),
+        })
+    }
+
+    fn save_settings(&self, info: &BotInfo, settings: &Settings) {
+        self.store(info).set("settings", settings);
+    }
+
+    /// Work out what, if anything, is wrong with a message.
+    fn check(&mut self, msg: &IrcPrivMsg, settings: &Settings,
+             info: &BotInfo) -> Option<&'static str> {
+        let key = recent_key(info.network, msg.channel.as_slice(),
+                             msg.sender_nick.as_slice());
+        let at = time::get_time().sec;
+        let recent = match self.recent.entry(key) {
+            Vacant(entry) => entry.set(Recent {
+                times: RingBuf::new(),
+                text: String::new(),
+                repeats: 0,
+                said: 0,
+            }),
+            Occupied(entry) => entry.into_mut(),
+        };
+
+        recent.times.push_back(at);
+        while recent.times.len() > FLOOD_LINES {
+            recent.times.pop_front();
+        }
+        if recent.times.len() == FLOOD_LINES
+            && at - *recent.times.front().unwrap() < FLOOD_SECONDS {
+            return Some("flooding");
+        }
+
+        if recent.text == msg.txt && at - recent.said <= REPEAT_WINDOW {
+            recent.repeats += 1;
+        } else {
+            recent.text = msg.txt.clone();
+            recent.repeats = 1;
+        }
+        recent.said = at;
+        if recent.repeats >= REPEATS {
+            return Some("repeating yourself");
+        }
+
+        let members = info.state.members(msg.channel.as_slice());
+        let members: Vec<String> = members.iter().map(|m| {
+            m.to_lowercase()
+        }).collect();
+        let highlights = msg.txt.as_slice().split(|c: char| {
+            !(c.is_alphanumeric() || "-_[]\\`^{}|".contains_char(c))
+        }).filter(|w| {
+            !w.is_empty() && members.contains(&w.to_lowercase())
+        }).count();
+        if highlights >= HIGHLIGHTS {
+            return Some("mass highlighting");
+        }
+
+        if !settings.bad_words.is_empty() {
+            let words: Vec<String> = settings.bad_words.iter().map(|w| {
+                quote(w.as_slice())
+            }).collect();
+            let pattern = format!(r"(?i)\b({})\b", words.connect("|"));
+            match Regex::new(pattern.as_slice()) {
+                Ok(ref re) if re.is_match(msg.txt.as_slice()) => {
+                    return Some("language");
+                },
+                _ => (),
+            }
+        }
+
+        if settings.block_links {
+            let re = regex!(r"(?i)\b(?:https?://|www\.)([^/\s:]+)");
+            for caps in re.captures_iter(msg.txt.as_slice()) {
+                let domain = caps.at(1).to_lowercase();
+                let allowed = settings.allowed_links.iter().any(|d| {
+                    domain == *d || domain.as_slice().ends_with(
+                        format!(".{}", d).as_slice())
+                });
+                if !allowed {
+                    return Some("posting links");
+                }
+            }
+        }
+        None
+    }
+
+    /// Give someone a strike, and do whatever their strikes call for.
+    fn punish(&mut self, msg: &IrcPrivMsg, reason: &str,
+              writer: &IrcWriter, info: &BotInfo) {
+        let chan = msg.channel.as_slice();
+        let nick = msg.sender_nick.as_slice();
+        let mask = ban_mask(msg.sender_info.as_slice());
+        let at = time::get_time().sec;
+
+        let key = strikes_key(info.network, mask.as_slice());
+        let mut strikes: Vec<Strike> = self.store(info).get(key.as_slice())
+                                                       .unwrap_or(Vec::new());
+        strikes.retain(|s| at - s.at < STRIKE_WINDOW);
+        strikes.push(Strike {
+            at: at,
+            channel: chan.to_string(),
+            reason: reason.to_string(),
+        });
+        self.store(info).set(key.as_slice(), &strikes);
+
+        let can_quiet = info.state.quiet(mask.as_slice()).is_some();
+        let wanted = match strikes.len() {
+            1 => Action::Warn,
+            2 if can_quiet => Action::Quiet,
+            2 => Action::Kick,
+            3 => Action::Kick,
+            _ => Action::Ban,
+        };
+        // Without ops, ask ChanServ for them and warn for now. Quiets and bans
+        // are saved as pending, and the bans plugin sets them once we're
+        // opped.
+        let opped = info.state.am_op(chan);
+        if !opped && wanted > Action::Warn {
+            writer.chanserv(format!("OP {}", chan).as_slice());
+        }
+        let action = if opped { wanted } else { Action::Warn };
+        let ban = match wanted {
+            Action::Quiet => Some(Ban::new(chan, mask.as_slice(), QUIET,
+                                           self.name, reason,
+                                           Some(QUIET_MINUTES * 60))),
+            Action::Ban => Some(Ban::new(chan, mask.as_slice(), BAN,
+                                         self.name, reason,
+                                         Some(BAN_MINUTES * 60))),
+            _ => None,
+        };
+        match ban {
+            Some(mut ban) => {
+                if opped {
+                    ban.apply(writer, &info.state);
+                } else {
+                    ban.pending = true;
+                    if wanted == Action::Ban {
+                        ban.kick = Some(nick.to_string());
+                    }
+                }
+                ban.save(&info.storage, info.network);
+            },
+            None => (),
+        }
+        match action {
+            Action::Warn => {
+                let reply = format!("{}: please stop {}.", nick, reason);
+                writer.msg(chan, reply.as_slice());
+            },
+            Action::Kick | Action::Ban => writer.kick(chan, nick, reason),
+            Action::Quiet => (),
+        }
+        // Start counting afresh, so one flood is one strike.
+        self.recent.remove(&recent_key(info.network, chan, nick));
+
+        let action = if action < wanted {
+            format!("{} (not opped, so asked ChanServ)", action.name())
+        } else {
+            action.name().to_string()
+        };
+        self.log(info, Entry {
+            when: time::now_utc().rfc3339().to_string(),
+            channel: chan.to_string(),
+            nick: nick.to_string(),
+            mask: mask,
+            action: action,
+            reason: reason.to_string(),
+        });
+    }
+
+    fn log(&self, info: &BotInfo, entry: Entry) {
+        println!("Moderation: {}", entry.describe());
+        self.store(info).transaction(|tx| {
+            let mut log: Vec<Entry> = tx.get("log").unwrap_or(Vec::new());
+            log.push(entry.clone());
+            while log.len() > LOG_SIZE {
+                log.remove(0);
+            }
+            tx.set("log", &log);
+            true
+        });
+    }
+
+    /// Turn moderation on or off in a channel.
+    fn moderate(&self, cmd: &IrcCommand, info: &BotInfo, on: bool) -> String {
+        let chan = match cmd.args.get(0) {
+            Some(chan) => chan.to_lowercase(),
+            None => return format!("Usage: {} <#channel>", cmd.name),
+        };
+        let key = channel_key(info.network, chan.as_slice());
+        let mut settings = self.settings(info);
+        settings.channels.retain(|c| *c != key);
+        if on {
+            settings.channels.push(key);
+        }
+        self.save_settings(info, &settings);
+        if on {
+            format!("Moderating {}.", chan)
+        } else {
+            format!("No longer moderating {}.", chan)
+        }
+    }
+
+    /// List, add or remove bad words.
+    fn bad_words(&self, cmd: &IrcCommand, info: &BotInfo) -> String {
+        let mut settings = self.settings(info);
+        let args = cmd.args.as_slice();
+        match args {
+            [] => {
+                let words: Vec<&str> = settings.bad_words.iter().map(|w| {
+                    w.as_slice()
+                }).collect();
+                if words.is_empty() {
+                    "No bad words.".to_string()
+                } else {
+                    join(&words, ", ")
+                }
+            },
+            ["add", word] => {
+                let word = word.to_lowercase();
+                if !settings.bad_words.contains(&word) {
+                    settings.bad_words.push(word);
+                }
+                self.save_settings(info, &settings);
+                "Added.".to_string()
+            },
+            ["remove", word] => {
+                settings.bad_words.retain(|w| *w != word.to_lowercase());
+                self.save_settings(info, &settings);
+                "Removed.".to_string()
+            },
+            _ => "Usage: badwords [add|remove <word>]".to_string(),
+        }
+    }
+
+    /// Show or change the link filter.
+    fn links(&self, cmd: &IrcCommand, info: &BotInfo) -> String {
+        let mut settings = self.settings(info);
+        let args = cmd.args.as_slice();
+        match args {
+            [] => {},
+            ["on"] => settings.block_links = true,
+            ["off"] => settings.block_links = false,
+            ["allow", domain] => {
+                let domain = domain.to_lowercase();
+                if !settings.allowed_links.contains(&domain) {
+                    settings.allowed_links.push(domain);
+                }
+            },
+            ["disallow", domain] => {
+                settings.allowed_links.retain(|d| *d != domain.to_lowercase());
+            },
+            _ => {
+                return "Usage: links [on|off|allow <domain>|disallow \
+                        <domain>]".to_string();
+            },
+        }
+        self.save_settings(info, &settings);
+        let allowed: Vec<&str> = settings.allowed_links.iter().map(|d| {
+            d.as_slice()
+        }).collect();
+        format!("Links are {}, except to: {}.",
+                if settings.block_links { "blocked" } else { "allowed" },
+                if allowed.is_empty() {
+                    "nowhere".to_string()
+                } else {
+                    join(&allowed, ", ")
+                })
+    }
+
+    /// Show the last few things the plugin did.
+    fn modlog(&self, cmd: &IrcCommand, info: &BotInfo) -> String {
+        let n = cmd.args.get(0).and_then(|n| from_str(*n)).unwrap_or(5u);
+        let log: Vec<Entry> = self.store(info).get("log")
+                                              .unwrap_or(Vec::new());
+        if log.is_empty() {
+            return "Nothing's been done yet.".to_string();
+        }
+        let skip = if log.len() > n { log.len() - n } else { 0 };
+        let lines: Vec<String> = log.iter().skip(skip).map(|e| {
+            e.describe()
+        }).collect();
+        lines.connect("\n")
+    }
+
+    /// Show or clear someone's strikes.
+    fn strikes(&self, cmd: &IrcCommand, info: &BotInfo) -> String {
+        let who = match cmd.args.get(0) {
+            Some(who) => *who,
+            None => return format!("Usage: {} <nick|mask>", cmd.name),
+        };
+        let mask = if who.contains_char('@') {
+            who.to_string()
+        } else {
+            match info.state.hostmask(who) {
+                Some(h) => ban_mask(h.as_slice()),
+                None => return format!("I haven't seen {}.", who),
+            }
+        };
+        let key = strikes_key(info.network, mask.as_slice());
+        if cmd.name == "pardon" {
+            return if self.store(info).delete(key.as_slice()) {
+                format!("Cleared the strikes for {}.", mask)
+            } else {
+                format!("{} has no strikes.", mask)
+            };
+        }
+        let strikes: Vec<Strike> = self.store(info).get(key.as_slice())
+                                                   .unwrap_or(Vec::new());
+        if strikes.is_empty() {
+            return format!("{} has no strikes.", mask);
+        }
+        let now = time::get_time().sec;
+        let lines: Vec<String> = strikes.iter().map(|s| {
+            format!("{}m ago in {}: {}", (now - s.at) / 60, s.channel,
+                    s.reason)
+        }).collect();
+        lines.connect("\n")
+    }
+}
+
+
+// What a channel is kept under in the settings.
+fn channel_key(network: &str, chan: &str) -> String {
+    format!("{}/{}", network, chan).as_slice().to_lowercase()
+}
+
+// What someone's strikes are kept under.
+fn strikes_key(network: &str, mask: &str) -> String {
+    format!("strikes:{}/{}", network, mask)
+}
+
+// What someone's recent lines in a channel are kept under.
+fn recent_key(network: &str, chan: &str, nick: &str) -> String {
+    format!("{} {} {}", network, chan, nick).as_slice().to_lowercase()
+}
+
+
+impl Plugin for Moderation {
+    /// Respond to private messages.
+    ///
+    /// Checks messages in moderated channels, and deals with anyone breaking
+    /// the rules. Other plugins still get the message.
+    fn privmsg(&mut self, msg: &IrcPrivMsg,
+               writer: &IrcWriter, info: &BotInfo) -> Handler {
+        let chan = msg.channel.as_slice();
+        let nick = msg.sender_nick.as_slice();
+        let settings = self.settings(info);
+        if !settings.channels.contains(&channel_key(info.network, chan))
+            || info.state.is_me(nick)
+            || info.state.is_privileged(chan, nick) {
+            return Handler::Passed;
+        }
+        match self.check(msg, &settings, info) {
+            Some(reason) => {
+                self.punish(msg, reason, writer, info);
+                Handler::Observed
+            },
+            None => Handler::Passed,
+        }
+    }
+
+    /// Respond to received commands.
+    ///
+    /// Called by the plugin subsystem when a command is encountered. Replies
+    /// go to the sender.
+    fn cmd(&mut self, cmd: &IrcCommand,
+           writer: &IrcWriter, info: &BotInfo) -> Handler {
+        let reply = match cmd.name {
+            "moderate" => self.moderate(cmd, info, true),
+            "unmoderate" => self.moderate(cmd, info, false),
+            "badwords" => self.bad_words(cmd, info),
+            "links" => self.links(cmd, info),
+            "modlog" => self.modlog(cmd, info),
+            "strikes" | "pardon" => self.strikes(cmd, info),
+            _ => return Handler::Passed,
+        };
+        writer.msg(cmd.sender, reply.as_slice());
+        Handler::Accepted
+    }
+
+    /// Setting things up is for admins, and reviewing for officers.
+    fn required_role(&self, cmd: &str) -> Role {
+        match cmd {
+            "moderate" | "unmoderate" | "badwords" | "links" => Role::Admin,
+            "modlog" | "strikes" | "pardon" => Role::Officer,
+            _ => Role::Everyone,
+        }
+    }
+
+    /// Return the plugin description.
+    fn help(&self) -> &'static str { self.description }
+
+    /// Return the plugin name.
+    fn name(&self) -> &'static str { self.name }
+}
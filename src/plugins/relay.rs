@@ -0,0 +1,640 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Relay
+//!
+//! Mirrors what's said in one channel into another, on another network or the
+//! same one. Admins link channels, written `network/#channel`, or just
+//! `#channel` for the network the command was sent on:
+//!
+//!     cleese relay add freenode/##cse-club oftc/#cse-club
+//!     cleese relay add ##cse-club ##cse-club-log oneway
+//!     cleese relay list
+//!     cleese relay remove 2
+//!     cleese relay filter ^!
+//!     cleese relay unfilter ^!
+//!     cleese relay ignore otherbot
+//!     cleese relay unignore otherbot
+//!
+//! Links go both ways unless they're `oneway`, and the bot has to be in both
+//! channels. Lines are relayed as `[oftc] <alice> hello`, labelled with the
+//! network they came from, or the channel when both are on the same network.
+//! Joins and parts are summed up once a minute rather than relayed one by one,
+//! naming a handful of people at most. Long lines are split to fit.
+//!
+//! Each link carries a few lines every few seconds at most, so a flood in one
+//! channel can't get the bot thrown off another network. Lines over that
+//! aren't relayed, and the next summary says how many there were.
+//!
+//! Lines matching a filter, and anything from an ignored nick, aren't relayed.
+//! The bot never sees its own lines, so links can't echo between channels it
+//! relays, and it won't pass on anything that looks relayed already, so two
+//! relay bots can't echo between each other either.
+
+extern crate time;
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use regex::Regex;
+
+use irc::{IrcPrivMsg, IrcWriter, IrcCommand, BotInfo, Plugin, Handler};
+use irc::{Role, Timer, Event, Namespace, Departure};
+use util::wrap;
+
+
+// How often joins and parts are summed up, in seconds.
+static SUMMARY_SECONDS: i64 = 60;
+
+// How many nicks a summary names before it just counts the rest.
+static SUMMARY_NICKS: uint = 10;
+
+// The longest line relayed, in bytes, leaving room for the rest of the
+// PRIVMSG in the server's 512.
+static MAX_LINE: uint = 400;
+
+// How many lines a link carries...
+static LINK_LINES: uint = 5;
+
+// ...in this many seconds, at most.
+static LINK_SECONDS: i64 = 10;
+
+
+/// A channel relayed into another.
+#[deriving(Clone, PartialEq, Encodable, Decodable)]
+struct Link {
+    from_network: String,
+    from_channel: String,
+    to_network: String,
+    to_channel: String,
+}
+
+impl Link {
+    /// Does this link carry what's said in a channel?
+    fn carries(&self, network: &str, chan: &str) -> bool {
+        self.from_network.as_slice() == network
+            && self.from_channel.as_slice().to_lowercase()
+                == chan.to_lowercase()
+    }
+
+    /// How relayed lines say where they're from: the network when it's
+    /// another one, or else the channel.
+    fn label(&self) -> &str {
+        if self.from_network == self.to_network {
+            self.from_channel.as_slice()
+        } else {
+            self.from_network.as_slice()
+        }
+    }
+}
+
+
+/// How the plugin is set up, kept in storage.
+#[deriving(Clone, Encodable, Decodable)]
+struct Settings {
+    links: Vec<Link>,
+    // Lines matching any of these aren't relayed.
+    filters: Vec<String>,
+    // Lowercased nicks that aren't relayed, like other relay bots.
+    ignored: Vec<String>,
+}
+
+
+/// The joins and parts to sum up in a channel.
+struct Summary {
+    network: String,
+    channel: String,
+    label: String,
+    joined: Vec<String>,
+    left: Vec<String>,
+}
+
+
+/// The lines sent down a link lately.
+struct Sent {
+    network: String,
+    channel: String,
+    label: String,
+    // When the current window started, and the lines sent in it.
+    since: i64,
+    lines: uint,
+    // Lines not relayed since the last summary.
+    dropped: uint,
+}
+
+
+/// Keeps the joins and parts waiting to be summed up, what's been sent down
+/// each link lately and the channels the bot is in on every network.
+/// Everything else lives in storage.
+pub struct Relay {
+    summaries: Vec<Summary>,
+    sent: HashMap<String, Sent>,
+    // Lowercased `network/#channel`.
+    joined: HashSet<String>,
+    description: &'static str,
+    name: &'static str
+}
+
+impl Relay {
+    /// Construct the plugin.
+    pub fn new() -> Relay {
+        Relay {
+            summaries: Vec::new(),
+            sent: HashMap::new(),
+            joined: HashSet::new(),
+            description: "Relays channels into each other. Admins can try \
+                          `cleese relay list`.",
+            name: "relay"
+        }
+    }
+
+    fn store<'a>(&self, info: &'a BotInfo) -> Namespace<'a> {
+        info.storage.namespace(self.name)
+    }
+
+    fn settings(&self, info: &BotInfo) -> Settings {
+        self.store(info).get("settings").unwrap_or(Settings {
+            links: Vec::new(),
+            filters: Vec::new(),
+            ignored: Vec::new(),
+        })
+    }
+
+    fn save_settings(&self, info: &BotInfo, settings: &Settings) {
+        self.store(info).set("settings", settings);
+    }
+
+    /// Send lines down a link, unless it's carried too many lately.
+    fn send(&mut self, link: &Link, lines: Vec<String>, writer: &IrcWriter) {
+        let at = time::get_time().sec;
+        let key = format!("{}/{} {}", link.to_network,
+                          link.to_channel.as_slice().to_lowercase(),
+                          link.label());
+        if !self.sent.contains_key(&key) {
+            self.sent.insert(key.clone(), Sent {
+                network: link.to_network.clone(),
+                channel: link.to_channel.clone(),
+                label: link.label().to_string(),
+                since: at,
+                lines: 0,
+                dropped: 0,
+            });
+        }
+        let sent = self.sent.get_mut(&key).unwrap();
+        if at - sent.since >= LINK_SECONDS {
+            sent.since = at;
+            sent.lines = 0;
+        }
+        let writer = writer.on(link.to_network.as_slice());
+        for line in lines.iter() {
+            if sent.lines >= LINK_LINES {
+                sent.dropped += 1;
+                continue;
+            }
+            sent.lines += 1;
+            writer.msg(link.to_channel.as_slice(), line.as_slice());
+        }
+    }
+
+    /// Pass a line on down every link from its channel.
+    fn relay(&mut self, msg: &IrcPrivMsg, writer: &IrcWriter,
+             info: &BotInfo) {
+        let nick = msg.sender_nick.as_slice();
+        let txt = msg.txt.as_slice();
+        let settings = self.settings(info);
+        if info.state.is_me(nick)
+            || settings.ignored.contains(&nick.to_lowercase())
+            || looks_relayed(txt)
+            || filtered(&settings, txt) {
+            return;
+        }
+        let links = settings.links.iter().filter(|l| {
+            l.carries(info.network, msg.channel.as_slice())
+        });
+        for link in links {
+            let (head, txt) = match action(txt) {
+                Some(act) => (format!("[{}] * {} ", link.label(), nick), act),
+                None => (format!("[{}] <{}> ", link.label(), nick), txt),
+            };
+            let width = if head.len() < MAX_LINE / 2 {
+                MAX_LINE - head.len()
+            } else {
+                MAX_LINE / 2
+            };
+            let lines = wrap(txt, width).iter().map(|part| {
+                format!("{}{}", head, part)
+            }).collect();
+            self.send(link, lines, writer);
+        }
+    }
+
+    /// Note someone joining or leaving a channel, for the next summary.
+    /// Coming and going before then cancels out.
+    fn note(&mut self, chan: &str, nick: &str, joined: bool, info: &BotInfo) {
+        let settings = self.settings(info);
+        if info.state.is_me(nick)
+            || settings.ignored.contains(&nick.to_lowercase()) {
+            return;
+        }
+        let nick = nick.to_string();
+        for link in settings.links.iter() {
+            if !link.carries(info.network, chan) {
+                continue;
+            }
+            let pos = self.summaries.iter().position(|s| {
+                s.network == link.to_network
+                    && s.channel.as_slice().to_lowercase()
+                        == link.to_channel.as_slice().to_lowercase()
+                    && s.label.as_slice() == link.label()
+            });
+            let i = match pos {
+                Some(i) => i,
+                None => {
+                    self.summaries.push(Summary {
+                        network: link.to_network.clone(),
+                        channel: link.to_channel.clone(),
+                        label: link.label().to_string(),
+                        joined: Vec::new(),
+                        left: Vec::new(),
+                    });
+                    self.summaries.len() - 1
+                },
+            };
+            let summary = &mut self.summaries[i];
+            let (add, cancel) = if joined {
+                (&mut summary.joined, &mut summary.left)
+            } else {
+                (&mut summary.left, &mut summary.joined)
+            };
+            match cancel.iter().position(|n| *n == nick) {
+                Some(i) => { cancel.remove(i); },
+                None if !add.contains(&nick) => add.push(nick.clone()),
+                None => (),
+            }
+        }
+    }
+
+    /// Send the summaries that have built up, and say how many lines each
+    /// link had to drop.
+    fn summarize(&mut self, writer: &IrcWriter) {
+        for s in self.summaries.iter() {
+            let mut parts = Vec::new();
+            if !s.joined.is_empty() {
+                parts.push(format!("joined: {}", some_of(&s.joined)));
+            }
+            if !s.left.is_empty() {
+                parts.push(format!("left: {}", some_of(&s.left)));
+            }
+            if parts.is_empty() {
+                continue;
+            }
+            let head = format!("[{}] ", s.label);
+            let writer = writer.on(s.network.as_slice());
+            let body = parts.connect("; ");
+            for part in wrap(body.as_slice(), MAX_LINE - head.len()).iter() {
+                let line = format!("{}{}", head, part);
+                writer.msg(s.channel.as_slice(), line.as_slice());
+            }
+        }
+        self.summaries.clear();
+        for s in self.sent.values() {
+            if s.dropped > 0 {
+                let line = format!("[{}] {} lines weren't relayed, as they \
+                                    came too fast.", s.label, s.dropped);
+                writer.on(s.network.as_slice())
+                      .msg(s.channel.as_slice(), line.as_slice());
+            }
+        }
+        self.sent.clear();
+    }
+
+    /// Is the bot in a channel, on this network or another?
+    fn is_in(&self, network: &str, chan: &str, info: &BotInfo) -> bool {
+        if network == info.network {
+            info.state.channel(chan).is_some()
+        } else {
+            let key = format!("{}/{}", network, chan);
+            self.joined.contains(&key.as_slice().to_lowercase())
+        }
+    }
+
+    /// Link two channels, one way or both.
+    fn add(&self, cmd: &IrcCommand, info: &BotInfo) -> String {
+        let usage = "Usage: relay add <[network/]#from> <[network/]#to> \
+                     [oneway]";
+        let (from, to) = match (cmd.args.get(1), cmd.args.get(2)) {
+            (Some(from), Some(to)) => (*from, *to),
+            _ => return usage.to_string(),
+        };
+        let oneway = match cmd.args.get(3).map(|a| *a) {
+            Some("oneway") => true,
+            Some(_) => return usage.to_string(),
+            None => false,
+        };
+        let (from_network, from_channel) = endpoint(from, info.network);
+        let (to_network, to_channel) = endpoint(to, info.network);
+        for network in [&from_network, &to_network].iter() {
+            if !info.networks.iter().any(|n| *n == network.as_slice()) {
+                return format!("I'm not on a network called {}.", network);
+            }
+        }
+        let missing = {
+            let ends = [(&from_network, &from_channel),
+                        (&to_network, &to_channel)];
+            ends.iter().find(|&&(n, c)| {
+                !self.is_in(n.as_slice(), c.as_slice(), info)
+            }).map(|&(n, c)| format!("I'm not in {}/{}.", n, c))
+        };
+        match missing {
+            Some(reply) => return reply,
+            None => (),
+        }
+        let link = Link {
+            from_network: from_network,
+            from_channel: from_channel,
+            to_network: to_network,
+            to_channel: to_channel,
+        };
+        if link.carries(link.to_network.as_slice(),
+                        link.to_channel.as_slice()) {
+            return "I can't relay a channel into itself.".to_string();
+        }
+        let back = Link {
+            from_network: link.to_network.clone(),
+            from_channel: link.to_channel.clone(),
+            to_network: link.from_network.clone(),
+            to_channel: link.from_channel.clone(),
+        };
+
+        let mut settings = self.settings(info);
+        let mut added = Vec::new();
+        if !settings.links.contains(&link) {
+            added.push(link.clone());
+        }
+        if !oneway && !settings.links.contains(&back) {
+            added.push(back);
+        }
+        if added.is_empty() {
+            return "They're already linked.".to_string();
+        }
+        settings.links.push_all(added.as_slice());
+        self.save_settings(info, &settings);
+        let way = if oneway { "to" } else { "and" };
+        format!("Relaying {}/{} {} {}/{}.", link.from_network,
+                link.from_channel, way, link.to_network, link.to_channel)
+    }
+
+    /// Unlink the numbered link from `relay list`.
+    fn remove(&self, cmd: &IrcCommand, info: &BotInfo) -> String {
+        let n: uint = match cmd.args.get(1).and_then(|n| from_str(*n)) {
+            Some(n) => n,
+            None => return "Usage: relay remove <number>".to_string(),
+        };
+        let mut settings = self.settings(info);
+        if n == 0 || n > settings.links.len() {
+            return format!("There's no link {}.", n);
+        }
+        let link = settings.links.remove(n - 1).unwrap();
+        self.save_settings(info, &settings);
+        format!("No longer relaying {}/{} to {}/{}.", link.from_network,
+                link.from_channel, link.to_network, link.to_channel)
+    }
+
+    /// Add or remove a filter, or someone to ignore.
+    fn change(&self, cmd: &IrcCommand, info: &BotInfo) -> String {
+        let what = cmd.args.get(0).map(|w| *w).unwrap_or("");
+        let arg = match cmd.args.get(1) {
+            Some(_) => cmd.args.slice_from(1).connect(" "),
+            None => {
+                let thing = if what.contains("filter") {
+                    "pattern"
+                } else {
+                    "nick"
+                };
+                return format!("Usage: relay {} <{}>", what, thing);
+            },
+        };
+        let mut settings = self.settings(info);
+        let reply = match what {
+            "filter" => {
+                match Regex::new(arg.as_slice()) {
+                    Ok(_) => (),
+                    Err(e) => return format!("That pattern won't do: {}", e),
+                }
+                if !settings.filters.contains(&arg) {
+                    settings.filters.push(arg.clone());
+                }
+                format!("Lines matching {} won't be relayed.", arg)
+            },
+            "unfilter" => {
+                settings.filters.retain(|f| *f != arg);
+                format!("Lines matching {} will be relayed.", arg)
+            },
+            "ignore" => {
+                let nick = arg.as_slice().to_lowercase();
+                if !settings.ignored.contains(&nick) {
+                    settings.ignored.push(nick);
+                }
+                format!("{} won't be relayed.", arg)
+            },
+            _ => {
+                let nick = arg.as_slice().to_lowercase();
+                settings.ignored.retain(|n| *n != nick);
+                format!("{} will be relayed.", arg)
+            },
+        };
+        self.save_settings(info, &settings);
+        reply
+    }
+
+    /// Show the links, filters and ignored nicks.
+    fn list(&self, info: &BotInfo) -> String {
+        let settings = self.settings(info);
+        let links = settings.links.iter().enumerate();
+        let mut lines: Vec<String> = links.map(|(i, l)| {
+            format!("{}. {}/{} to {}/{}", i + 1, l.from_network,
+                    l.from_channel, l.to_network, l.to_channel)
+        }).collect();
+        if lines.is_empty() {
+            lines.push("Nothing is relayed.".to_string());
+        }
+        if !settings.filters.is_empty() {
+            lines.push(format!("Filtered: {}", settings.filters.connect(" ")));
+        }
+        if !settings.ignored.is_empty() {
+            lines.push(format!("Ignored: {}", settings.ignored.connect(", ")));
+        }
+        lines.connect("\n")
+    }
+}
+
+
+/// Read `network/#channel`, or `#channel` on the network we're on.
+fn endpoint(s: &str, network: &str) -> (String, String) {
+    match s.find('/') {
+        Some(i) if !s.starts_with("#") && !s.starts_with("&") => {
+            (s.slice_to(i).to_string(), s.slice_from(i + 1).to_string())
+        },
+        _ => (network.to_string(), s.to_string()),
+    }
+}
+
+/// A list of nicks, naming only the first few.
+fn some_of(nicks: &Vec<String>) -> String {
+    if nicks.len() <= SUMMARY_NICKS {
+        return nicks.connect(", ");
+    }
+    format!("{} and {} more", nicks.slice_to(SUMMARY_NICKS).connect(", "),
+            nicks.len() - SUMMARY_NICKS)
+}
+
+/// What someone did, if a line is a CTCP ACTION.
+fn action(txt: &str) -> Option<&str> {
+    if txt.starts_with("\x01ACTION ") {
+        Some(txt.slice_from(8).trim_right_chars('\x01'))
+    } else {
+        None
+    }
+}
+
+/// Does a line look like it was relayed, by us or another bot?
+fn looks_relayed(txt: &str) -> bool {
+    regex!(r"^\[\S+\] (<\S+>|\* \S+) ").is_match(txt)
+}
+
+/// Does a line match any of the filters?
+fn filtered(settings: &Settings, txt: &str) -> bool {
+    settings.filters.iter().any(|f| {
+        match Regex::new(f.as_slice()) {
+            Ok(re) => re.is_match(txt),
+            Err(_) => false,
+        }
+    })
+}
+
+
+impl Plugin for Relay {
+    /// Relay channel messages down their links. Everyone else still sees
+    /// them.
+    fn privmsg(&mut self, msg: &IrcPrivMsg,
+               writer: &IrcWriter, info: &BotInfo) -> Handler {
+        self.relay(msg, writer, info);
+        Handler::Passed
+    }
+
+    /// Respond to received commands.
+    ///
+    /// Called by the plugin subsystem when a command is encountered. It
+    /// responds to "relay", replying to the sender.
+    fn cmd(&mut self, cmd: &IrcCommand,
+           writer: &IrcWriter, info: &BotInfo) -> Handler {
+        if cmd.name != "relay" {
+            return Handler::Passed;
+        }
+        let reply = match cmd.args.get(0).map(|a| *a) {
+            Some("add") => self.add(cmd, info),
+            Some("remove") => self.remove(cmd, info),
+            Some("filter") | Some("unfilter") | Some("ignore")
+                | Some("unignore") => self.change(cmd, info),
+            Some("list") | None => self.list(info),
+            Some(_) => "Usage: relay add|remove|list|filter|unfilter|ignore|\
+                        unignore".to_string(),
+        };
+        writer.msg(cmd.sender, reply.as_slice());
+        Handler::Accepted
+    }
+
+    /// Sum up joins and parts every so often.
+    fn startup(&mut self, writer: &IrcWriter, _: &BotInfo) {
+        writer.schedule(Timer::every(self.name, "summary",
+                                     Duration::seconds(SUMMARY_SECONDS)));
+    }
+
+    /// Send the summaries.
+    fn timer(&mut self, _: &str, writer: &IrcWriter, _: &BotInfo) {
+        self.summarize(writer);
+    }
+
+    /// Note joins for the summary, and keep track of the bot's own.
+    fn join(&mut self, channel: &str, nick: &str,
+            _: &IrcWriter, info: &BotInfo) {
+        if info.state.is_me(nick) {
+            let key = format!("{}/{}", info.network, channel);
+            self.joined.insert(key.as_slice().to_lowercase());
+        }
+        self.note(channel, nick, true, info);
+    }
+
+    fn subscriptions(&self) -> Vec<&'static str> {
+        vec!["channel.left"]
+    }
+
+    /// Note parts, kicks and quits for the summary, and keep track of the
+    /// bot's own.
+    fn event(&mut self, event: &Event, _: &IrcWriter, info: &BotInfo) {
+        match event.payload::<Departure>() {
+            Some(d) => {
+                if info.state.is_me(d.nick.as_slice()) {
+                    let key = format!("{}/{}", info.network, d.channel);
+                    self.joined.remove(&key.as_slice().to_lowercase());
+                }
+                self.note(d.channel.as_slice(), d.nick.as_slice(), false,
+                          info)
+            },
+            None => (),
+        }
+    }
+
+    /// Setting up relays is for admins.
+    fn required_role(&self, cmd: &str) -> Role {
+        match cmd {
+            "relay" => Role::Admin,
+            _ => Role::Everyone,
+        }
+    }
+
+    /// Return the plugin description.
+    fn help(&self) -> &'static str { self.description }
+
+    /// Return the plugin name.
+    fn name(&self) -> &'static str { self.name }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{endpoint, looks_relayed, some_of, SUMMARY_NICKS};
+
+    fn pair(network: &str, chan: &str) -> (String, String) {
+        (network.to_string(), chan.to_string())
+    }
+
+    #[test]
+    fn reads_endpoints() {
+        assert_eq!(endpoint("#cse", "here"), pair("here", "#cse"));
+        assert_eq!(endpoint("oftc/#cse", "here"), pair("oftc", "#cse"));
+        assert_eq!(endpoint("&local", "here"), pair("here", "&local"));
+        // A slash in a channel name doesn't make it a network.
+        assert_eq!(endpoint("#a/b", "here"), pair("here", "#a/b"));
+    }
+
+    #[test]
+    fn spots_relayed_lines() {
+        assert!(looks_relayed("[oftc] <bob> hi"));
+        assert!(looks_relayed("[oftc] * bob waves"));
+        assert!(!looks_relayed("<bob> hi"));
+        assert!(!looks_relayed("[oftc]<bob> hi"));
+        assert!(!looks_relayed("[not a relay] <bob> hi"));
+    }
+
+    #[test]
+    fn names_a_few_nicks() {
+        let few = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(some_of(&few), "a, b".to_string());
+        let many: Vec<String> = range(0, SUMMARY_NICKS + 3).map(|i| {
+            format!("n{}", i)
+        }).collect();
+        let named = some_of(&many);
+        assert!(named.as_slice().starts_with("n0, n1, "));
+        assert!(named.as_slice().ends_with(" and 3 more"));
+    }
+}
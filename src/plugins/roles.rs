@@ -0,0 +1,147 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Roles
+//!
+//! Hands out and takes away roles at runtime. Admins can grant and revoke any
+//! role below their own, and owners can grant and revoke anything:
+//!
+//!     cleese grant officer alice
+//!     cleese grant trusted *!*@club.example.org #cse-club
+//!     cleese revoke alice
+//!
+//! Grants only apply on the network they're made on. Anyone can ask what role
+//! they have with `cleese role`.
+
+use irc::{IrcPrivMsg, IrcWriter, IrcCommand, BotInfo, Plugin, Handler};
+use irc::{Role, Grant};
+
+
+/// Only has the minimum fields, as the grants live in storage.
+pub struct Roles {
+    description: &'static str,
+    name: &'static str
+}
+
+impl Roles {
+    /// Construct the plugin.
+    pub fn new() -> Roles {
+        Roles {
+            description: "Manage who can do what. Try `cleese role`.",
+            name: "roles"
+        }
+    }
+
+    /// Grant a role, if the sender outranks it.
+    fn grant(&self, cmd: &IrcCommand, info: &BotInfo) -> String {
+        if cmd.args.len() < 2 {
+            return "Usage: grant <role> <who> [#channel]".to_string();
+        }
+        let who = cmd.args[1];
+        let role = match Role::parse(cmd.args[0]) {
+            Some(role) => role,
+            None => return format!("I don't know the role {}.", cmd.args[0]),
+        };
+        let mine = info.role(cmd);
+        if mine != Role::Owner && role >= mine {
+            return format!("You can't grant {}.", role.name());
+        }
+        let channel = cmd.args.get(2).map(|c| c.to_string());
+        let reply = match channel {
+            Some(ref c) => format!("{} is now {} in {}.", who, role.name(), c),
+            None => format!("{} is now {}.", who, role.name()),
+        };
+        let grant = Grant {
+            who: who.to_string(),
+            role: role,
+            network: info.network.to_string(),
+            channel: channel,
+        };
+        grant.save(&info.storage);
+        reply
+    }
+
+    /// Take away a role granted at runtime, if the sender outranks it, just
+    /// as they'd have to to grant it.
+    fn revoke(&self, cmd: &IrcCommand, info: &BotInfo) -> String {
+        let who = match cmd.args.get(0) {
+            Some(who) => *who,
+            None => return "Usage: revoke <who> [#channel]".to_string(),
+        };
+        let channel = cmd.args.get(1).map(|c| *c);
+        let mine = info.role(cmd);
+        let grants = Grant::all(&info.storage, info.network);
+        let outranked = grants.iter().find(|g| {
+            g.who.as_slice() == who
+                && g.channel.as_ref().map(|c| c.as_slice()) == channel
+                && mine != Role::Owner && g.role >= mine
+        });
+        match outranked {
+            Some(g) => return format!("You can't revoke {}.", g.role.name()),
+            None => (),
+        }
+        if Grant::revoke(&info.storage, info.network, who, channel) {
+            format!("{} no longer has that role.", who)
+        } else {
+            format!("{} wasn't granted anything there.", who)
+        }
+    }
+
+    /// List every grant made at runtime on this network.
+    fn list(&self, info: &BotInfo) -> String {
+        let grants = Grant::all(&info.storage, info.network);
+        if grants.is_empty() {
+            return "Nothing has been granted.".to_string();
+        }
+        let lines: Vec<String> = grants.iter().map(|g| {
+            match g.channel {
+                Some(ref c) => format!("{}: {} in {}", g.who, g.role.name(), c),
+                None => format!("{}: {}", g.who, g.role.name()),
+            }
+        }).collect();
+        lines.connect("\n")
+    }
+}
+
+impl Plugin for Roles {
+    /// Respond to private messages.
+    ///
+    /// Called by the plugin subsystem when a private message is received. It
+    /// currently does nothing.
+    fn privmsg(&mut self, _: &IrcPrivMsg,
+               _: &IrcWriter, _: &BotInfo) -> Handler {
+        Handler::Passed
+    }
+
+    /// Respond to received commands.
+    ///
+    /// Called by the plugin subsystem when a command is encountered. It
+    /// responds to "role", "roles", "grant" and "revoke". Replies go to the
+    /// sender, so the channel isn't flooded.
+    fn cmd(&mut self, cmd: &IrcCommand,
+           writer: &IrcWriter, info: &BotInfo) -> Handler {
+        let reply = match cmd.name {
+            "role" => format!("You're {} here.", info.role(cmd).name()),
+            "roles" => self.list(info),
+            "grant" => self.grant(cmd, info),
+            "revoke" => self.revoke(cmd, info),
+            _ => return Handler::Passed,
+        };
+        writer.msg(cmd.sender, reply.as_slice());
+        Handler::Accepted
+    }
+
+    /// Managing roles is for admins.
+    fn required_role(&self, cmd: &str) -> Role {
+        match cmd {
+            "roles" | "grant" | "revoke" => Role::Admin,
+            _ => Role::Everyone,
+        }
+    }
+
+    /// Return the plugin description.
+    fn help(&self) -> &'static str { self.description }
+
+    /// Return the plugin name.
+    fn name(&self) -> &'static str { self.name }
+}
@@ -0,0 +1,859 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Scripts
+//!
+//! Lets club members write small commands in Lua without touching Rust. Every
+//! `.lua` file in the configured `scripts_dir` gets its own Lua state with only
+//! the base, string, table and math libraries, so a script can't reach the
+//! file system or any other script. Every call into a script is limited in how
+//! much memory and how many instructions it can use.
+//!
+//! Scripts register what they respond to through the `cleese` table, and talk
+//! back through the `irc` table, which has `irc.msg(target, text)` and
+//! `irc.join(channel)`:
+//!
+//! ```lua
+//! cleese.command("roll", "Roll a six-sided die", function(cmd)
+//!     irc.msg(cmd.channel, cmd.sender .. " rolled a " .. math.random(6))
+//!     return true
+//! end)
+//!
+//! cleese.every("stretch", 3600, function()
+//!     irc.msg(bot.channels[1], "Remember to stretch!")
+//! end)
+//! ```
+//!
+//! Commands get a table like `IrcCommand` (`name`, `args`, `channel` and
+//! `sender`), `cleese.on_privmsg` handlers get one like `IrcPrivMsg`
+//! (`sender_nick`, `sender_info`, `channel` and `txt`), and the `bot` table
+//! holds the fields of `BotInfo`, including the `network` a handler was
+//! called for. Handlers return true to accept what they were given.
+//! `cleese.after` and `cleese.cron` start the other kinds of timers, and
+//! `cleese.cancel` stops one. Arguments have to be strings where strings are
+//! expected; numbers aren't turned into them.
+//!
+//! Scripts aren't trusted with anything that could take over the bot. A
+//! target with spaces, line breaks or a leading `:` in it, which could sneak a
+//! command of its own past `irc.msg`, is refused, and so are services like
+//! NickServ. `irc.join` only works from a command an admin sent, and scripts
+//! can't change the bot's nick.
+//!
+//! `cleese.get(key)` and `cleese.set(key, value)` keep strings in storage,
+//! in a namespace of the script's own. Setting a key to nil deletes it. Each
+//! script can keep up to 100 keys and 64KB between them, and anything set past
+//! that is refused. Every change is written out to disk, so only the first 10
+//! per call are kept.
+//!
+//! Scripts are reloaded whenever their files change, or when an admin runs
+//! `cleese scripts reload`. A script that fails to load keeps running its old
+//! version.
+
+#![allow(non_snake_case)]
+
+use libc::{c_int, c_void, size_t};
+use libc;
+use std::c_str::ToCStr;
+use std::collections::HashMap;
+use std::io::File;
+use std::io::fs;
+use std::mem;
+use std::ptr;
+use std::time::Duration;
+
+use irc::{IrcPrivMsg, IrcWriter, IrcCommand, BotInfo, Plugin, Handler, Timer};
+use irc::{Storage, Role};
+use plugins::lua::*;
+use util::clean_text;
+
+
+/// How much memory each script may use, in bytes.
+static MEMORY_LIMIT: size_t = 4 * 1024 * 1024;
+
+/// How many instructions a script may run per call.
+static INSTRUCTION_LIMIT: uint = 10000000;
+
+/// How often, in instructions, the limit is checked.
+static CHECK_EVERY: c_int = 1000;
+
+/// How many keys each script may keep in storage.
+static STORAGE_KEYS: uint = 100;
+
+/// How many bytes of keys and values each script may keep in storage.
+static STORAGE_BYTES: uint = 64 * 1024;
+
+/// How many times a script may set or delete a key per call.
+static WRITE_LIMIT: uint = 10;
+
+/// How often, in seconds, the scripts directory is checked for changes.
+static WATCH_INTERVAL: i64 = 5;
+
+/// The plugin name, which also owns every script's timers.
+static NAME: &'static str = "scripts";
+
+
+/// How much a script has used. Lua's allocator and the instruction hook both
+/// get at this through the state's allocator data. The memory limit is only
+/// enforced while the script itself runs, so the bot setting up a call can
+/// never be the one that runs out.
+struct Limits {
+    memory: size_t,
+    steps: uint,
+    enforce: bool,
+}
+
+/// Something a script asked for, carried out once the script returns.
+enum Action {
+    Msg(String, String),
+    Join(String),
+    Schedule(Timer),
+    Cancel(String),
+}
+
+/// Everything a script has registered. Boxed so the Lua closures can hold on
+/// to a pointer to it.
+struct Host {
+    script: String,
+    limits: Limits,
+    commands: Vec<(String, String, c_int)>,
+    privmsg: Vec<c_int>,
+    timers: HashMap<String, c_int>,
+    actions: Vec<Action>,
+    // Only set while the script is running.
+    storage: *const Storage,
+    // How many keys the running call has set or deleted.
+    writes: uint,
+    // Whether the script is running a command from an admin.
+    admin: bool,
+}
+
+/// A single loaded script and its Lua state.
+struct Script {
+    path: Path,
+    modified: u64,
+    state: *mut lua_State,
+    host: Box<Host>,
+}
+
+impl Script {
+    /// Load and run the script at `path`, which registers its handlers.
+    fn load(path: &Path, info: &BotInfo) -> Result<Script, String> {
+        let code = match File::open(path).read_to_string() {
+            Ok(code) => code,
+            Err(e) => return Err(format!("{}", e)),
+        };
+
+        let mut host = box Host {
+            script: path.filestem_str().unwrap_or("script").to_string(),
+            limits: Limits { memory: 0, steps: 0, enforce: false },
+            commands: Vec::new(),
+            privmsg: Vec::new(),
+            timers: HashMap::new(),
+            actions: Vec::new(),
+            storage: ptr::null(),
+            writes: 0,
+            admin: false,
+        };
+        let limits = &mut host.limits as *mut Limits as *mut c_void;
+        let state = unsafe { lua_newstate(alloc, limits) };
+        if state.is_null() {
+            return Err("couldn't create a Lua state".to_string());
+        }
+
+        let mut script = Script {
+            path: path.clone(),
+            modified: modified(path),
+            state: state,
+            host: host,
+        };
+        unsafe {
+            script.sandbox();
+            script.bind();
+        }
+
+        let chunk = format!("@{}", path.filename_display());
+        let loaded = unsafe {
+            luaL_loadbuffer(state, code.as_ptr() as *const libc::c_char,
+                            code.len() as size_t, chunk.to_c_str().as_ptr())
+        };
+        if loaded != 0 {
+            return Err(script.error());
+        }
+        try!(script.call(0, info));
+        Ok(script)
+    }
+
+    /// Open the safe standard libraries and remove anything that could load
+    /// code from outside the script.
+    unsafe fn sandbox(&mut self) {
+        let L = self.state;
+        let libs: [(&str, lua_CFunction), ..4] =
+                   [("", open_base), ("table", open_table),
+                    ("string", open_string), ("math", open_math)];
+        for &(name, open) in libs.iter() {
+            lua_pushcclosure(L, open, 0);
+            push_str(L, name);
+            lua_call(L, 1, 0);
+        }
+        for name in ["dofile", "loadfile", "load", "loadstring", "require",
+                     "module", "getfenv", "setfenv", "collectgarbage",
+                     "newproxy"].iter() {
+            lua_pushnil(L);
+            set_global(L, *name);
+        }
+        get_field(L, LUA_GLOBALSINDEX, "string");
+        lua_pushnil(L);
+        set_field(L, -2, "dump");
+        lua_pop(L, 1);
+
+        lua_sethook(L, hook, LUA_MASKCOUNT, CHECK_EVERY);
+    }
+
+    /// Set up the `cleese` and `irc` tables.
+    unsafe fn bind(&mut self) {
+        let L = self.state;
+        let host = &mut *self.host as *mut Host as *mut c_void;
+
+        lua_createtable(L, 0, 8);
+        register(L, host, "command", l_command);
+        register(L, host, "on_privmsg", l_on_privmsg);
+        register(L, host, "after", l_after);
+        register(L, host, "every", l_every);
+        register(L, host, "cron", l_cron);
+        register(L, host, "cancel", l_cancel);
+        register(L, host, "get", l_get);
+        register(L, host, "set", l_set);
+        set_global(L, "cleese");
+
+        lua_createtable(L, 0, 2);
+        register(L, host, "msg", l_msg);
+        register(L, host, "join", l_join);
+        set_global(L, "irc");
+    }
+
+    /// Call a function the script registered. `args` pushes the arguments and
+    /// returns how many there are.
+    fn call_ref(&mut self, r: c_int, info: &BotInfo,
+                args: |*mut lua_State| -> c_int) -> Result<bool, String> {
+        unsafe { lua_rawgeti(self.state, LUA_REGISTRYINDEX, r) };
+        let nargs = args(self.state);
+        self.call(nargs, info)
+    }
+
+    /// Call the function below the top `nargs` values on the stack, returning
+    /// whether it accepted whatever it was given.
+    fn call(&mut self, nargs: c_int, info: &BotInfo) -> Result<bool, String> {
+        let L = self.state;
+        unsafe { set_bot(L, info) };
+        self.host.limits.steps = 0;
+        self.host.storage = &info.storage as *const Storage;
+        self.host.writes = 0;
+        self.host.limits.enforce = true;
+        let failed = unsafe { lua_pcall(L, nargs, 1, 0) } != 0;
+        self.host.limits.enforce = false;
+        self.host.storage = ptr::null();
+        if failed {
+            return Err(self.error());
+        }
+        let accepted = unsafe { lua_toboolean(L, -1) } != 0;
+        unsafe { lua_settop(L, 0) };
+        Ok(accepted)
+    }
+
+    /// Take the error message off the top of the stack.
+    fn error(&mut self) -> String {
+        let e = unsafe { to_string(self.state, -1) };
+        unsafe { lua_settop(self.state, 0) };
+        e.unwrap_or("unknown error".to_string())
+    }
+
+    /// Carry out everything the script asked for.
+    fn flush(&mut self, writer: &IrcWriter) {
+        let actions = mem::replace(&mut self.host.actions, Vec::new());
+        for action in actions.into_iter() {
+            match action {
+                Action::Msg(target, text) => {
+                    writer.msg(target.as_slice(), text.as_slice())
+                },
+                Action::Join(chan) => writer.join(chan.as_slice()),
+                Action::Schedule(timer) => writer.schedule(timer),
+                Action::Cancel(id) => writer.cancel(NAME, id.as_slice()),
+            }
+        }
+    }
+
+    /// Cancel every timer the script started.
+    fn cancel_timers(&self, writer: &IrcWriter) {
+        for id in self.host.timers.keys() {
+            let id = format!("{}/{}", self.host.script, id);
+            writer.cancel(NAME, id.as_slice());
+        }
+    }
+
+    fn name(&self) -> &str {
+        self.host.script.as_slice()
+    }
+}
+
+impl Drop for Script {
+    fn drop(&mut self) {
+        unsafe { lua_close(self.state) };
+    }
+}
+
+
+/// Runs every script in the scripts directory.
+pub struct Scripts {
+    dir: Option<Path>,
+    scripts: Vec<Script>,
+    description: &'static str,
+    name: &'static str
+}
+
+impl Scripts {
+    /// Construct the plugin. Scripts aren't loaded until startup.
+    pub fn new() -> Scripts {
+        Scripts {
+            dir: None,
+            scripts: Vec::new(),
+            description: "Lua commands. `cleese scripts` lists them.",
+            name: NAME
+        }
+    }
+
+    /// Bring the loaded scripts in line with the scripts directory: load new
+    /// ones, reload changed ones (or all of them, if `force` is set) and drop
+    /// removed ones. Returns a description of what happened.
+    fn sync(&mut self, force: bool, writer: &IrcWriter,
+            info: &BotInfo) -> Vec<String> {
+        let mut report = Vec::new();
+        let paths: Vec<Path> = match self.dir {
+            Some(ref dir) => match fs::readdir(dir) {
+                Ok(paths) => paths.into_iter().filter(|p| {
+                    p.extension_str() == Some("lua")
+                }).collect(),
+                Err(e) => {
+                    report.push(format!("Couldn't read {}: {}",
+                                        dir.display(), e));
+                    return report;
+                }
+            },
+            None => return report,
+        };
+
+        // Scripts whose files are gone.
+        let scripts = mem::replace(&mut self.scripts, Vec::new());
+        let (kept, removed) = scripts.partition(|s| paths.contains(&s.path));
+        self.scripts = kept;
+        for script in removed.iter() {
+            script.cancel_timers(writer);
+            report.push(format!("Unloaded {}", script.name()));
+        }
+
+        for path in paths.iter() {
+            let pos = self.scripts.iter().position(|s| s.path == *path);
+            let changed = match pos {
+                Some(i) => force || self.scripts[i].modified != modified(path),
+                None => true,
+            };
+            if !changed {
+                continue;
+            }
+            match (Script::load(path, info), pos) {
+                (Ok(mut script), Some(i)) => {
+                    self.scripts[i].cancel_timers(writer);
+                    script.flush(writer);
+                    report.push(format!("Reloaded {}", script.name()));
+                    self.scripts[i] = script;
+                },
+                (Ok(mut script), None) => {
+                    script.flush(writer);
+                    report.push(format!("Loaded {}", script.name()));
+                    self.scripts.push(script);
+                },
+                (Err(e), Some(i)) => {
+                    // Don't try again until the file changes again.
+                    self.scripts[i].modified = modified(path);
+                    report.push(format!("Couldn't reload {}, keeping the \
+                                         old version: {}",
+                                        path.filename_display(), e));
+                },
+                (Err(e), None) => {
+                    report.push(format!("Couldn't load {}: {}",
+                                        path.filename_display(), e));
+                },
+            }
+        }
+        report
+    }
+
+    /// List every command the scripts provide.
+    fn list(&self) -> String {
+        let mut result = String::new();
+        for script in self.scripts.iter() {
+            for &(ref name, ref help, _) in script.host.commands.iter() {
+                result.push_str(format!("`cleese {}`: {}\n",
+                                        name, help).as_slice());
+            }
+        }
+        if result.is_empty() {
+            result.push_str("No scripts are loaded.");
+        }
+        result
+    }
+}
+
+impl Plugin for Scripts {
+    /// Load every script and start watching the directory for changes.
+    fn startup(&mut self, writer: &IrcWriter, info: &BotInfo) {
+        self.dir = info.scripts_dir.map(|d| Path::new(d));
+        if self.dir.is_none() {
+            return;
+        }
+        for line in self.sync(false, writer, info).iter() {
+            println!("{}", line);
+        }
+        writer.schedule(Timer::every(NAME, "watch",
+                                     Duration::seconds(WATCH_INTERVAL)));
+    }
+
+    /// Pass private messages to every script that asked for them.
+    fn privmsg(&mut self, msg: &IrcPrivMsg,
+               writer: &IrcWriter, info: &BotInfo) -> Handler {
+        let mut handled = Handler::Passed;
+        for script in self.scripts.iter_mut() {
+            let handlers = script.host.privmsg.clone();
+            for r in handlers.iter() {
+                let result = script.call_ref(*r, info, |L| {
+                    unsafe { push_privmsg(L, msg) };
+                    1
+                });
+                script.flush(writer);
+                match result {
+                    Ok(true) => handled = Handler::Accepted,
+                    Ok(false) => (),
+                    Err(e) => println!("Script {} failed on a message: {}",
+                                       script.name(), e),
+                }
+            }
+        }
+        handled
+    }
+
+    /// Run the script command with the given name, if there is one.
+    fn cmd(&mut self, cmd: &IrcCommand,
+           writer: &IrcWriter, info: &BotInfo) -> Handler {
+        if cmd.name == "scripts" {
+            if cmd.args == vec!["reload"] && info.is_admin(cmd) {
+                for line in self.sync(true, writer, info).iter() {
+                    writer.msg(cmd.channel, line.as_slice());
+                }
+            } else {
+                writer.msg(cmd.sender, self.list().as_slice());
+            }
+            return Handler::Accepted;
+        }
+
+        for script in self.scripts.iter_mut() {
+            let r = match script.host.commands.iter().find(|c| {
+                c.0.as_slice() == cmd.name
+            }) {
+                Some(&(_, _, r)) => r,
+                None => continue,
+            };
+            script.host.admin = info.role(cmd) >= Role::Admin;
+            let result = script.call_ref(r, info, |L| {
+                unsafe { push_command(L, cmd) };
+                1
+            });
+            script.host.admin = false;
+            script.flush(writer);
+            return match result {
+                Ok(true) => Handler::Accepted,
+                Ok(false) => Handler::Passed,
+                Err(e) => {
+                    println!("Script {} failed on `{}`: {}",
+                             script.name(), cmd.name, e);
+                    writer.msg(cmd.sender, "Sorry, that script didn't work.");
+                    Handler::Accepted
+                }
+            };
+        }
+        Handler::Passed
+    }
+
+    /// Check for changed scripts, or run a script's timer.
+    fn timer(&mut self, id: &str, writer: &IrcWriter, info: &BotInfo) {
+        if id == "watch" {
+            for line in self.sync(false, writer, info).iter() {
+                println!("{}", line);
+            }
+            return;
+        }
+
+        let (name, timer) = match id.find('/') {
+            Some(i) => (id.slice_to(i), id.slice_from(i + 1)),
+            None => return,
+        };
+        for script in self.scripts.iter_mut() {
+            if script.name() != name {
+                continue;
+            }
+            let r = match script.host.timers.get(&timer.to_string()) {
+                Some(r) => *r,
+                None => return,
+            };
+            let result = script.call_ref(r, info, |_| 0);
+            script.flush(writer);
+            match result {
+                Err(e) => println!("Script {} failed on timer {}: {}",
+                                   name, timer, e),
+                _ => (),
+            }
+        }
+    }
+
+    /// Return the plugin description.
+    fn help(&self) -> &'static str { self.description }
+
+    /// Return the plugin name.
+    fn name(&self) -> &'static str { self.name }
+}
+
+
+/// When a file was last modified, or 0 if it can't be read.
+fn modified(path: &Path) -> u64 {
+    match fs::stat(path) {
+        Ok(stat) => stat.modified,
+        Err(_) => 0,
+    }
+}
+
+/// Add a function to the table on top of the stack, with the host as its
+/// upvalue.
+unsafe fn register(L: *mut lua_State, host: *mut c_void, name: &str,
+                   f: lua_CFunction) {
+    lua_pushlightuserdata(L, host);
+    lua_pushcclosure(L, f, 1);
+    set_field(L, -2, name);
+}
+
+/// Fill in the `bot` table from the bot info.
+unsafe fn set_bot(L: *mut lua_State, info: &BotInfo) {
+    lua_createtable(L, 0, 5);
+    push_str(L, info.network);
+    set_field(L, -2, "network");
+    push_str(L, info.nick);
+    set_field(L, -2, "nick");
+    push_str(L, info.descr);
+    set_field(L, -2, "descr");
+    push_str(L, info.cmd_prefix);
+    set_field(L, -2, "cmd_prefix");
+    lua_createtable(L, info.channels.len() as c_int, 0);
+    for (i, chan) in info.channels.iter().enumerate() {
+        push_str(L, *chan);
+        lua_rawseti(L, -2, i as c_int + 1);
+    }
+    set_field(L, -2, "channels");
+    set_global(L, "bot");
+}
+
+unsafe fn push_command(L: *mut lua_State, cmd: &IrcCommand) {
+    lua_createtable(L, 0, 4);
+    push_str(L, cmd.name);
+    set_field(L, -2, "name");
+    push_str(L, cmd.channel);
+    set_field(L, -2, "channel");
+    push_str(L, cmd.sender);
+    set_field(L, -2, "sender");
+    lua_createtable(L, cmd.args.len() as c_int, 0);
+    for (i, arg) in cmd.args.iter().enumerate() {
+        push_str(L, *arg);
+        lua_rawseti(L, -2, i as c_int + 1);
+    }
+    set_field(L, -2, "args");
+}
+
+unsafe fn push_privmsg(L: *mut lua_State, msg: &IrcPrivMsg) {
+    lua_createtable(L, 0, 4);
+    push_str(L, msg.sender_nick.as_slice());
+    set_field(L, -2, "sender_nick");
+    push_str(L, msg.sender_info.as_slice());
+    set_field(L, -2, "sender_info");
+    push_str(L, msg.channel.as_slice());
+    set_field(L, -2, "channel");
+    push_str(L, msg.txt.as_slice());
+    set_field(L, -2, "txt");
+}
+
+
+/// Lua's allocator, refusing anything past the memory limit while it's
+/// enforced. Really running out of memory aborts, as it does anywhere else in
+/// the bot, rather than raising an error somewhere it can't be caught safely.
+extern "C" fn alloc(ud: *mut c_void, p: *mut c_void,
+                    osize: size_t, nsize: size_t) -> *mut c_void {
+    let limits = unsafe { &mut *(ud as *mut Limits) };
+    if nsize == 0 {
+        unsafe { libc::free(p) };
+        limits.memory -= osize;
+        return ptr::null_mut();
+    }
+    if limits.enforce && limits.memory + nsize - osize > MEMORY_LIMIT {
+        return ptr::null_mut();
+    }
+    let q = unsafe { libc::realloc(p, nsize) };
+    if q.is_null() {
+        unsafe { libc::abort() };
+    }
+    limits.memory = limits.memory + nsize - osize;
+    q
+}
+
+/// Called every few instructions, stopping scripts that run too long. Nothing
+/// here owns anything, so `lua_error` can jump straight out of it.
+extern "C" fn hook(L: *mut lua_State, _: *mut c_void) {
+    unsafe {
+        let mut ud = ptr::null_mut();
+        lua_getallocf(L, &mut ud);
+        let limits = &mut *(ud as *mut Limits);
+        limits.steps += CHECK_EVERY as uint;
+        if limits.steps > INSTRUCTION_LIMIT {
+            push_str(L, "script ran for too long");
+            lua_error(L);
+        }
+    }
+}
+
+unsafe fn host<'a>(L: *mut lua_State) -> &'a mut Host {
+    &mut *(lua_touserdata(L, lua_upvalueindex(1)) as *mut Host)
+}
+
+// The functions scripts call can't let a Lua error jump past anything they
+// own. Anything that can raise one, like taking a reference to a function,
+// comes first, and arguments are only read with `string_arg`, which never
+// raises.
+
+/// `cleese.command(name, help, f)`
+extern "C" fn l_command(L: *mut lua_State) -> c_int {
+    unsafe {
+        if lua_type(L, 1) != LUA_TSTRING || lua_type(L, 2) != LUA_TSTRING
+            || !lua_isfunction(L, 3) {
+            return 0;
+        }
+        lua_pushvalue(L, 3);
+        let r = luaL_ref(L, LUA_REGISTRYINDEX);
+        match (string_arg(L, 1), string_arg(L, 2)) {
+            (Some(name), Some(help)) => host(L).commands.push((name, help, r)),
+            _ => luaL_unref(L, LUA_REGISTRYINDEX, r),
+        }
+    }
+    0
+}
+
+/// `cleese.on_privmsg(f)`
+extern "C" fn l_on_privmsg(L: *mut lua_State) -> c_int {
+    unsafe {
+        if lua_isfunction(L, 1) {
+            lua_pushvalue(L, 1);
+            let r = luaL_ref(L, LUA_REGISTRYINDEX);
+            host(L).privmsg.push(r);
+        }
+    }
+    0
+}
+
+/// Shared by the timer functions, which all take `(id, when, f)`. `make`
+/// reads `when` and builds the timer with the full id. `luaL_unref` only
+/// writes to slots the registry already has, so it can't raise an error.
+unsafe fn add_timer(L: *mut lua_State,
+                    make: |*mut lua_State, &str| -> Option<Timer>) -> c_int {
+    if lua_type(L, 1) != LUA_TSTRING || !lua_isfunction(L, 3) {
+        return 0;
+    }
+    lua_pushvalue(L, 3);
+    let r = luaL_ref(L, LUA_REGISTRYINDEX);
+
+    let id = string_arg(L, 1).unwrap();
+    let full = format!("{}/{}", host(L).script, id);
+    let timer = match make(L, full.as_slice()) {
+        Some(timer) => timer,
+        None => {
+            luaL_unref(L, LUA_REGISTRYINDEX, r);
+            return 0;
+        },
+    };
+    let host = host(L);
+    match host.timers.remove(&id) {
+        Some(old) => luaL_unref(L, LUA_REGISTRYINDEX, old),
+        None => (),
+    }
+    host.timers.insert(id, r);
+    host.actions.push(Action::Schedule(timer));
+    0
+}
+
+/// `cleese.after(id, seconds, f)`
+extern "C" fn l_after(L: *mut lua_State) -> c_int {
+    unsafe {
+        add_timer(L, |L, id| {
+            let secs = lua_tonumber(L, 2) as i64;
+            Some(Timer::once(NAME, id, Duration::seconds(secs)))
+        })
+    }
+}
+
+/// `cleese.every(id, seconds, f)`
+extern "C" fn l_every(L: *mut lua_State) -> c_int {
+    unsafe {
+        add_timer(L, |L, id| {
+            let secs = lua_tonumber(L, 2) as i64;
+            Some(Timer::every(NAME, id, Duration::seconds(secs)))
+        })
+    }
+}
+
+/// `cleese.cron(id, spec, f)`
+extern "C" fn l_cron(L: *mut lua_State) -> c_int {
+    unsafe {
+        add_timer(L, |L, id| {
+            match string_arg(L, 2) {
+                Some(spec) => Timer::cron(NAME, id, spec.as_slice()),
+                None => None,
+            }
+        })
+    }
+}
+
+/// `cleese.cancel(id)`
+extern "C" fn l_cancel(L: *mut lua_State) -> c_int {
+    unsafe {
+        let id = match string_arg(L, 1) {
+            Some(id) => id,
+            None => return 0,
+        };
+        let host = host(L);
+        match host.timers.remove(&id) {
+            Some(r) => {
+                luaL_unref(L, LUA_REGISTRYINDEX, r);
+                let full = format!("{}/{}", host.script, id);
+                host.actions.push(Action::Cancel(full));
+            },
+            None => (),
+        }
+    }
+    0
+}
+
+/// `irc.msg(target, text)`
+extern "C" fn l_msg(L: *mut lua_State) -> c_int {
+    unsafe {
+        let host = host(L);
+        let (target, text) = match (string_arg(L, 1), string_arg(L, 2)) {
+            (Some(target), Some(text)) => (target, text),
+            _ => return 0,
+        };
+        if valid_target(target.as_slice()) {
+            let text = clean_text(text.as_slice());
+            host.actions.push(Action::Msg(target, text));
+        } else {
+            println!("Script {} isn't allowed to message {}",
+                     host.script, target.as_slice().escape_default());
+        }
+    }
+    0
+}
+
+/// `irc.join(channel)`, only from a command an admin sent.
+extern "C" fn l_join(L: *mut lua_State) -> c_int {
+    unsafe {
+        let host = host(L);
+        let chan = match string_arg(L, 1) {
+            Some(chan) => chan,
+            None => return 0,
+        };
+        let is_chan = chan.as_slice().starts_with("#")
+            || chan.as_slice().starts_with("&");
+        if host.admin && is_chan && valid_target(chan.as_slice()) {
+            host.actions.push(Action::Join(chan));
+        } else {
+            println!("Script {} isn't allowed to join {}",
+                     host.script, chan.as_slice().escape_default());
+        }
+    }
+    0
+}
+
+/// `cleese.get(key)`
+extern "C" fn l_get(L: *mut lua_State) -> c_int {
+    unsafe {
+        let host = host(L);
+        let value: Option<String> = match string_arg(L, 1) {
+            Some(key) if !host.storage.is_null() => {
+                let ns = format!("{}/{}", NAME, host.script);
+                (*host.storage).namespace(ns.as_slice()).get(key.as_slice())
+            },
+            _ => None,
+        };
+        // We own the value, so pushing it mustn't raise an error. It's from
+        // the script's own storage, which is kept small.
+        host.limits.enforce = false;
+        match value {
+            Some(v) => push_str(L, v.as_slice()),
+            None => lua_pushnil(L),
+        }
+        host.limits.enforce = true;
+    }
+    1
+}
+
+/// `cleese.set(key, value)`
+extern "C" fn l_set(L: *mut lua_State) -> c_int {
+    unsafe {
+        let host = host(L);
+        let key = match string_arg(L, 1) {
+            Some(key) if !host.storage.is_null() => key,
+            _ => return 0,
+        };
+        if host.writes == WRITE_LIMIT {
+            println!("Script {} has written too much this call, so {} was left \
+                      alone", host.script, key.as_slice().escape_default());
+            return 0;
+        }
+        host.writes += 1;
+        let ns = format!("{}/{}", NAME, host.script);
+        let store = (*host.storage).namespace(ns.as_slice());
+        // Anything but a string or nil is left alone. Nothing here can raise
+        // an error, so it's fine to check the type while owning the key.
+        match string_arg(L, 2) {
+            Some(value) => {
+                let stored = store.transaction(|tx| {
+                    if !tx.fits(key.as_slice(), value.as_slice(),
+                                STORAGE_KEYS, STORAGE_BYTES) {
+                        return false;
+                    }
+                    tx.set(key.as_slice(), &value);
+                    true
+                });
+                if !stored {
+                    println!("Script {} is out of storage, so {} wasn't set",
+                             host.script, key.as_slice().escape_default());
+                }
+            },
+            None if lua_type(L, 2) <= LUA_TNIL => {
+                store.delete(key.as_slice());
+            },
+            None => (),
+        }
+    }
+    0
+}
+
+
+/// Can a script send to this? Anything with whitespace or a NUL in it, or a
+/// leading `:`, could sneak a command of its own onto the line, and services
+/// aren't for scripts to talk to.
+fn valid_target(target: &str) -> bool {
+    !target.is_empty() && !target.starts_with(":")
+        && !target.chars().any(|c| c.is_whitespace() || c == '\0')
+        && !target.to_lowercase().as_slice().ends_with("serv")
+}
@@ -0,0 +1,710 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # WebAssembly
+//!
+//! Runs plugins the bot doesn't trust as WebAssembly modules. Every `.wasm`
+//! file in the configured `wasm_dir` is loaded on startup as a plugin of its
+//! own, named after the file, in a Wasmtime sandbox. A module can only use
+//! the functions the bot gives it, so it can't reach the file system, the
+//! network or any other plugin. Every call into a module is limited in how
+//! much memory and how much fuel, which stands in for instructions, it can
+//! use.
+//!
+//! A module imports what it needs from `cleese`. Strings are passed as a
+//! pointer into the module's memory and a length, and functions that hand a
+//! string back copy as much as fits into a buffer and return its full length,
+//! or -1 if there isn't one:
+//!
+//! ```
+//! reply(ptr, len)                         answer where the message came from
+//! info(key, key_len, buf, buf_len) -> i32 read a field of the bot info
+//! get(key, key_len, buf, buf_len) -> i32  read from storage
+//! set(key, key_len, val, val_len) -> i32  write to storage, 1 if it was kept
+//! delete(key, key_len) -> i32             delete from storage, 1 if it was
+//! ```
+//!
+//! The bot info fields are `network`, `nick`, `descr`, `cmd_prefix`, `server`
+//! and `channels`, which are separated by spaces. Storage is a namespace of
+//! the module's own, holding up to 100 keys and 64KB between them. Every
+//! change is written out to disk, so `set` and `delete` return 0 after the
+//! first 10 per call.
+//!
+//! In turn a module exports its `memory`, `alloc(len) -> ptr` for the bot to
+//! pass strings in with, and `help() -> i64` with its help text, packed as the
+//! pointer shifted up 32 bits plus the length. Then it exports
+//! `on_command(ptr, len) -> i32` and `on_privmsg(ptr, len) -> i32` if it wants
+//! them, which return 1 to accept what they were given. A command is passed as
+//! its name, channel, sender and arguments, and a private message as the
+//! sender's nick, the rest of their hostmask, the channel and the text. Each
+//! field is on a line of its own, and arguments are separated by spaces.
+//!
+//! A module can only reply a few times per call. Modules aren't reloaded
+//! while the bot runs, so restart it to pick up a changed one.
+
+use libc::{c_char, c_void, size_t};
+use std::cmp::min;
+use std::i32;
+use std::io::File;
+use std::io::fs;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+use std::slice::raw::{buf_as_slice, mut_buf_as_slice};
+
+use irc::{IrcPrivMsg, IrcWriter, IrcCommand, BotInfo, Plugin, Handler};
+use irc::Storage;
+use plugins::wasmtime::*;
+use util::{clean_text, leak};
+
+
+/// How much fuel a module may burn per call. Most instructions take one.
+static FUEL: u64 = 10000000;
+
+/// How much memory each module may have, in bytes.
+static MEMORY_LIMIT: i64 = 4 * 1024 * 1024;
+
+/// How many elements each module's table may have.
+static TABLE_LIMIT: i64 = 10000;
+
+/// How many times a module may reply per call.
+static REPLY_LIMIT: uint = 5;
+
+/// How many keys each module may keep in storage.
+static STORAGE_KEYS: uint = 100;
+
+/// How many bytes of keys and values each module may keep in storage.
+static STORAGE_BYTES: uint = 64 * 1024;
+
+/// How many times a module may set or delete a key per call.
+static WRITE_LIMIT: uint = 10;
+
+
+/// One Wasmtime engine, shared by every module.
+struct Engine {
+    raw: *mut wasm_engine_t,
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        unsafe { wasm_engine_delete(self.raw) };
+    }
+}
+
+
+/// What the functions a module calls can get at. Boxed so Wasmtime can hold
+/// on to a pointer to it.
+struct Host {
+    name: String,
+    // Only set while the module is running.
+    storage: *const Storage,
+    fields: Vec<(String, String)>,
+    replies: Vec<String>,
+    // How many keys the running call has set or deleted.
+    writes: uint,
+}
+
+
+/// A WebAssembly module, which looks like any other plugin.
+pub struct WasmPlugin {
+    // The store has to go before the engine does.
+    engine: Rc<Engine>,
+    store: *mut wasmtime_store_t,
+    instance: wasmtime_instance_t,
+    host: Box<Host>,
+    on_command: bool,
+    on_privmsg: bool,
+    name: &'static str,
+    help: &'static str,
+}
+
+impl WasmPlugin {
+    /// Load and instantiate the module at `path`.
+    fn load(engine: Rc<Engine>, path: &Path) -> Result<WasmPlugin, String> {
+        let code = match File::open(path).read_to_end() {
+            Ok(code) => code,
+            Err(e) => return Err(format!("{}", e)),
+        };
+        let name = path.filestem_str().unwrap_or("wasm").to_string();
+
+        let mut module = ptr::null_mut();
+        let e = unsafe {
+            wasmtime_module_new(engine.raw, code.as_ptr(),
+                                code.len() as size_t, &mut module)
+        };
+        if !e.is_null() {
+            return Err(unsafe { error_message(e) });
+        }
+        let store = unsafe {
+            wasmtime_store_new(engine.raw, ptr::null_mut(), ptr::null())
+        };
+        unsafe {
+            wasmtime_store_limiter(store, MEMORY_LIMIT, TABLE_LIMIT, 1, 1, 1);
+        }
+        let mut plugin = WasmPlugin {
+            engine: engine,
+            store: store,
+            instance: wasmtime_instance_t { store_id: 0, index: 0 },
+            host: box Host {
+                name: name.clone(),
+                storage: ptr::null(),
+                fields: Vec::new(),
+                replies: Vec::new(),
+                writes: 0,
+            },
+            on_command: false,
+            on_privmsg: false,
+            name: "",
+            help: "",
+        };
+        let instantiated = unsafe { plugin.instantiate(module) };
+        unsafe { wasmtime_module_delete(module) };
+        try!(instantiated);
+
+        plugin.on_command = plugin.export("on_command").is_some();
+        plugin.on_privmsg = plugin.export("on_privmsg").is_some();
+        let help = match try!(plugin.call("help", &[])) {
+            Some(v) => try!(plugin.string_at(v.as_i64())),
+            None => String::new(),
+        };
+        // Modules are only loaded once, at startup.
+        plugin.name = leak(name);
+        plugin.help = leak(help);
+        Ok(plugin)
+    }
+
+    /// Link the module to the bot's functions and start it up.
+    unsafe fn instantiate(&mut self, module: *mut wasmtime_module_t)
+                          -> Result<(), String> {
+        let linker = wasmtime_linker_new(self.engine.raw);
+        let linked = self.link(linker);
+        let result = match linked {
+            Ok(()) => {
+                let ctx = self.context();
+                match wasmtime_context_set_fuel(ctx, FUEL) {
+                    e if !e.is_null() => Err(error_message(e)),
+                    _ => {
+                        let mut trap = ptr::null_mut();
+                        let e = wasmtime_linker_instantiate(
+                            linker as *const wasmtime_linker_t, ctx,
+                            module as *const wasmtime_module_t,
+                            &mut self.instance, &mut trap);
+                        check(e, trap)
+                    }
+                }
+            },
+            Err(e) => Err(e),
+        };
+        wasmtime_linker_delete(linker);
+        result
+    }
+
+    /// Give the linker the functions a module can import.
+    unsafe fn link(&mut self,
+                   linker: *mut wasmtime_linker_t) -> Result<(), String> {
+        let host = &mut *self.host as *mut Host as *mut c_void;
+        let funcs: [(&str, uint, bool, wasmtime_func_callback_t), ..5] =
+                   [("reply", 2, false, w_reply),
+                    ("info", 4, true, w_info),
+                    ("get", 4, true, w_get),
+                    ("set", 4, true, w_set),
+                    ("delete", 2, true, w_delete)];
+        let module = "cleese";
+        for &(name, params, returns, f) in funcs.iter() {
+            let params = Vec::from_elem(params, WASM_I32);
+            let results = if returns { vec![WASM_I32] } else { Vec::new() };
+            let ty = functype(params.as_slice(), results.as_slice());
+            let e = wasmtime_linker_define_func(
+                linker, module.as_ptr() as *const c_char,
+                module.len() as size_t, name.as_ptr() as *const c_char,
+                name.len() as size_t, ty as *const wasm_functype_t, f, host,
+                ptr::null());
+            wasm_functype_delete(ty);
+            if !e.is_null() {
+                return Err(error_message(e));
+            }
+        }
+        Ok(())
+    }
+
+    fn context(&self) -> *mut wasmtime_context_t {
+        unsafe { wasmtime_store_context(self.store) }
+    }
+
+    /// Look up one of the module's exports.
+    fn export(&self, name: &str) -> Option<wasmtime_extern_t> {
+        let mut item = wasmtime_extern_t::new();
+        let found = unsafe {
+            wasmtime_instance_export_get(self.context(), &self.instance,
+                                         name.as_ptr() as *const c_char,
+                                         name.len() as size_t, &mut item)
+        };
+        if found != 0 { Some(item) } else { None }
+    }
+
+    /// Call one of the module's functions that returns a single value, with a
+    /// fresh tank of fuel. None if it doesn't export one by that name.
+    fn call(&mut self, name: &str, args: &[wasmtime_val_t])
+            -> Result<Option<wasmtime_val_t>, String> {
+        let func = match self.export(name) {
+            Some(item) => match unsafe { item.func() } {
+                Some(func) => func,
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        let ctx = self.context();
+        let mut result = wasmtime_val_t::i32(0);
+        let mut trap = ptr::null_mut();
+        unsafe {
+            let e = wasmtime_context_set_fuel(ctx, FUEL);
+            if !e.is_null() {
+                return Err(error_message(e));
+            }
+            let e = wasmtime_func_call(ctx, func, args.as_ptr(),
+                                       args.len() as size_t, &mut result, 1,
+                                       &mut trap);
+            try!(check(e, trap));
+        }
+        Ok(Some(result))
+    }
+
+    /// Copy a string into the module's memory, returning where it went.
+    fn pass(&mut self, s: &str) -> Result<(i32, i32), String> {
+        let len = s.len() as i32;
+        let at = match try!(self.call("alloc", &[wasmtime_val_t::i32(len)])) {
+            Some(v) => v.as_i32(),
+            None => return Err("the module doesn't export alloc".to_string()),
+        };
+        // Allocating can grow the memory, so it's only looked up now.
+        let memory = match self.export("memory") {
+            Some(memory) => memory,
+            None => return Err("the module doesn't export memory".to_string()),
+        };
+        let copied = unsafe {
+            with_memory(self.context(), &memory, |mem| {
+                match span(mem.len(), at, len) {
+                    Some((start, end)) => {
+                        mem.slice_mut(start, end)
+                           .clone_from_slice(s.as_bytes());
+                        true
+                    },
+                    None => false,
+                }
+            })
+        };
+        match copied {
+            Some(true) => Ok((at, len)),
+            _ => Err("alloc gave back memory the module doesn't have"
+                     .to_string()),
+        }
+    }
+
+    /// Read a string the module packed into an i64.
+    fn string_at(&self, packed: i64) -> Result<String, String> {
+        let at = (packed >> 32) as i32;
+        let len = packed as i32;
+        let memory = match self.export("memory") {
+            Some(memory) => memory,
+            None => return Err("the module doesn't export memory".to_string()),
+        };
+        let s = unsafe {
+            with_memory(self.context(), &memory, |mem| {
+                span(mem.len(), at, len).map(|(start, end)| {
+                    String::from_utf8_lossy(mem.slice(start, end)).into_string()
+                })
+            })
+        };
+        match s {
+            Some(Some(s)) => Ok(s),
+            _ => Err("help points outside the module's memory".to_string()),
+        }
+    }
+
+    /// Pass `payload` to one of the module's handlers, returning whether it
+    /// was accepted. The replies are left in the host for `flush`.
+    fn handle(&mut self, handler: &str, payload: &str,
+              info: &BotInfo) -> Result<bool, String> {
+        let (at, len) = try!(self.pass(payload));
+        self.host.storage = &info.storage as *const Storage;
+        self.host.fields = fields(info);
+        self.host.writes = 0;
+        let args = [wasmtime_val_t::i32(at), wasmtime_val_t::i32(len)];
+        let result = self.call(handler, &args);
+        self.host.storage = ptr::null();
+        self.host.fields.clear();
+        result.map(|v| v.map_or(false, |v| v.as_i32() != 0))
+    }
+
+    /// Send everything the module replied.
+    fn flush(&mut self, target: &str, writer: &IrcWriter) {
+        let replies = mem::replace(&mut self.host.replies, Vec::new());
+        for reply in replies.iter() {
+            writer.msg(target, reply.as_slice());
+        }
+    }
+}
+
+impl Drop for WasmPlugin {
+    fn drop(&mut self) {
+        unsafe { wasmtime_store_delete(self.store) };
+    }
+}
+
+impl Plugin for WasmPlugin {
+    /// Pass private messages on, if the module wants them.
+    fn privmsg(&mut self, msg: &IrcPrivMsg,
+               writer: &IrcWriter, info: &BotInfo) -> Handler {
+        if !self.on_privmsg {
+            return Handler::Passed;
+        }
+        let payload = format!("{}\n{}\n{}\n{}", msg.sender_nick,
+                              msg.sender_info, msg.channel, msg.txt);
+        let result = self.handle("on_privmsg", payload.as_slice(), info);
+        let target = reply_to(msg.channel.as_slice(),
+                              msg.sender_nick.as_slice());
+        self.flush(target, writer);
+        match result {
+            Ok(true) => Handler::Accepted,
+            Ok(false) => Handler::Passed,
+            Err(e) => {
+                println!("Module {} failed on a message: {}", self.name, e);
+                Handler::Passed
+            }
+        }
+    }
+
+    /// Pass commands on, if the module wants them.
+    fn cmd(&mut self, cmd: &IrcCommand,
+           writer: &IrcWriter, info: &BotInfo) -> Handler {
+        if !self.on_command {
+            return Handler::Passed;
+        }
+        let payload = format!("{}\n{}\n{}\n{}", cmd.name, cmd.channel,
+                              cmd.sender, cmd.args.connect(" "));
+        let result = self.handle("on_command", payload.as_slice(), info);
+        self.flush(reply_to(cmd.channel, cmd.sender), writer);
+        match result {
+            Ok(true) => Handler::Accepted,
+            Ok(false) => Handler::Passed,
+            Err(e) => {
+                println!("Module {} failed on `{}`: {}",
+                         self.name, cmd.name, e);
+                Handler::Passed
+            }
+        }
+    }
+
+    /// Return the help the module gave.
+    fn help(&self) -> &'static str { self.help }
+
+    /// Return the plugin name, which is the module's file name.
+    fn name(&self) -> &'static str { self.name }
+}
+
+
+/// Load every module in `dir`, skipping and reporting any that don't load.
+pub fn load_dir(dir: &Path) -> Vec<WasmPlugin> {
+    let mut paths: Vec<Path> = match fs::readdir(dir) {
+        Ok(paths) => paths.into_iter().filter(|p| {
+            p.extension_str() == Some("wasm")
+        }).collect(),
+        Err(e) => {
+            println!("Couldn't read {}: {}", dir.display(), e);
+            return Vec::new();
+        }
+    };
+    paths.sort();
+
+    let engine = unsafe {
+        let config = wasm_config_new();
+        wasmtime_config_consume_fuel_set(config, 1);
+        wasm_engine_new_with_config(config)
+    };
+    if engine.is_null() {
+        println!("Couldn't start the WebAssembly engine");
+        return Vec::new();
+    }
+    let engine = Rc::new(Engine { raw: engine });
+
+    let mut plugins = Vec::new();
+    for path in paths.iter() {
+        match WasmPlugin::load(engine.clone(), path) {
+            Ok(plugin) => {
+                println!("Loaded {}", path.filename_display());
+                plugins.push(plugin);
+            },
+            Err(e) => println!("Couldn't load {}: {}",
+                               path.filename_display(), e),
+        }
+    }
+    plugins
+}
+
+
+/// Where to send a reply to something sent to `channel`, which is the bot's
+/// own nick for a private message.
+fn reply_to<'a>(channel: &'a str, sender: &'a str) -> &'a str {
+    if channel.starts_with("#") || channel.starts_with("&") {
+        channel
+    } else {
+        sender
+    }
+}
+
+/// The bot info a module can read.
+fn fields(info: &BotInfo) -> Vec<(String, String)> {
+    let server = info.server.clone().unwrap_or(String::new());
+    vec![("network".to_string(), info.network.to_string()),
+         ("nick".to_string(), info.nick.to_string()),
+         ("descr".to_string(), info.descr.to_string()),
+         ("cmd_prefix".to_string(), info.cmd_prefix.to_string()),
+         ("server".to_string(), server),
+         ("channels".to_string(), info.channels.connect(" "))]
+}
+
+/// Turn an error or trap from Wasmtime into a result, freeing it.
+unsafe fn check(e: *mut wasmtime_error_t,
+                trap: *mut wasm_trap_t) -> Result<(), String> {
+    if !e.is_null() {
+        Err(error_message(e))
+    } else if !trap.is_null() {
+        Err(trap_message(trap))
+    } else {
+        Ok(())
+    }
+}
+
+/// Run `f` on the memory `item` is a handle to, if it's a memory.
+unsafe fn with_memory<T>(ctx: *mut wasmtime_context_t, item: &wasmtime_extern_t,
+                         f: |&mut [u8]| -> T) -> Option<T> {
+    let memory = match item.memory() {
+        Some(memory) => memory,
+        None => return None,
+    };
+    let ctx = ctx as *const wasmtime_context_t;
+    let data = wasmtime_memory_data(ctx, memory);
+    let size = wasmtime_memory_data_size(ctx, memory) as uint;
+    Some(mut_buf_as_slice(data, size, f))
+}
+
+/// Where `len` bytes at `at` are in a memory of `size` bytes, or None if they
+/// aren't all in it. Modules pass addresses and lengths as unsigned.
+fn span(size: uint, at: i32, len: i32) -> Option<(uint, uint)> {
+    let start = at as u32 as u64;
+    let end = start + len as u32 as u64;
+    if end > size as u64 {
+        None
+    } else {
+        Some((start as uint, end as uint))
+    }
+}
+
+
+// The functions modules call. Wasmtime calls them from C, so they mustn't
+// panic: anything out of place is reported to the module as a trap instead.
+
+unsafe fn host<'a>(env: *mut c_void) -> &'a mut Host {
+    &mut *(env as *mut Host)
+}
+
+unsafe fn arguments(args: *const wasmtime_val_t, n: size_t) -> Vec<i32> {
+    buf_as_slice(args, n as uint, |args| {
+        args.iter().map(|a| a.as_i32()).collect()
+    })
+}
+
+unsafe fn trap(msg: &str) -> *mut wasm_trap_t {
+    wasmtime_trap_new(msg.as_ptr() as *const c_char, msg.len() as size_t)
+}
+
+unsafe fn set_result(results: *mut wasmtime_val_t, n: size_t, v: i32) {
+    if n >= 1 {
+        *results = wasmtime_val_t::i32(v);
+    }
+}
+
+// A context and a memory handle in it.
+type Memory = (*mut wasmtime_context_t, wasmtime_extern_t);
+
+/// The caller's memory, if it exports one.
+unsafe fn caller_memory(caller: *mut wasmtime_caller_t) -> Option<Memory> {
+    let mut item = wasmtime_extern_t::new();
+    let name = "memory";
+    let found = wasmtime_caller_export_get(caller,
+                                           name.as_ptr() as *const c_char,
+                                           name.len() as size_t, &mut item);
+    if found != 0 {
+        Some((wasmtime_caller_context(caller), item))
+    } else {
+        None
+    }
+}
+
+/// Read a string out of the caller's memory.
+unsafe fn read(caller: *mut wasmtime_caller_t, at: i32,
+               len: i32) -> Option<String> {
+    let (ctx, memory) = match caller_memory(caller) {
+        Some(m) => m,
+        None => return None,
+    };
+    let s = with_memory(ctx, &memory, |mem| {
+        span(mem.len(), at, len).map(|(start, end)| {
+            String::from_utf8_lossy(mem.slice(start, end)).into_string()
+        })
+    });
+    s.and_then(|s| s)
+}
+
+/// Copy as much of `s` as fits into the caller's buffer, returning its full
+/// length, or None if the buffer isn't in the caller's memory.
+unsafe fn write(caller: *mut wasmtime_caller_t, at: i32, len: i32,
+                s: &str) -> Option<i32> {
+    let (ctx, memory) = match caller_memory(caller) {
+        Some(m) => m,
+        None => return None,
+    };
+    let written = with_memory(ctx, &memory, |mem| {
+        span(mem.len(), at, len).map(|(start, end)| {
+            let n = min(end - start, s.len());
+            mem.slice_mut(start, start + n)
+               .clone_from_slice(s.as_bytes().slice_to(n));
+            min(s.len(), i32::MAX as uint) as i32
+        })
+    });
+    written.and_then(|n| n)
+}
+
+/// `reply(ptr, len)`
+extern "C" fn w_reply(env: *mut c_void, caller: *mut wasmtime_caller_t,
+                      args: *const wasmtime_val_t, nargs: size_t,
+                      _: *mut wasmtime_val_t, _: size_t) -> *mut wasm_trap_t {
+    unsafe {
+        let host = host(env);
+        let args = arguments(args, nargs);
+        let text = match read(caller, args[0], args[1]) {
+            Some(text) => text,
+            None => return trap("reply: text is outside memory"),
+        };
+        if host.replies.len() < REPLY_LIMIT {
+            host.replies.push(clean_text(text.as_slice()));
+        }
+        ptr::null_mut()
+    }
+}
+
+/// `info(key, key_len, buf, buf_len) -> i32`
+extern "C" fn w_info(env: *mut c_void, caller: *mut wasmtime_caller_t,
+                     args: *const wasmtime_val_t, nargs: size_t,
+                     results: *mut wasmtime_val_t,
+                     nresults: size_t) -> *mut wasm_trap_t {
+    unsafe {
+        let host = host(env);
+        let args = arguments(args, nargs);
+        let key = match read(caller, args[0], args[1]) {
+            Some(key) => key,
+            None => return trap("info: key is outside memory"),
+        };
+        let value = host.fields.iter().find(|&&(ref k, _)| *k == key)
+                                      .map(|&(_, ref v)| v.clone());
+        let n = match value {
+            Some(v) => match write(caller, args[2], args[3], v.as_slice()) {
+                Some(n) => n,
+                None => return trap("info: buffer is outside memory"),
+            },
+            None => -1,
+        };
+        set_result(results, nresults, n);
+        ptr::null_mut()
+    }
+}
+
+/// `get(key, key_len, buf, buf_len) -> i32`
+extern "C" fn w_get(env: *mut c_void, caller: *mut wasmtime_caller_t,
+                    args: *const wasmtime_val_t, nargs: size_t,
+                    results: *mut wasmtime_val_t,
+                    nresults: size_t) -> *mut wasm_trap_t {
+    unsafe {
+        let host = host(env);
+        let args = arguments(args, nargs);
+        let key = match read(caller, args[0], args[1]) {
+            Some(key) => key,
+            None => return trap("get: key is outside memory"),
+        };
+        let value: Option<String> = if host.storage.is_null() {
+            None
+        } else {
+            let ns = format!("wasm/{}", host.name);
+            (*host.storage).namespace(ns.as_slice()).get(key.as_slice())
+        };
+        let n = match value {
+            Some(v) => match write(caller, args[2], args[3], v.as_slice()) {
+                Some(n) => n,
+                None => return trap("get: buffer is outside memory"),
+            },
+            None => -1,
+        };
+        set_result(results, nresults, n);
+        ptr::null_mut()
+    }
+}
+
+/// Count a write against the running call, if it has any left.
+fn may_write(host: &mut Host) -> bool {
+    if host.writes == WRITE_LIMIT {
+        return false;
+    }
+    host.writes += 1;
+    true
+}
+
+/// `set(key, key_len, val, val_len) -> i32`
+extern "C" fn w_set(env: *mut c_void, caller: *mut wasmtime_caller_t,
+                    args: *const wasmtime_val_t, nargs: size_t,
+                    results: *mut wasmtime_val_t,
+                    nresults: size_t) -> *mut wasm_trap_t {
+    unsafe {
+        let host = host(env);
+        let args = arguments(args, nargs);
+        let (key, value) = match (read(caller, args[0], args[1]),
+                                  read(caller, args[2], args[3])) {
+            (Some(key), Some(value)) => (key, value),
+            _ => return trap("set: key or value is outside memory"),
+        };
+        let stored = !host.storage.is_null() && may_write(host) && {
+            let ns = format!("wasm/{}", host.name);
+            let store = (*host.storage).namespace(ns.as_slice());
+            store.transaction(|tx| {
+                if !tx.fits(key.as_slice(), value.as_slice(),
+                            STORAGE_KEYS, STORAGE_BYTES) {
+                    return false;
+                }
+                tx.set(key.as_slice(), &value);
+                true
+            })
+        };
+        set_result(results, nresults, if stored { 1 } else { 0 });
+        ptr::null_mut()
+    }
+}
+
+/// `delete(key, key_len) -> i32`
+extern "C" fn w_delete(env: *mut c_void, caller: *mut wasmtime_caller_t,
+                       args: *const wasmtime_val_t, nargs: size_t,
+                       results: *mut wasmtime_val_t,
+                       nresults: size_t) -> *mut wasm_trap_t {
+    unsafe {
+        let host = host(env);
+        let args = arguments(args, nargs);
+        let key = match read(caller, args[0], args[1]) {
+            Some(key) => key,
+            None => return trap("delete: key is outside memory"),
+        };
+        let deleted = !host.storage.is_null() && may_write(host) && {
+            let ns = format!("wasm/{}", host.name);
+            (*host.storage).namespace(ns.as_slice()).delete(key.as_slice())
+        };
+        set_result(results, nresults, if deleted { 1 } else { 0 });
+        ptr::null_mut()
+    }
+}
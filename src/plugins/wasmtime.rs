@@ -0,0 +1,265 @@
+// For copyright information, see the LICENSE.md folder at the top of this
+// project's directory structure.
+
+//! # Wasmtime
+//!
+//! The small slice of the Wasmtime C API the WebAssembly plugins need, as of
+//! Wasmtime 14. The structs here are laid out to match `wasmtime.h`, and have
+//! to be kept in step with it if the library is upgraded.
+//!
+//! Wasmtime's handles to functions and memories are small structs that are
+//! passed around by value. Unions in the C API are written out as enough
+//! `u64`s to cover their largest member, and C's `bool` as a `u8`.
+
+#![allow(non_camel_case_types)]
+
+use libc::{c_char, c_void, size_t};
+
+
+pub enum wasm_engine_t {}
+pub enum wasm_config_t {}
+pub enum wasm_valtype_t {}
+pub enum wasm_functype_t {}
+pub enum wasm_trap_t {}
+pub enum wasmtime_error_t {}
+pub enum wasmtime_store_t {}
+pub enum wasmtime_context_t {}
+pub enum wasmtime_module_t {}
+pub enum wasmtime_linker_t {}
+pub enum wasmtime_caller_t {}
+
+#[repr(C)]
+pub struct wasm_byte_vec_t {
+    pub size: size_t,
+    pub data: *mut c_char,
+}
+
+#[repr(C)]
+pub struct wasm_valtype_vec_t {
+    pub size: size_t,
+    pub data: *mut *mut wasm_valtype_t,
+}
+
+#[repr(C)]
+pub struct wasmtime_instance_t {
+    pub store_id: u64,
+    pub index: size_t,
+}
+
+#[repr(C)]
+pub struct wasmtime_func_t {
+    pub store_id: u64,
+    pub index: size_t,
+}
+
+#[repr(C)]
+pub struct wasmtime_memory_t {
+    pub store_id: u64,
+    pub index: size_t,
+}
+
+/// An export, which is a function, global, table or memory handle depending
+/// on `kind`.
+#[repr(C)]
+pub struct wasmtime_extern_t {
+    pub kind: u8,
+    pub of: [u64, ..2],
+}
+
+/// A value passed to or from a function. Integers are kept in the low bytes
+/// of `of`.
+#[repr(C)]
+pub struct wasmtime_val_t {
+    pub kind: u8,
+    pub of: [u64, ..2],
+}
+
+pub type wasmtime_func_callback_t =
+    extern "C" fn(env: *mut c_void, caller: *mut wasmtime_caller_t,
+                  args: *const wasmtime_val_t, nargs: size_t,
+                  results: *mut wasmtime_val_t,
+                  nresults: size_t) -> *mut wasm_trap_t;
+
+pub static WASM_I32: u8 = 0;
+pub static WASM_I64: u8 = 1;
+pub static WASMTIME_I32: u8 = 0;
+pub static WASMTIME_I64: u8 = 1;
+pub static WASMTIME_EXTERN_FUNC: u8 = 0;
+pub static WASMTIME_EXTERN_MEMORY: u8 = 3;
+
+#[link(name = "wasmtime")]
+extern "C" {
+    pub fn wasm_config_new() -> *mut wasm_config_t;
+    pub fn wasmtime_config_consume_fuel_set(config: *mut wasm_config_t,
+                                            enable: u8);
+    pub fn wasm_engine_new_with_config(config: *mut wasm_config_t)
+                                       -> *mut wasm_engine_t;
+    pub fn wasm_engine_delete(engine: *mut wasm_engine_t);
+
+    pub fn wasmtime_store_new(engine: *mut wasm_engine_t, data: *mut c_void,
+                              finalizer: *const c_void)
+                              -> *mut wasmtime_store_t;
+    pub fn wasmtime_store_delete(store: *mut wasmtime_store_t);
+    pub fn wasmtime_store_context(store: *mut wasmtime_store_t)
+                                  -> *mut wasmtime_context_t;
+    pub fn wasmtime_store_limiter(store: *mut wasmtime_store_t,
+                                  memory_size: i64, table_elements: i64,
+                                  instances: i64, tables: i64, memories: i64);
+    pub fn wasmtime_context_set_fuel(context: *mut wasmtime_context_t,
+                                     fuel: u64) -> *mut wasmtime_error_t;
+
+    pub fn wasmtime_module_new(engine: *mut wasm_engine_t, wasm: *const u8,
+                               len: size_t, ret: *mut *mut wasmtime_module_t)
+                               -> *mut wasmtime_error_t;
+    pub fn wasmtime_module_delete(module: *mut wasmtime_module_t);
+
+    pub fn wasm_valtype_new(kind: u8) -> *mut wasm_valtype_t;
+    pub fn wasm_valtype_vec_new(out: *mut wasm_valtype_vec_t, size: size_t,
+                                data: *const *mut wasm_valtype_t);
+    pub fn wasm_valtype_vec_new_empty(out: *mut wasm_valtype_vec_t);
+    pub fn wasm_functype_new(params: *mut wasm_valtype_vec_t,
+                             results: *mut wasm_valtype_vec_t)
+                             -> *mut wasm_functype_t;
+    pub fn wasm_functype_delete(ty: *mut wasm_functype_t);
+
+    pub fn wasmtime_linker_new(engine: *mut wasm_engine_t)
+                               -> *mut wasmtime_linker_t;
+    pub fn wasmtime_linker_delete(linker: *mut wasmtime_linker_t);
+    pub fn wasmtime_linker_define_func(linker: *mut wasmtime_linker_t,
+                                       module: *const c_char,
+                                       module_len: size_t,
+                                       name: *const c_char, name_len: size_t,
+                                       ty: *const wasm_functype_t,
+                                       cb: wasmtime_func_callback_t,
+                                       data: *mut c_void,
+                                       finalizer: *const c_void)
+                                       -> *mut wasmtime_error_t;
+    pub fn wasmtime_linker_instantiate(linker: *const wasmtime_linker_t,
+                                       store: *mut wasmtime_context_t,
+                                       module: *const wasmtime_module_t,
+                                       instance: *mut wasmtime_instance_t,
+                                       trap: *mut *mut wasm_trap_t)
+                                       -> *mut wasmtime_error_t;
+
+    pub fn wasmtime_instance_export_get(store: *mut wasmtime_context_t,
+                                        instance: *const wasmtime_instance_t,
+                                        name: *const c_char, len: size_t,
+                                        item: *mut wasmtime_extern_t) -> u8;
+    pub fn wasmtime_caller_export_get(caller: *mut wasmtime_caller_t,
+                                      name: *const c_char, len: size_t,
+                                      item: *mut wasmtime_extern_t) -> u8;
+    pub fn wasmtime_caller_context(caller: *mut wasmtime_caller_t)
+                                   -> *mut wasmtime_context_t;
+
+    pub fn wasmtime_func_call(store: *mut wasmtime_context_t,
+                              func: *const wasmtime_func_t,
+                              args: *const wasmtime_val_t, nargs: size_t,
+                              results: *mut wasmtime_val_t, nresults: size_t,
+                              trap: *mut *mut wasm_trap_t)
+                              -> *mut wasmtime_error_t;
+
+    pub fn wasmtime_memory_data(store: *const wasmtime_context_t,
+                                memory: *const wasmtime_memory_t) -> *mut u8;
+    pub fn wasmtime_memory_data_size(store: *const wasmtime_context_t,
+                                     memory: *const wasmtime_memory_t)
+                                     -> size_t;
+
+    pub fn wasmtime_trap_new(msg: *const c_char, len: size_t)
+                             -> *mut wasm_trap_t;
+    pub fn wasm_trap_message(trap: *const wasm_trap_t,
+                             out: *mut wasm_byte_vec_t);
+    pub fn wasm_trap_delete(trap: *mut wasm_trap_t);
+    pub fn wasmtime_error_message(error: *const wasmtime_error_t,
+                                  out: *mut wasm_byte_vec_t);
+    pub fn wasmtime_error_delete(error: *mut wasmtime_error_t);
+    pub fn wasm_byte_vec_delete(v: *mut wasm_byte_vec_t);
+}
+
+impl wasmtime_extern_t {
+    pub fn new() -> wasmtime_extern_t {
+        wasmtime_extern_t { kind: 0, of: [0, 0] }
+    }
+
+    /// The function this is a handle to, if it is one.
+    pub unsafe fn func(&self) -> Option<*const wasmtime_func_t> {
+        if self.kind == WASMTIME_EXTERN_FUNC {
+            Some(&self.of as *const [u64, ..2] as *const wasmtime_func_t)
+        } else {
+            None
+        }
+    }
+
+    /// The memory this is a handle to, if it is one.
+    pub unsafe fn memory(&self) -> Option<*const wasmtime_memory_t> {
+        if self.kind == WASMTIME_EXTERN_MEMORY {
+            Some(&self.of as *const [u64, ..2] as *const wasmtime_memory_t)
+        } else {
+            None
+        }
+    }
+}
+
+impl wasmtime_val_t {
+    pub fn i32(v: i32) -> wasmtime_val_t {
+        wasmtime_val_t { kind: WASMTIME_I32, of: [v as u32 as u64, 0] }
+    }
+
+    pub fn i64(v: i64) -> wasmtime_val_t {
+        wasmtime_val_t { kind: WASMTIME_I64, of: [v as u64, 0] }
+    }
+
+    pub fn as_i32(&self) -> i32 { self.of[0] as u32 as i32 }
+
+    pub fn as_i64(&self) -> i64 { self.of[0] as i64 }
+}
+
+/// Take the message out of a byte vector Wasmtime gave us, and free it.
+unsafe fn take(v: &mut wasm_byte_vec_t) -> String {
+    if v.data.is_null() {
+        return String::new();
+    }
+    let mut len = v.size as uint;
+    // Trap messages end in a NUL.
+    while len > 0 && *v.data.offset(len as int - 1) == 0 {
+        len -= 1;
+    }
+    let bytes = ::std::vec::raw::from_buf(v.data as *const u8, len);
+    wasm_byte_vec_delete(v);
+    String::from_utf8_lossy(bytes.as_slice()).into_string()
+}
+
+/// Describe an error, freeing it.
+pub unsafe fn error_message(e: *mut wasmtime_error_t) -> String {
+    let mut v = wasm_byte_vec_t { size: 0, data: 0 as *mut c_char };
+    wasmtime_error_message(e as *const wasmtime_error_t, &mut v);
+    wasmtime_error_delete(e);
+    take(&mut v)
+}
+
+/// Describe a trap, freeing it.
+pub unsafe fn trap_message(t: *mut wasm_trap_t) -> String {
+    let mut v = wasm_byte_vec_t { size: 0, data: 0 as *mut c_char };
+    wasm_trap_message(t as *const wasm_trap_t, &mut v);
+    wasm_trap_delete(t);
+    take(&mut v)
+}
+
+/// Build a function type from its parameter and result kinds.
+pub unsafe fn functype(params: &[u8], results: &[u8]) -> *mut wasm_functype_t {
+    let mut p = valtypes(params);
+    let mut r = valtypes(results);
+    wasm_functype_new(&mut p, &mut r)
+}
+
+unsafe fn valtypes(kinds: &[u8]) -> wasm_valtype_vec_t {
+    let mut vec = wasm_valtype_vec_t { size: 0, data: 0 as *mut _ };
+    let types: Vec<*mut wasm_valtype_t> = kinds.iter().map(|k| {
+        wasm_valtype_new(*k)
+    }).collect();
+    if types.is_empty() {
+        wasm_valtype_vec_new_empty(&mut vec);
+    } else {
+        wasm_valtype_vec_new(&mut vec, types.len() as size_t, types.as_ptr());
+    }
+    vec
+}
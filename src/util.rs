@@ -113,3 +113,105 @@ pub fn join_strings(xs: &Vec<String>, between: &str) -> String {
     return res;
 }
 
+
+
+/// Make text from code the bot doesn't trust safe to send.
+///
+/// Line breaks already become separate messages, but some servers end a line
+/// at a lone carriage return too, and a NUL can cut one short.
+///
+/// ## Example
+///
+/// ```
+/// let s = clean_text("one\rQUIT\0");
+/// println!("{}", s);
+/// => one
+/// => QUIT
+/// ```
+pub fn clean_text(text: &str) -> String {
+    text.chars().filter(|c| *c != '\0').map(|c| {
+        if c == '\r' { '\n' } else { c }
+    }).collect()
+}
+
+
+/// Leak a string, so it lives as long as the program does.
+///
+/// Plugins give their name and help as `&'static str`, which plugins that
+/// only learn them at runtime can't otherwise do. Only use this for strings
+/// made once or rarely, as they're never freed.
+///
+/// ## Example
+///
+/// ```
+/// let name: &'static str = leak(format!("{}-plugin", "weather"));
+/// ```
+pub fn leak(s: String) -> &'static str {
+    unsafe {
+        let leaked: &'static str = ::std::mem::transmute(s.as_slice());
+        ::std::mem::forget(s);
+        leaked
+    }
+}
+
+
+/// Split text into lines of at most `width` bytes, breaking between words
+/// where it can.
+///
+/// ## Example
+///
+/// ```
+/// for line in wrap("one two three", 8).iter() {
+///     println!("{}", line);
+/// }
+/// => one two
+/// => three
+/// ```
+pub fn wrap(text: &str, width: uint) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut rest = text;
+    while rest.len() > width {
+        let mut end = width;
+        while end > 0 && !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        // A character wider than the line still has to go somewhere.
+        if end == 0 {
+            end = rest.char_range_at(0).next;
+        }
+        let cut = match rest.slice_to(end).rfind(' ') {
+            Some(i) if i > 0 => i,
+            _ => end,
+        };
+        lines.push(rest.slice_to(cut).to_string());
+        rest = rest.slice_from(cut).trim_left_chars(' ');
+    }
+    if !rest.is_empty() || lines.is_empty() {
+        lines.push(rest.to_string());
+    }
+    lines
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::wrap;
+
+    #[test]
+    fn wraps_between_words() {
+        assert_eq!(wrap("one two three", 8),
+                   vec!["one two".to_string(), "three".to_string()]);
+        assert_eq!(wrap("short", 8), vec!["short".to_string()]);
+        assert_eq!(wrap("", 8), vec!["".to_string()]);
+    }
+
+    #[test]
+    fn wraps_long_words() {
+        assert_eq!(wrap("abcdefghij", 4),
+                   vec!["abcd".to_string(), "efgh".to_string(),
+                        "ij".to_string()]);
+        // Never in the middle of a character.
+        assert_eq!(wrap("ééé", 3),
+                   vec!["é".to_string(), "é".to_string(), "é".to_string()]);
+    }
+}